@@ -1,5 +1,16 @@
 use wgpu::*;
+use wgpu::util::DeviceExt;
 use nalgebra as na;
+use adaptive_voxel_pathtracer::octree::{
+    Octree,
+    OctreeProvider,
+    brick_map::BrickMapProvider,
+    dag::DagProvider,
+    dynamic_provider::DynamicOctreeProvider,
+    static_provider::{GpuStorage, StaticOctreeProvider},
+};
+use adaptive_voxel_pathtracer::renderer::{self, CameraData, PerformanceData, SceneData, compute_pipeline::ComputePipeline};
+use adaptive_voxel_pathtracer::renderer::performance::TraversalMode;
 
 pub async fn run_performance_benchmark(target_fps: f32) {
     println!("\n=== Performance Benchmark ===");
@@ -61,4 +72,231 @@ pub async fn run_performance_benchmark(target_fps: f32) {
     println!("- The adaptive system now adjusts step size (0.005 to 0.05) to maintain {:.0} FPS", target_fps);
     println!("- Step size increases when FPS < {:.0}, decreases when FPS > {:.0}", target_fps, target_fps * 1.2);
     println!("- Distance-based scaling further optimizes distant objects");
-}
\ No newline at end of file
+}
+
+/// Render the same scene through each octree provider offscreen and report the
+/// average GPU frame time at the benchmark camera positions
+pub async fn run_provider_benchmark(octree: &Octree, width: u32, height: u32) {
+    const WARMUP_FRAMES: u32 = 3;
+    const TIMED_FRAMES: u32 = 20;
+
+    println!("\n=== Octree Provider Benchmark ===");
+    println!("{}x{}, {} frames per position\n", width, height, TIMED_FRAMES);
+
+    let test_positions = [
+        (na::Point3::new(0.0, 1.0, -3.8), "Far outside"),
+        (na::Point3::new(0.0, 1.0, -1.0), "Just outside"),
+        (na::Point3::new(0.0, 1.0, 0.5), "Slightly inside"),
+        (na::Point3::new(0.6, 1.5, 0.3), "Inside, looking down"),
+    ];
+    let target = na::Point3::new(0.0, 0.8, 1.0);
+
+    let instance = Instance::new(&InstanceDescriptor {
+        backends: Backends::all(),
+        ..Default::default()
+    });
+
+    let adapter = instance.request_adapter(&RequestAdapterOptions {
+        power_preference: PowerPreference::HighPerformance,
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }).await.unwrap();
+
+    let (device, queue) = adapter.request_device(
+        &DeviceDescriptor::default(),
+    ).await.unwrap();
+
+    let mut providers: Vec<(&str, Box<dyn OctreeProvider>)> = Vec::new();
+    for (name, storage) in [
+        ("Static (dense texture)", GpuStorage::DenseTexture),
+        ("Static (sparse buffer)", GpuStorage::SparseBuffer),
+        ("Static (distance field)", GpuStorage::DistanceField),
+    ] {
+        let mut provider = StaticOctreeProvider::from_octree(octree.clone(), storage);
+        provider.create_gpu_resources(&device, &queue);
+        providers.push((name, Box::new(provider)));
+    }
+    providers.push(("Brick map", Box::new(BrickMapProvider::from_octree(octree))));
+    providers.push(("Sparse voxel DAG", Box::new(DagProvider::from_octree(octree))));
+    let mut dynamic = DynamicOctreeProvider::new(octree.clone(), GpuStorage::DenseTexture);
+    dynamic.create_gpu_resources(&device, &queue);
+    providers.push(("Dynamic (dense texture)", Box::new(dynamic)));
+
+    let camera_bind_group_layout = renderer::camera_bind_group_layout(&device);
+    let performance_bind_group_layout = renderer::performance_bind_group_layout(&device);
+
+    let camera_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Camera Buffer"),
+        size: std::mem::size_of::<CameraData>() as BufferAddress,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    // Fixed mid-quality step size so every provider marches the same rays,
+    // timed once per traversal
+    let traversals = [TraversalMode::Adaptive, TraversalMode::Dda];
+    let performance_buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Performance Buffer"),
+        size: std::mem::size_of::<PerformanceData>() as BufferAddress,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let write_performance_data = |traversal: TraversalMode, debug_flags| {
+        queue.write_buffer(&performance_buffer, 0, bytemuck::cast_slice(&[PerformanceData {
+            base_voxel_size: 0.01,
+            frame_time: 0.016,
+            traversal_mode: traversal.shader_value(),
+            debug_flags,
+            max_bounces: 0,
+            samples_per_pixel: 1,
+            frame_index: 0,
+            _padding: 0,
+        }]));
+    };
+    let stats_buffer = renderer::create_ray_stats_buffer(&device);
+    let performance_bind_group = renderer::performance_bind_group(&device, &performance_bind_group_layout, &performance_buffer, &stats_buffer);
+
+    let output_texture = device.create_texture(&TextureDescriptor {
+        label: Some("Benchmark Output Texture"),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::STORAGE_BINDING,
+        view_formats: &[],
+    });
+    let output_view = output_texture.create_view(&TextureViewDescriptor::default());
+    let accumulation_buffer = renderer::create_accumulation_buffer(&device, width, height);
+
+    // Average milliseconds per frame and lookups per ray without and with
+    // empty space skipping, indexed by [provider][traversal][position]
+    let mut timings = vec![[Vec::new(), Vec::new()]; providers.len()];
+    let mut lookups = vec![[Vec::new(), Vec::new()]; providers.len()];
+    for (provider_index, (_, provider)) in providers.iter().enumerate() {
+        let (octree_bind_group_layout, octree_bind_group) = provider.bind_gpu_resources(&device);
+        let scene_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Scene Buffer"),
+            contents: bytemuck::cast_slice(&[SceneData::from_provider(provider.as_ref())]),
+            usage: BufferUsages::UNIFORM,
+        });
+        let camera_bind_group = renderer::camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer, &scene_buffer);
+        let pipeline = ComputePipeline::new(
+            &device,
+            &camera_bind_group_layout,
+            &performance_bind_group_layout,
+            &octree_bind_group_layout,
+            provider.shader_source(),
+        );
+        let render_frame = || {
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Benchmark Encoder"),
+            });
+            pipeline.dispatch(
+                &device,
+                &mut encoder,
+                &output_view,
+                &accumulation_buffer,
+                &camera_bind_group,
+                &performance_bind_group,
+                &octree_bind_group,
+                width,
+                height,
+            );
+            queue.submit(std::iter::once(encoder.finish()));
+            device.poll(PollType::Wait).unwrap();
+        };
+
+        for (traversal_index, &traversal) in traversals.iter().enumerate() {
+            for (eye, _) in test_positions.iter() {
+                queue.write_buffer(&camera_buffer, 0, bytemuck::cast_slice(&[camera_data(*eye, target, width, height)]));
+
+                write_performance_data(traversal, 0);
+                let mut elapsed = std::time::Duration::ZERO;
+                for frame in 0..WARMUP_FRAMES + TIMED_FRAMES {
+                    let start = std::time::Instant::now();
+                    render_frame();
+                    if frame >= WARMUP_FRAMES {
+                        elapsed += start.elapsed();
+                    }
+                }
+                timings[provider_index][traversal_index].push(elapsed.as_secs_f64() * 1000.0 / TIMED_FRAMES as f64);
+
+                // One untimed frame each way to count lookups
+                let mut counts = [0.0; 2];
+                for (count, skipping) in counts.iter_mut().zip([renderer::DEBUG_NO_SKIPPING, 0]) {
+                    write_performance_data(traversal, renderer::DEBUG_COUNT_ITERATIONS | skipping);
+                    render_frame();
+                    *count = renderer::read_ray_stats(&device, &queue, &stats_buffer).iterations_per_ray();
+                }
+                lookups[provider_index][traversal_index].push(counts);
+            }
+        }
+    }
+
+    print!("| Position |");
+    for (name, _) in &providers {
+        for traversal in &traversals {
+            print!(" {} {:?} |", name, traversal);
+        }
+    }
+    println!();
+    let separator = format!("|----------|{}", "---|".repeat(providers.len() * traversals.len()));
+    println!("{}", separator);
+    for (position_index, (_, description)) in test_positions.iter().enumerate() {
+        print!("| {} |", description);
+        for provider_timings in &timings {
+            for traversal_timings in provider_timings {
+                print!(" {:.2} ms |", traversal_timings[position_index]);
+            }
+        }
+        println!();
+    }
+
+    println!("\nLookups per ray, without -> with empty space skipping:\n");
+    print!("| Position |");
+    for (name, _) in &providers {
+        for traversal in &traversals {
+            print!(" {} {:?} |", name, traversal);
+        }
+    }
+    println!();
+    println!("{}", separator);
+    for (position_index, (_, description)) in test_positions.iter().enumerate() {
+        print!("| {} |", description);
+        for provider_lookups in &lookups {
+            for traversal_lookups in provider_lookups {
+                let [without, with] = traversal_lookups[position_index];
+                // Rays that never hit the volume take no lookups either way
+                let change = if without > 0.0 {
+                    format!("{:+.0}%", (with / without - 1.0) * 100.0)
+                } else {
+                    "n/a".to_string()
+                };
+                print!(" {:.0} -> {:.0} ({}) |", without, with, change);
+            }
+        }
+        println!();
+    }
+}
+
+fn camera_data(eye: na::Point3<f32>, target: na::Point3<f32>, width: u32, height: u32) -> CameraData {
+    let up = na::Vector3::new(0.0, 1.0, 0.0);
+    let view = na::Matrix4::look_at_rh(&eye, &target, &up);
+    let proj = na::Matrix4::new_perspective(width as f32 / height as f32, 60.0_f32.to_radians(), 0.1, 1000.0);
+    let forward = (target - eye).normalize();
+
+    CameraData {
+        view_proj: (proj * view).into(),
+        position: eye.into(),
+        _padding1: 0.0,
+        forward: forward.into(),
+        _padding2: 0.0,
+        screen_size: [width as f32, height as f32],
+        _padding3: [0.0; 2],
+    }
+}
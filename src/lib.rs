@@ -0,0 +1,2 @@
+pub mod octree;
+pub mod renderer;
@@ -1,7 +1,7 @@
 use wgpu::*;
 use wgpu::util::DeviceExt;
 use winit::{
-    event::{Event, WindowEvent, ElementState, DeviceEvent},
+    event::{Event, WindowEvent, ElementState, DeviceEvent, MouseButton},
     event_loop::EventLoop,
     window::Window,
     keyboard::{KeyCode, PhysicalKey},
@@ -9,16 +9,48 @@ use winit::{
 use std::collections::HashSet;
 use nalgebra as na;
 use std::time::Instant;
-use env_logger;
 use log::info;
 use std::sync::Arc;
 use clap::Parser;
 use chrono::Local;
 
-mod renderer;
+use adaptive_voxel_pathtracer::{octree, renderer};
 use renderer::VoxelRenderer;
+use renderer::performance::TraversalMode;
+use octree::{
+    Octree,
+    OctreeProvider,
+    brick_map::BrickMapProvider,
+    dag::DagProvider,
+    dynamic_provider::DynamicOctreeProvider,
+    instancing::{InstancedProvider, InstancedScene},
+    VoxelData,
+    mesh::Mesh,
+    scene_description::{SceneCamera, SceneDescription},
+    static_provider::{GpuStorage, StaticOctreeProvider},
+    streaming::StreamingOctreeProvider,
+    vox::VoxScene,
+};
+use std::path::{Path, PathBuf};
 mod benchmark;
-mod octree;
+
+/// Which octree provider renders the scene
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Provider {
+    /// Octree uploaded as configured by `--gpu-storage`
+    Static,
+    /// Shallow grid of 8³ bricks in a brick atlas
+    BrickMap,
+    /// Sparse voxel DAG with identical subtrees stored once
+    Dag,
+    /// Octree stored as configured by `--gpu-storage` and edited while rendering;
+    /// left click carves a hole
+    Dynamic,
+    /// Chunked world written by `--save-world`, streamed from the `--scene` directory
+    Streaming,
+    /// Models placed by the scene's `instance` statements, each traced in its own space
+    Instanced,
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -27,29 +59,29 @@ struct Args {
     #[arg(long)]
     screenshot: bool,
 
-    /// Camera X position
-    #[arg(long, default_value_t = 0.0)]
-    cam_x: f32,
+    /// Camera X position, defaulting to the scene's camera
+    #[arg(long, allow_negative_numbers = true)]
+    cam_x: Option<f32>,
 
-    /// Camera Y position
-    #[arg(long, default_value_t = 1.0)]
-    cam_y: f32,
+    /// Camera Y position, defaulting to the scene's camera
+    #[arg(long, allow_negative_numbers = true)]
+    cam_y: Option<f32>,
 
-    /// Camera Z position (negative is in front of the box)
-    #[arg(long, default_value_t = -3.8)]
-    cam_z: f32,
+    /// Camera Z position (negative is in front of the box), defaulting to the scene's camera
+    #[arg(long, allow_negative_numbers = true)]
+    cam_z: Option<f32>,
 
-    /// Camera look-at X position
-    #[arg(long, default_value_t = 0.0)]
-    look_x: f32,
+    /// Camera look-at X position, defaulting to the scene's camera
+    #[arg(long, allow_negative_numbers = true)]
+    look_x: Option<f32>,
 
-    /// Camera look-at Y position
-    #[arg(long, default_value_t = 1.0)]
-    look_y: f32,
+    /// Camera look-at Y position, defaulting to the scene's camera
+    #[arg(long, allow_negative_numbers = true)]
+    look_y: Option<f32>,
 
-    /// Camera look-at Z position
-    #[arg(long, default_value_t = 1.0)]
-    look_z: f32,
+    /// Camera look-at Z position, defaulting to the scene's camera
+    #[arg(long, allow_negative_numbers = true)]
+    look_z: Option<f32>,
 
     /// Window width
     #[arg(long, default_value_t = 1280)]
@@ -67,9 +99,96 @@ struct Args {
     #[arg(long)]
     benchmark: bool,
 
+    /// Print node, memory and material statistics for the scene and exit
+    #[arg(long)]
+    info: bool,
+
     /// Target FPS for adaptive quality system
     #[arg(long, default_value_t = 60.0)]
     target_fps: f32,
+
+    /// Ray traversal to use; by default exact DDA while it holds the target FPS,
+    /// adaptive marching otherwise. Screenshots default to DDA.
+    #[arg(long, value_enum)]
+    traversal: Option<TraversalMode>,
+
+    /// Shade pixels by how many lookups their ray took, from blue (few) to red,
+    /// and report the average. Toggle with I in interactive mode.
+    #[arg(long)]
+    iteration_view: bool,
+
+    /// Ignore the empty space the backends report, to compare lookup counts.
+    /// Toggle with K in interactive mode.
+    #[arg(long)]
+    no_skipping: bool,
+
+    /// Path trace with up to this many bounces, lit by emissive voxels; 0 shows
+    /// unlit first-hit colors. Toggle with P in interactive mode.
+    #[arg(long, default_value_t = 0)]
+    max_bounces: u32,
+
+    /// Paths traced per pixel and frame when path tracing
+    #[arg(long, default_value_t = 1)]
+    samples: u32,
+
+    /// Frames to accumulate before saving a path traced screenshot
+    #[arg(long, default_value_t = 1)]
+    accumulate: u32,
+
+    /// Octree provider used for rendering
+    #[arg(long, value_enum, default_value_t = Provider::Static)]
+    provider: Provider,
+
+    /// How the static and dynamic providers store the octree on the GPU
+    #[arg(long, value_enum, default_value_t = GpuStorage::DenseTexture)]
+    gpu_storage: GpuStorage,
+
+    /// Load the scene from a .scene description, a native octree file, a
+    /// MagicaVoxel .vox file or an OBJ/STL mesh instead of the built-in Cornell Box
+    #[arg(long)]
+    scene: Option<PathBuf>,
+
+    /// World size of one voxel when importing .vox files or voxelizing meshes
+    #[arg(long, default_value_t = 0.02)]
+    voxel_size: f32,
+
+    /// Uniform scale applied to meshes before voxelizing
+    #[arg(long, default_value_t = 1.0)]
+    mesh_scale: f32,
+
+    /// Translation applied to meshes after scaling
+    #[arg(long, num_args = 3, value_delimiter = ',', default_values_t = [0.0, 0.0, 0.0])]
+    mesh_offset: Vec<f32>,
+
+    /// Fill the inside of closed meshes instead of voxelizing only the surface
+    #[arg(long)]
+    mesh_solid: bool,
+
+    /// Save the loaded or built scene to a native octree file
+    #[arg(long)]
+    save_scene: Option<PathBuf>,
+
+    /// Split the loaded or built scene into a chunked world directory for `--provider streaming`
+    #[arg(long)]
+    save_world: Option<PathBuf>,
+
+    /// Maximum number of chunks the streaming provider keeps loaded
+    #[arg(long, default_value_t = 256)]
+    stream_budget: usize,
+}
+
+impl Args {
+    /// `renderer::DEBUG_*` bits requested on the command line
+    fn debug_flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.iteration_view {
+            flags |= renderer::DEBUG_ITERATION_VIEW;
+        }
+        if self.no_skipping {
+            flags |= renderer::DEBUG_NO_SKIPPING;
+        }
+        flags
+    }
 }
 
 fn main() {
@@ -78,11 +197,181 @@ fn main() {
     pollster::block_on(run(args));
 }
 
+/// Load a scene file, picking the importer from its extension. Only scene
+/// descriptions carry a camera.
+fn load_scene_octree(path: &Path, args: &Args) -> std::io::Result<(Octree, Option<SceneCamera>)> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_ascii_lowercase();
+    let octree = match extension.as_str() {
+        "scene" => {
+            let scene = SceneDescription::load(path)?;
+            return Ok((scene.voxelize()?, scene.camera));
+        }
+        "vox" => VoxScene::load(path)?.to_octree(args.voxel_size)?,
+        "obj" | "stl" => {
+            let mut mesh = Mesh::load(path)?;
+            let offset = na::Vector3::new(args.mesh_offset[0], args.mesh_offset[1], args.mesh_offset[2]);
+            let transform = na::Matrix4::new_translation(&offset) * na::Matrix4::new_scaling(args.mesh_scale);
+            mesh.transform(&transform);
+            info!("Voxelizing {} triangles at voxel size {}", mesh.triangles.len(), args.voxel_size);
+            mesh.voxelize(args.voxel_size, args.mesh_solid)?
+        }
+        _ => Octree::load(path)?,
+    };
+    Ok((octree, None))
+}
+
+/// Camera from the command line, falling back to the scene's camera and then
+/// to a view into the Cornell Box
+fn resolve_camera(args: &Args, scene_camera: Option<SceneCamera>) -> SceneCamera {
+    let default = scene_camera.unwrap_or(SceneCamera {
+        eye: na::Point3::new(0.0, 1.0, -3.8),
+        target: na::Point3::new(0.0, 1.0, 1.0),
+    });
+    SceneCamera {
+        eye: na::Point3::new(
+            args.cam_x.unwrap_or(default.eye.x),
+            args.cam_y.unwrap_or(default.eye.y),
+            args.cam_z.unwrap_or(default.eye.z),
+        ),
+        target: na::Point3::new(
+            args.look_x.unwrap_or(default.target.x),
+            args.look_y.unwrap_or(default.target.y),
+            args.look_z.unwrap_or(default.target.z),
+        ),
+    }
+}
+
+/// Load the scene given by `--scene`, or voxelize the built-in Cornell Box,
+/// and save it if `--save-scene` is set
+fn build_scene(args: &Args) -> (Octree, Option<SceneCamera>) {
+    let (octree, camera) = match args.scene {
+        Some(ref path) => match load_scene_octree(path, args) {
+            Ok((octree, camera)) => {
+                info!("Loaded scene {} ({} nodes)", path.display(), octree.node_count());
+                (octree, camera)
+            }
+            Err(e) => {
+                log::error!("Failed to load scene {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            let scene = SceneDescription::cornell_box();
+            (scene.voxelize().expect("the built-in scene is valid"), scene.camera)
+        }
+    };
+
+    if let Some(ref path) = args.save_scene {
+        match octree.save(path) {
+            Ok(_) => info!("Scene saved to: {}", path.display()),
+            Err(e) => log::error!("Failed to save scene {}: {}", path.display(), e),
+        }
+    }
+
+    if let Some(ref path) = args.save_world
+        && let Err(e) = octree.save_world(path)
+    {
+        log::error!("Failed to save world {}: {}", path.display(), e);
+    }
+
+    (octree, camera)
+}
+
+/// Open the world directory given by `--scene` for streaming. Screenshots wait
+/// for the chunks around the camera instead of showing the coarse fallback.
+fn open_streaming_world(args: &Args, device: &Device, queue: &Queue) -> (Box<dyn OctreeProvider>, SceneCamera) {
+    let Some(ref path) = args.scene else {
+        log::error!("--provider streaming needs a world directory written by --save-world as --scene");
+        std::process::exit(1);
+    };
+    let mut provider = match StreamingOctreeProvider::open(path, args.stream_budget) {
+        Ok(provider) => provider,
+        Err(e) => {
+            log::error!("Failed to open world {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    provider.create_gpu_resources(device, queue);
+
+    let camera = resolve_camera(args, None);
+    provider.update_view(camera.eye);
+    if args.screenshot {
+        provider.wait_for_pending(queue);
+        info!("Loaded {} chunks around the camera", provider.resident_count());
+    }
+
+    (Box::new(provider), camera)
+}
+
+/// Keep the models of a `.scene` description apart for instancing; any other
+/// scene becomes a single instance of the whole octree
+fn build_instanced_scene(args: &Args) -> (InstancedScene, Option<SceneCamera>) {
+    let loaded = args.scene.as_ref()
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("scene")))
+        .map(|path| -> std::io::Result<_> {
+            let scene = SceneDescription::load(path)?;
+            Ok((scene.to_instanced()?, scene.camera))
+        });
+
+    match loaded {
+        Some(Ok(instanced)) => instanced,
+        Some(Err(e)) => {
+            log::error!("Failed to load scene {}: {}", args.scene.as_ref().unwrap().display(), e);
+            std::process::exit(1);
+        }
+        None => {
+            let (octree, camera) = build_scene(args);
+            (InstancedScene::from_octree(octree), camera)
+        }
+    }
+}
+
+/// Build or load the scene, hand it to the selected provider and pick the camera
+fn create_octree_provider(args: &Args, device: &Device, queue: &Queue) -> (Box<dyn OctreeProvider>, SceneCamera) {
+    if args.provider == Provider::Streaming {
+        return open_streaming_world(args, device, queue);
+    }
+    if args.provider == Provider::Instanced {
+        let (scene, scene_camera) = build_instanced_scene(args);
+        let mut provider = InstancedProvider::new(scene);
+        provider.create_gpu_resources(device);
+        return (Box::new(provider), resolve_camera(args, scene_camera));
+    }
+
+    let (octree, scene_camera) = build_scene(args);
+
+    let provider: Box<dyn OctreeProvider> = match args.provider {
+        Provider::Static => {
+            let mut provider = StaticOctreeProvider::from_octree(octree, args.gpu_storage);
+            provider.create_gpu_resources(device, queue);
+            Box::new(provider)
+        }
+        Provider::BrickMap => Box::new(BrickMapProvider::from_octree(&octree)),
+        Provider::Dag => Box::new(DagProvider::from_octree(&octree)),
+        Provider::Dynamic => {
+            let mut provider = DynamicOctreeProvider::new(octree, args.gpu_storage);
+            provider.create_gpu_resources(device, queue);
+            Box::new(provider)
+        }
+        Provider::Streaming | Provider::Instanced => unreachable!("handled before building a single octree"),
+    };
+
+    (provider, resolve_camera(args, scene_camera))
+}
+
 async fn run(args: Args) {
     info!("Starting Adaptive Voxel Path Tracer");
 
-    if args.benchmark {
+    if args.info {
+        let (octree, _) = build_scene(&args);
+        println!("\n=== Scene Info ===");
+        println!("Bounds:           center {:?}, half size {}, depth {}",
+                 octree.root.center.as_slice(), octree.root.half_size, octree.max_depth);
+        print!("{}", octree.stats());
+    } else if args.benchmark {
         benchmark::run_performance_benchmark(args.target_fps).await;
+        let (octree, _) = build_scene(&args);
+        benchmark::run_provider_benchmark(&octree, args.width, args.height).await;
     } else if args.screenshot {
         info!("Screenshot mode enabled");
         run_screenshot_mode(args).await;
@@ -106,14 +395,16 @@ struct Application {
 }
 
 impl Application {
-    fn new(device: Device, queue: Queue, renderer: VoxelRenderer) -> Self {
+    fn new(device: Device, queue: Queue, renderer: VoxelRenderer, camera: SceneCamera) -> Self {
+        // Start at the scene camera, turned towards its target
+        let direction = (camera.target - camera.eye).normalize();
         Self {
             renderer,
             device,
             queue,
-            camera_position: na::Point3::new(0.0, 1.0, -1.0),  // Start outside, looking in
-            camera_yaw: 0.0,    // Looking straight ahead (positive Z)
-            camera_pitch: 0.0,  // Level view
+            camera_position: camera.eye,
+            camera_yaw: direction.x.atan2(direction.z),
+            camera_pitch: direction.y.asin(),
             camera_speed: 0.05,
             mouse_sensitivity: 0.002,
             keys_pressed: HashSet::new(),
@@ -182,10 +473,47 @@ impl Application {
         self.camera_pitch = self.camera_pitch.clamp(-1.5, 1.5); // Limit to ~85 degrees up/down
     }
 
+    /// Clear a ball of voxels where the view ray first hits the scene.
+    /// Only dynamic providers report hits, so clicks do nothing elsewhere.
+    fn carve(&mut self) {
+        const RADIUS_CELLS: i32 = 3;
+
+        let origin = self.camera_position.coords;
+        let Some(hit) = self.renderer.octree_provider().raycast(origin, self.get_camera_direction(), f32::INFINITY) else {
+            return;
+        };
+
+        let provider = self.renderer.octree_provider_mut();
+        let (min, max) = provider.get_bounds();
+        let cell_size = (max - min).component_div(&provider.get_grid_dims().cast::<f32>()).min();
+        // Start half a cell inside the hit face so the surface voxel is the center
+        let center = hit.position - hit.normal * cell_size * 0.5;
+        for x in -RADIUS_CELLS..=RADIUS_CELLS {
+            for y in -RADIUS_CELLS..=RADIUS_CELLS {
+                for z in -RADIUS_CELLS..=RADIUS_CELLS {
+                    let offset = na::Vector3::new(x, y, z);
+                    if offset.dot(&offset) > RADIUS_CELLS * RADIUS_CELLS {
+                        continue;
+                    }
+                    // Cells past the scene bounds are simply not there to clear
+                    let _ = provider.update_voxel(center + offset.cast::<f32>() * cell_size, VoxelData::empty());
+                }
+            }
+        }
+    }
+
     fn handle_key(&mut self, keycode: KeyCode, state: ElementState) {
         match state {
             ElementState::Pressed => {
-                self.keys_pressed.insert(keycode);
+                // Debug toggles fire once per press, not on key repeat
+                if self.keys_pressed.insert(keycode) {
+                    match keycode {
+                        KeyCode::KeyI => self.renderer.toggle_debug_flags(&self.queue, renderer::DEBUG_ITERATION_VIEW),
+                        KeyCode::KeyK => self.renderer.toggle_debug_flags(&self.queue, renderer::DEBUG_NO_SKIPPING),
+                        KeyCode::KeyP => self.renderer.toggle_path_tracing(&self.queue),
+                        _ => {}
+                    }
+                }
             }
             ElementState::Released => {
                 self.keys_pressed.remove(&keycode);
@@ -206,7 +534,7 @@ impl Application {
         );
 
         // Show FPS in console periodically
-        if self.performance_monitor.total_frames % 60 == 0 {
+        if self.performance_monitor.total_frames.is_multiple_of(60) {
             info!("FPS: {:.1} | Camera: ({:.2}, {:.2}, {:.2})",
                   self.performance_monitor.get_current_fps(),
                   self.camera_position.x,
@@ -261,14 +589,17 @@ async fn run_interactive_mode(args: Args) {
     ).await.unwrap();
 
     let size = window.inner_size();
-    let renderer = VoxelRenderer::new(&device, &queue, &adapter, surface, size.width, size.height, args.target_fps);
-    let mut app = Application::new(device, queue, renderer);
+    let (octree_provider, camera) = create_octree_provider(&args, &device, &queue);
+    let renderer = VoxelRenderer::new(&device, &adapter, surface, size.width, size.height, args.target_fps, args.traversal, octree_provider);
+    let mut app = Application::new(device, queue, renderer, camera);
+    app.renderer.toggle_debug_flags(&app.queue, args.debug_flags());
+    app.renderer.set_path_tracing(&app.queue, args.max_bounces, args.samples);
 
     // Capture mouse cursor for FPS controls
     let _ = window.set_cursor_grab(winit::window::CursorGrabMode::Confined);
     window.set_cursor_visible(false);
 
-    info!("Controls: WASD - Move, Space/Shift - Up/Down, Mouse - Look around, ESC - Exit");
+    info!("Controls: WASD - Move, Space/Shift - Up/Down, Mouse - Look around, Click - Carve (dynamic provider), ESC - Exit");
 
     #[allow(deprecated)]
     let _ = event_loop.run(move |event, control_flow| {
@@ -281,16 +612,22 @@ async fn run_interactive_mode(args: Args) {
                     WindowEvent::CloseRequested => {
                         control_flow.exit();
                     }
-                    WindowEvent::Resized(physical_size) => {
-                        if physical_size.width > 0 && physical_size.height > 0 {
-                            app.resize(physical_size.width, physical_size.height);
-                        }
+                    WindowEvent::Resized(physical_size)
+                        if physical_size.width > 0 && physical_size.height > 0 => {
+                        app.resize(physical_size.width, physical_size.height);
                     }
                     WindowEvent::RedrawRequested => {
                         app.update_camera();
                         app.render();
                         window.request_redraw();
                     }
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } => {
+                        app.carve();
+                    }
                     WindowEvent::KeyboardInput {
                         event: winit::event::KeyEvent {
                             physical_key: PhysicalKey::Code(keycode),
@@ -325,7 +662,6 @@ async fn run_interactive_mode(args: Args) {
 }
 
 async fn run_screenshot_mode(args: Args) {
-    use renderer::performance_monitor::PerformanceMonitor;
     use std::time::Duration;
 
     info!("Initializing headless screenshot renderer");
@@ -383,7 +719,7 @@ async fn run_screenshot_mode(args: Args) {
 
     // Render one frame with specified camera parameters
     let texture_view = texture.create_view(&TextureViewDescriptor::default());
-    render_screenshot_frame(
+    let camera = render_screenshot_frame(
         &device,
         &queue,
         &texture_view,
@@ -438,8 +774,8 @@ async fn run_screenshot_mode(args: Args) {
     let filename = format!(
         "screenshot_{}_cam_{:.1}_{:.1}_{:.1}_look_{:.1}_{:.1}_{:.1}_{}x{}.png",
         timestamp,
-        args.cam_x, args.cam_y, args.cam_z,
-        args.look_x, args.look_y, args.look_z,
+        camera.eye.x, camera.eye.y, camera.eye.z,
+        camera.target.x, camera.target.y, camera.target.z,
         args.width, args.height
     );
 
@@ -467,12 +803,16 @@ async fn render_screenshot_frame(
     args: &Args,
     width: u32,
     height: u32,
-) {
+) -> SceneCamera {
     use nalgebra as na;
 
+    // Build the scene first so its camera can fill in unspecified parameters
+    let (octree_provider, camera) = create_octree_provider(args, device, queue);
+    let (octree_bind_group_layout, octree_bind_group) = octree_provider.bind_gpu_resources(device);
+
     // Create camera data with specified parameters
-    let eye = na::Point3::new(args.cam_x, args.cam_y, args.cam_z);
-    let target_point = na::Point3::new(args.look_x, args.look_y, args.look_z);
+    let eye = camera.eye;
+    let target_point = camera.target;
     let up = na::Vector3::new(0.0, 1.0, 0.0);
 
     let aspect_ratio = width as f32 / height as f32;
@@ -496,10 +836,15 @@ async fn render_screenshot_frame(
         _padding3: [0.0; 2],
     };
 
-    let performance_data = renderer::PerformanceData {
+    let performance_data = |frame_index| renderer::PerformanceData {
         base_voxel_size: 1.0,
         frame_time: 0.016,
-        _padding: [0.0; 2],
+        traversal_mode: args.traversal.unwrap_or(TraversalMode::Dda).shader_value(),
+        debug_flags: args.debug_flags() | if args.iteration_view { renderer::DEBUG_COUNT_ITERATIONS } else { 0 },
+        max_bounces: args.max_bounces,
+        samples_per_pixel: args.samples.max(1),
+        frame_index,
+        _padding: 0,
     };
 
     // Create buffers
@@ -509,67 +854,28 @@ async fn render_screenshot_frame(
         usage: BufferUsages::UNIFORM,
     });
 
+    let scene_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+        label: Some("Scene Buffer"),
+        contents: bytemuck::cast_slice(&[renderer::SceneData::from_provider(octree_provider.as_ref())]),
+        usage: BufferUsages::UNIFORM,
+    });
+
     let performance_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
         label: Some("Performance Buffer"),
-        contents: bytemuck::cast_slice(&[performance_data]),
-        usage: BufferUsages::UNIFORM,
+        contents: bytemuck::cast_slice(&[performance_data(0)]),
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
     });
 
     // Create bind group layouts
-    let camera_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        label: Some("Camera Bind Group Layout"),
-        entries: &[
-            BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
-    });
+    let camera_bind_group_layout = renderer::camera_bind_group_layout(device);
 
-    let performance_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        label: Some("Performance Bind Group Layout"),
-        entries: &[
-            BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::COMPUTE,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            },
-        ],
-    });
+    let performance_bind_group_layout = renderer::performance_bind_group_layout(device);
 
     // Create bind groups
-    let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
-        label: Some("Camera Bind Group"),
-        layout: &camera_bind_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            },
-        ],
-    });
+    let camera_bind_group = renderer::camera_bind_group(device, &camera_bind_group_layout, &camera_buffer, &scene_buffer);
 
-    let performance_bind_group = device.create_bind_group(&BindGroupDescriptor {
-        label: Some("Performance Bind Group"),
-        layout: &performance_bind_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: performance_buffer.as_entire_binding(),
-            },
-        ],
-    });
+    let stats_buffer = renderer::create_ray_stats_buffer(device);
+    let performance_bind_group = renderer::performance_bind_group(device, &performance_bind_group_layout, &performance_buffer, &stats_buffer);
 
     // Create compute texture
     let compute_texture = device.create_texture(&TextureDescriptor {
@@ -587,12 +893,7 @@ async fn render_screenshot_frame(
         view_formats: &[],
     });
     let compute_texture_view = compute_texture.create_view(&TextureViewDescriptor::default());
-
-    // Create octree provider
-    use octree::{OctreeProvider, static_provider::StaticOctreeProvider};
-    let mut octree_provider = Box::new(StaticOctreeProvider::new_cornell_box());
-    octree_provider.create_texture(device, queue);
-    let (octree_bind_group_layout, octree_bind_group) = octree_provider.bind_gpu_resources(device);
+    let accumulation_buffer = renderer::create_accumulation_buffer(device, width, height);
 
     // Create compute pipeline
     let compute_pipeline = renderer::compute_pipeline::ComputePipeline::new(
@@ -600,28 +901,40 @@ async fn render_screenshot_frame(
         &camera_bind_group_layout,
         &performance_bind_group_layout,
         &octree_bind_group_layout,
+        octree_provider.shader_source(),
     );
 
     // Create blit pipeline
     let blit_pipeline = renderer::blit_pipeline::BlitPipeline::new(device, TextureFormat::Rgba8UnormSrgb);
 
-    // Render frame
+    // Render the frames one submission each, so no single dispatch runs long
+    let frames = args.accumulate.max(1);
+    for frame_index in 0..frames {
+        queue.write_buffer(&performance_buffer, 0, bytemuck::cast_slice(&[performance_data(frame_index)]));
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+        compute_pipeline.dispatch(
+            device,
+            &mut encoder,
+            &compute_texture_view,
+            &accumulation_buffer,
+            &camera_bind_group,
+            &performance_bind_group,
+            &octree_bind_group,
+            width,
+            height,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+    if frames > 1 {
+        info!("Accumulated {} frames", frames);
+    }
+
     let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
-        label: Some("Render Encoder"),
+        label: Some("Blit Encoder"),
     });
 
-    // Run compute shader
-    compute_pipeline.dispatch(
-        device,
-        &mut encoder,
-        &compute_texture_view,
-        &camera_bind_group,
-        &performance_bind_group,
-        &octree_bind_group,
-        width,
-        height,
-    );
-
     // Blit to target
     blit_pipeline.blit(
         device,
@@ -631,4 +944,10 @@ async fn render_screenshot_frame(
     );
 
     queue.submit(std::iter::once(encoder.finish()));
+
+    if args.iteration_view {
+        let stats = renderer::read_ray_stats(device, queue, &stats_buffer);
+        println!("{:.1} lookups per ray", stats.iterations_per_ray());
+    }
+    camera
 }
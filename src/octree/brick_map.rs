@@ -0,0 +1,310 @@
+use super::{Octree, OctreeProvider, VoxelData};
+use super::linear::pack_rgba8;
+use super::material::{self, MaterialTable};
+use bytemuck::{Pod, Zeroable};
+use nalgebra as na;
+use std::collections::HashMap;
+use wgpu::*;
+use wgpu::util::DeviceExt;
+use log::info;
+
+/// Voxels along each edge of a brick
+pub const BRICK_SIZE: usize = 8;
+const BRICK_VOXELS: usize = BRICK_SIZE * BRICK_SIZE * BRICK_SIZE;
+
+/// Grid value for a cell without a brick
+const EMPTY_BRICK: u32 = u32::MAX;
+
+/// Uniform header for `brick_map.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuBrickMapHeader {
+    pub origin: [f32; 3],
+    pub voxel_size: f32,
+    pub grid_dims: [u32; 3],
+    pub brick_count: u32,
+}
+
+/// Two-level voxel grid: a shallow grid over the scene whose cells point at
+/// fixed-size 8³ bricks in a brick atlas. Only bricks holding at least one voxel
+/// are stored, and the GPU steps through it with a coarse/fine DDA instead of
+/// descending a pointer tree.
+pub struct BrickMapProvider {
+    origin: na::Vector3<f32>,
+    voxel_size: f32,
+    grid_dims: na::Vector3<u32>,
+    /// Brick index per grid cell, x fastest, `EMPTY_BRICK` when unoccupied
+    grid: Vec<u32>,
+    /// `BRICK_VOXELS` palette references per brick; 0 is empty, `i` is `palette[i - 1]`
+    bricks: Vec<u32>,
+    palette: Vec<VoxelData>,
+    materials: MaterialTable,
+    base_voxel_size: f32,
+}
+
+impl BrickMapProvider {
+    /// Rasterize the octree's leaves at its finest resolution into bricks
+    pub fn from_octree(octree: &Octree) -> Self {
+        let resolution = 1u32 << octree.max_depth;
+        let grid_size = resolution.div_ceil(BRICK_SIZE as u32);
+        let voxel_size = octree.root.half_size * 2.0 / resolution as f32;
+        let origin = octree.root.center.add_scalar(-octree.root.half_size);
+
+        let mut provider = Self {
+            origin,
+            voxel_size,
+            grid_dims: na::Vector3::repeat(grid_size),
+            grid: vec![EMPTY_BRICK; (grid_size as usize).pow(3)],
+            bricks: Vec::new(),
+            palette: Vec::new(),
+            materials: octree.materials.clone(),
+            base_voxel_size: 0.02,
+        };
+
+        let mut palette_indices: HashMap<[u32; 8], u32> = HashMap::new();
+        for (center, half_size, data) in octree.leaves() {
+            let key: [u32; 8] = bytemuck::cast(data);
+            let material = *palette_indices.entry(key).or_insert_with(|| {
+                provider.palette.push(data);
+                provider.palette.len() as u32
+            });
+
+            // Collapsed leaves cover a cube of finest-level voxels
+            let to_voxel = |world: na::Vector3<f32>| ((world - origin) / voxel_size).map(|v| v.round() as u32);
+            let first = to_voxel(center.add_scalar(-half_size));
+            let last = to_voxel(center.add_scalar(half_size));
+            for z in first.z..last.z {
+                for y in first.y..last.y {
+                    for x in first.x..last.x {
+                        provider.set_voxel(na::Vector3::new(x, y, z), material);
+                    }
+                }
+            }
+        }
+
+        let brick_count = provider.brick_count();
+        info!("Built brick map: {}³ grid, {} bricks ({:.1}% of cells), {} materials, {:.2} MB atlas",
+              grid_size, brick_count,
+              brick_count as f64 * 100.0 / provider.grid.len() as f64,
+              provider.palette.len(),
+              (brick_count * BRICK_VOXELS * 4) as f64 / (1024.0 * 1024.0));
+
+        provider
+    }
+
+    pub fn brick_count(&self) -> usize {
+        self.bricks.len() / BRICK_VOXELS
+    }
+
+    fn grid_index(&self, brick: na::Vector3<u32>) -> usize {
+        ((brick.z * self.grid_dims.y + brick.y) * self.grid_dims.x + brick.x) as usize
+    }
+
+    fn set_voxel(&mut self, voxel: na::Vector3<u32>, material: u32) {
+        let brick = voxel / BRICK_SIZE as u32;
+        if brick.iter().zip(self.grid_dims.iter()).any(|(b, dim)| b >= dim) {
+            return;
+        }
+
+        let cell = self.grid_index(brick);
+        if self.grid[cell] == EMPTY_BRICK {
+            self.grid[cell] = self.brick_count() as u32;
+            self.bricks.resize(self.bricks.len() + BRICK_VOXELS, 0);
+        }
+        let offset = brick_offset(voxel);
+        self.bricks[self.grid[cell] as usize * BRICK_VOXELS + offset] = material;
+    }
+
+    fn header(&self) -> GpuBrickMapHeader {
+        GpuBrickMapHeader {
+            origin: self.origin.into(),
+            voxel_size: self.voxel_size,
+            grid_dims: self.grid_dims.into(),
+            brick_count: self.brick_count() as u32,
+        }
+    }
+}
+
+/// Position of a voxel within its brick, x fastest
+fn brick_offset(voxel: na::Vector3<u32>) -> usize {
+    let local = voxel.map(|v| v as usize % BRICK_SIZE);
+    (local.z * BRICK_SIZE + local.y) * BRICK_SIZE + local.x
+}
+
+impl OctreeProvider for BrickMapProvider {
+    /// Brick maps hold a single resolution, so the distance is not used
+    fn sample_voxel(&self, position: na::Vector3<f32>, _distance_from_camera: f32) -> VoxelData {
+        let local = (position - self.origin) / self.voxel_size;
+        let extent = (self.grid_dims * BRICK_SIZE as u32).map(|v| v as f32);
+        if local.iter().zip(extent.iter()).any(|(v, max)| *v < 0.0 || v >= max) {
+            return VoxelData::empty();
+        }
+
+        let voxel = local.map(|v| v as u32);
+        let brick = self.grid[self.grid_index(voxel / BRICK_SIZE as u32)];
+        if brick == EMPTY_BRICK {
+            return VoxelData::empty();
+        }
+
+        match self.bricks[brick as usize * BRICK_VOXELS + brick_offset(voxel)] {
+            0 => VoxelData::empty(),
+            material => self.palette[material as usize - 1],
+        }
+    }
+
+    fn set_performance_target(&mut self, target_voxel_size: f32) {
+        self.base_voxel_size = target_voxel_size;
+    }
+
+    fn get_bounds(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let extent = (self.grid_dims * BRICK_SIZE as u32).map(|v| v as f32 * self.voxel_size);
+        (self.origin, self.origin + extent)
+    }
+
+    fn get_grid_dims(&self) -> na::Vector3<u32> {
+        self.grid_dims * BRICK_SIZE as u32
+    }
+
+    /// Uploads the grid, the brick atlas of palette references and the palette
+    /// itself; the buffers live as long as the bind group
+    fn bind_gpu_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+        let packed_palette: Vec<[u32; 2]> = std::iter::once([0; 2])
+            .chain(self.palette.iter().map(|data| [pack_rgba8(data), self.materials.lookup(data)]))
+            .collect();
+        // Storage bindings cannot be empty
+        let atlas: &[u32] = if self.bricks.is_empty() { &[0] } else { &self.bricks };
+
+        let grid_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Brick Grid Buffer"),
+            contents: bytemuck::cast_slice(&self.grid),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let atlas_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Brick Atlas Buffer"),
+            contents: bytemuck::cast_slice(atlas),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let palette_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Brick Palette Buffer"),
+            contents: bytemuck::cast_slice(&packed_palette),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let material_buffer = self.materials.create_buffer(device);
+
+        let header_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Brick Map Header Buffer"),
+            contents: bytemuck::cast_slice(&[self.header()]),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Brick Map Bind Group Layout"),
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(3),
+                material::material_layout_entry(),
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Brick Map Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: grid_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: atlas_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: header_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: palette_buffer.as_entire_binding(),
+                },
+                material::material_bind_entry(&material_buffer),
+            ],
+        });
+
+        (bind_group_layout, bind_group)
+    }
+
+    fn shader_source(&self) -> &'static str {
+        include_str!("../shaders/brick_map.wgsl")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::test_scenes::{self, COLORS, Rng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn bricks_match_the_octree_at_every_cell() {
+        // 32³ cells over several materials: scattered voxels, a collapsed brick
+        // and a collapsed 4³ block inside another brick
+        let mut octree = Octree::new(na::Vector3::new(0.5, 0.0, -1.0), 2.0, 5);
+        let lamp = octree.materials.insert(material::Material::emissive([1.0, 0.8, 0.6], 3.0));
+        let metal = octree.materials.insert(material::Material::metal(0.3));
+        let mut voxels = test_scenes::random_voxels(&mut Rng::new(0x85ebca6b), 300, 32, metal + 1);
+        voxels.extend(test_scenes::block([8, 16, 0], 8, VoxelData { material: lamp, ..VoxelData::solid(COLORS[0]) }));
+        voxels.extend(test_scenes::block([20, 4, 28], 4, VoxelData::solid(COLORS[1])));
+        test_scenes::insert_cells(&mut octree, &voxels);
+        assert!(octree.leaves().any(|(_, half_size, _)| half_size == 0.5));
+
+        let provider = BrickMapProvider::from_octree(&octree);
+        let root_min = octree.root.center.add_scalar(-octree.root.half_size);
+        let mut occupied_bricks = HashSet::new();
+        for i in 0..32 * 32 * 32 {
+            let cell = na::Vector3::new(i % 32, i / 32 % 32, i / 1024);
+            let position = root_min + cell.map(|v| (v as f32 + 0.5) * 0.125);
+            let expected = octree.sample(position, 0);
+            assert_eq!(provider.sample_voxel(position, 0.0), expected, "cell {:?}", cell);
+            if expected.density > 0.0 {
+                occupied_bricks.insert(cell / BRICK_SIZE as u32);
+            }
+        }
+        assert_eq!(provider.brick_count(), occupied_bricks.len());
+
+        // One palette entry per distinct voxel, however many leaves share it
+        let mut distinct: Vec<VoxelData> = Vec::new();
+        for (_, _, data) in octree.leaves() {
+            if !distinct.contains(&data) {
+                distinct.push(data);
+            }
+        }
+        assert_eq!(provider.palette.len(), distinct.len());
+        assert!(provider.palette.len() < octree.leaves().count());
+        assert!(provider.palette.iter().all(|data| distinct.contains(data)));
+    }
+}
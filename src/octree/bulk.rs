@@ -0,0 +1,151 @@
+use super::scene_file::invalid;
+use super::{MAX_DEPTH, Octree, OctreeNode, VoxelData};
+use nalgebra as na;
+use rayon::prelude::*;
+use std::io;
+
+/// Subtrees holding more voxels than this are built on the thread pool
+const PARALLEL_VOXELS: usize = 4096;
+
+/// Interleave the low 21 bits of each coordinate into a Morton (Z-order) code,
+/// x in the lowest bit to match the child index order of `OctreeNode`
+pub fn morton_encode(x: u32, y: u32, z: u32) -> u64 {
+    spread_bits(x) | spread_bits(y) << 1 | spread_bits(z) << 2
+}
+
+fn spread_bits(value: u32) -> u64 {
+    let mut v = value as u64 & 0x1f_ffff;
+    v = (v | v << 32) & 0x1f_0000_0000_ffff;
+    v = (v | v << 16) & 0x1f_0000_ff00_00ff;
+    v = (v | v << 8) & 0x100f_00f0_0f00_f00f;
+    v = (v | v << 4) & 0x10c3_0c30_c30c_30c3;
+    v = (v | v << 2) & 0x1249_2492_4924_9249;
+    v
+}
+
+impl Octree {
+    /// Build an octree from finest-level cells without incremental inserts.
+    /// Cell `(x, y, z)` lies in `0..2^max_depth` from the root's minimum corner;
+    /// out-of-range cells are ignored and the last duplicate wins, as with `insert`.
+    /// The cells are sorted by Morton code so every subtree is a contiguous run,
+    /// and large subtrees are built in parallel. The result is identical to
+    /// inserting each cell, including collapsed uniform regions and LoD data.
+    /// Fails when `max_depth` exceeds `MAX_DEPTH`.
+    pub fn from_voxels(center: na::Vector3<f32>, half_size: f32, max_depth: u8, voxels: Vec<([u32; 3], VoxelData)>) -> io::Result<Self> {
+        if max_depth > MAX_DEPTH {
+            return Err(invalid(&format!("octree depth {} exceeds the maximum of {}", max_depth, MAX_DEPTH)));
+        }
+        let resolution = 1u64 << max_depth;
+
+        let mut coded: Vec<(u64, VoxelData)> = voxels
+            .into_par_iter()
+            .filter(|(cell, _)| cell.iter().all(|&v| (v as u64) < resolution))
+            .map(|([x, y, z], data)| (morton_encode(x, y, z), data))
+            .collect();
+        coded.par_sort_by_key(|(code, _)| *code);
+
+        // Keep the last of each run of equal codes; the sort is stable
+        let mut unique: Vec<(u64, VoxelData)> = Vec::with_capacity(coded.len());
+        for entry in coded {
+            match unique.last_mut() {
+                Some(last) if last.0 == entry.0 => *last = entry,
+                _ => unique.push(entry),
+            }
+        }
+
+        let mut octree = Octree::new(center, half_size, max_depth);
+        if !unique.is_empty() {
+            build_node(&mut octree.root, &unique, max_depth);
+        }
+        Ok(octree)
+    }
+}
+
+/// Fill `node` from a Morton-sorted run of cells lying inside it. `levels` is
+/// the number of octree levels below the node.
+fn build_node(node: &mut OctreeNode, voxels: &[(u64, VoxelData)], levels: u8) {
+    if levels == 0 {
+        node.voxel_data = Some(voxels[0].1);
+        return;
+    }
+
+    node.subdivide();
+    let Some(ref mut children) = node.children else {
+        return;
+    };
+
+    // The child index is the three Morton bits of the level below this node
+    let shift = (levels as u32 - 1) * 3;
+    let mut runs: [&[(u64, VoxelData)]; 8] = [&[]; 8];
+    let mut rest = voxels;
+    for (index, run) in runs.iter_mut().enumerate() {
+        let end = rest.partition_point(|(code, _)| ((code >> shift) & 7) as usize <= index);
+        (*run, rest) = rest.split_at(end);
+    }
+
+    if voxels.len() > PARALLEL_VOXELS {
+        children.par_iter_mut().zip(runs.par_iter()).for_each(|(child, run)| {
+            if !run.is_empty() {
+                build_node(child, run, levels - 1);
+            }
+        });
+    } else {
+        for (child, run) in children.iter_mut().zip(runs.iter()) {
+            if !run.is_empty() {
+                build_node(child, run, levels - 1);
+            }
+        }
+    }
+
+    if !node.try_collapse() {
+        node.aggregate_children();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::test_scenes::{self, COLORS, Rng};
+
+    fn assert_same_nodes(a: &OctreeNode, b: &OctreeNode) {
+        assert_eq!((a.center, a.half_size, a.level), (b.center, b.half_size, b.level));
+        assert_eq!(a.voxel_data, b.voxel_data, "node at {:?}", a.center);
+        match (&a.children, &b.children) {
+            (Some(a_children), Some(b_children)) => {
+                for (a_child, b_child) in a_children.iter().zip(b_children.iter()) {
+                    assert_same_nodes(a_child, b_child);
+                }
+            }
+            (None, None) => {}
+            _ => panic!("node at {:?} is a leaf in only one tree", a.center),
+        }
+    }
+
+    #[test]
+    fn matches_sequential_inserts() {
+        // Scattered cells with repeats and some out of range, and a 4³ block
+        let mut voxels = test_scenes::random_voxels(&mut Rng::new(0x9e3779b9), 400, 18, 1);
+        voxels.extend(test_scenes::block([8, 4, 12], 4, VoxelData::solid(COLORS[2])));
+
+        let center = na::Vector3::new(1.0, -2.0, 0.5);
+        let half_size = 2.0;
+        let max_depth = 4;
+        // The 4³ block is one depth 2 leaf
+        let block_half_size = half_size / 4.0;
+        let bulk = Octree::from_voxels(center, half_size, max_depth, voxels.clone()).unwrap();
+
+        let mut inserted = Octree::new(center, half_size, max_depth);
+        test_scenes::insert_cells(&mut inserted, &voxels);
+        inserted.optimize();
+
+        assert_same_nodes(&bulk.root, &inserted.root);
+        assert!(bulk.leaves().any(|(_, half_size, _)| half_size == block_half_size));
+    }
+
+    #[test]
+    fn rejects_depths_past_morton_range() {
+        let voxels = vec![([0, 0, 0], VoxelData::solid([1.0; 3]))];
+        assert!(Octree::from_voxels(na::Vector3::zeros(), 1.0, MAX_DEPTH + 1, voxels.clone()).is_err());
+        assert!(Octree::from_voxels(na::Vector3::zeros(), 1.0, MAX_DEPTH, voxels).is_ok());
+    }
+}
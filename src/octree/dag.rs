@@ -0,0 +1,410 @@
+use super::{Octree, OctreeNode, OctreeProvider, VoxelData};
+use super::linear::{GpuOctreeNode, pack_rgba8, unpack_rgba8};
+use super::material::{self, MaterialTable};
+use bytemuck::{Pod, Zeroable};
+use nalgebra as na;
+use std::collections::HashMap;
+use wgpu::*;
+use wgpu::util::DeviceExt;
+use log::info;
+
+/// Set on a child reference that points at an attribute instead of a node
+const LEAF_BIT: u32 = 1 << 31;
+
+/// Uniform header for `dag.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuDagHeader {
+    pub center: [f32; 3],
+    pub half_size: f32,
+    pub max_depth: u32,
+    pub root: u32,
+    pub _padding: [u32; 2],
+}
+
+/// Sizes of an octree and of the DAG built from it
+#[derive(Copy, Clone, Debug)]
+pub struct DagStats {
+    pub octree_nodes: usize,
+    /// Size of the same octree as a `LinearOctree` node buffer
+    pub octree_bytes: usize,
+    pub dag_nodes: usize,
+    pub dag_bytes: usize,
+    pub attributes: usize,
+}
+
+impl DagStats {
+    /// How many times smaller the DAG is than the linear octree
+    pub fn compression_ratio(&self) -> f64 {
+        self.octree_bytes as f64 / self.dag_bytes.max(1) as f64
+    }
+}
+
+/// Sparse voxel directed acyclic graph: an octree in which identical subtrees
+/// are stored once. Nodes are variable length in `nodes`: a word holding the
+/// 8-bit child mask, then one reference per occupied child in octant order. A
+/// reference is either the index of another node or `LEAF_BIT` plus an index
+/// into `attributes`, the table of distinct (RGBA8, material index) pairs.
+/// Keeping the attributes out of the nodes lets equal geometry with equal
+/// materials share one subtree wherever it appears. Interior LoD data is not kept.
+pub struct SparseVoxelDag {
+    pub center: na::Vector3<f32>,
+    pub half_size: f32,
+    pub max_depth: u8,
+    /// Reference to the root, in the same encoding as child references
+    pub root: u32,
+    pub nodes: Vec<u32>,
+    pub attributes: Vec<[u32; 2]>,
+}
+
+impl SparseVoxelDag {
+    /// Deduplicate the octree's subtrees bottom-up with a hash of their encoding
+    pub fn from_octree(octree: &Octree) -> Self {
+        let mut builder = DagBuilder {
+            materials: &octree.materials,
+            nodes: Vec::new(),
+            attributes: Vec::new(),
+            node_indices: HashMap::new(),
+            attribute_indices: HashMap::new(),
+        };
+
+        // An empty scene is a root without children
+        let root = match builder.add_subtree(&octree.root) {
+            Some(root) => root,
+            None => builder.add_node(vec![0]),
+        };
+
+        Self {
+            center: octree.root.center,
+            half_size: octree.root.half_size,
+            max_depth: octree.max_depth,
+            root,
+            nodes: builder.nodes,
+            attributes: builder.attributes,
+        }
+    }
+
+    /// Number of distinct nodes, leaves excluded
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut index = 0;
+        while index < self.nodes.len() {
+            index += 1 + (self.nodes[index] & 0xff).count_ones() as usize;
+            count += 1;
+        }
+        count
+    }
+
+    /// Size of the node and attribute buffers in bytes
+    pub fn size_bytes(&self) -> usize {
+        (self.nodes.len() + self.attributes.len() * 2) * 4
+    }
+
+    /// Compare against the octree this DAG was built from
+    pub fn stats(&self, octree: &Octree) -> DagStats {
+        let octree_nodes = octree.node_count();
+        DagStats {
+            octree_nodes,
+            octree_bytes: octree_nodes * std::mem::size_of::<GpuOctreeNode>(),
+            dag_nodes: self.node_count(),
+            dag_bytes: self.size_bytes(),
+            attributes: self.attributes.len(),
+        }
+    }
+
+    /// Packed RGBA8 value and material index at a position, `None` when empty
+    pub fn lookup(&self, position: na::Vector3<f32>) -> Option<[u32; 2]> {
+        if (position - self.center).abs().max() > self.half_size {
+            return None;
+        }
+
+        let mut center = self.center;
+        let mut half_size = self.half_size;
+        let mut reference = self.root;
+        for _ in 0..self.max_depth {
+            if reference & LEAF_BIT != 0 {
+                break;
+            }
+
+            let mask = self.nodes[reference as usize] & 0xff;
+            half_size *= 0.5;
+            let mut octant = 0;
+            if position.x > center.x { octant |= 1; }
+            if position.y > center.y { octant |= 2; }
+            if position.z > center.z { octant |= 4; }
+            center += position.zip_map(&center, |p, c| if p > c { half_size } else { -half_size });
+
+            if mask & (1 << octant) == 0 {
+                return None;
+            }
+            let slot = (mask & ((1 << octant) - 1)).count_ones() as usize;
+            reference = self.nodes[reference as usize + 1 + slot];
+        }
+
+        (reference & LEAF_BIT != 0).then(|| self.attributes[(reference & !LEAF_BIT) as usize])
+    }
+
+    fn header(&self) -> GpuDagHeader {
+        GpuDagHeader {
+            center: self.center.into(),
+            half_size: self.half_size,
+            max_depth: self.max_depth as u32,
+            root: self.root,
+            _padding: [0; 2],
+        }
+    }
+}
+
+struct DagBuilder<'a> {
+    materials: &'a MaterialTable,
+    nodes: Vec<u32>,
+    attributes: Vec<[u32; 2]>,
+    node_indices: HashMap<Vec<u32>, u32>,
+    attribute_indices: HashMap<[u32; 2], u32>,
+}
+
+impl DagBuilder<'_> {
+    /// Reference for a subtree, or `None` if it holds no voxels
+    fn add_subtree(&mut self, node: &OctreeNode) -> Option<u32> {
+        let Some(ref children) = node.children else {
+            return node.voxel_data
+                .filter(|data| data.density > 0.0)
+                .map(|data| LEAF_BIT | self.add_attribute([pack_rgba8(&data), self.materials.lookup(&data)]));
+        };
+
+        let mut words = vec![0];
+        for (octant, child) in children.iter().enumerate() {
+            if let Some(reference) = self.add_subtree(child) {
+                words[0] |= 1 << octant;
+                words.push(reference);
+            }
+        }
+
+        if words[0] == 0 {
+            return None;
+        }
+        Some(self.add_node(words))
+    }
+
+    fn add_node(&mut self, words: Vec<u32>) -> u32 {
+        if let Some(&index) = self.node_indices.get(&words) {
+            return index;
+        }
+        let index = self.nodes.len() as u32;
+        self.nodes.extend_from_slice(&words);
+        self.node_indices.insert(words, index);
+        index
+    }
+
+    fn add_attribute(&mut self, attribute: [u32; 2]) -> u32 {
+        *self.attribute_indices.entry(attribute).or_insert_with(|| {
+            self.attributes.push(attribute);
+            self.attributes.len() as u32 - 1
+        })
+    }
+}
+
+/// Renders a `SparseVoxelDag` with a stack-free descent in `dag.wgsl`
+pub struct DagProvider {
+    dag: SparseVoxelDag,
+    materials: MaterialTable,
+    base_voxel_size: f32,
+}
+
+impl DagProvider {
+    pub fn from_octree(octree: &Octree) -> Self {
+        let materials = octree.materials.clone();
+        let dag = SparseVoxelDag::from_octree(octree);
+
+        let stats = dag.stats(octree);
+        info!("Built sparse voxel DAG: {} octree nodes ({:.2} MB) -> {} DAG nodes ({:.2} MB), {} attributes, {:.1}:1",
+              stats.octree_nodes, stats.octree_bytes as f64 / (1024.0 * 1024.0),
+              stats.dag_nodes, stats.dag_bytes as f64 / (1024.0 * 1024.0),
+              stats.attributes, stats.compression_ratio());
+
+        Self { dag, materials, base_voxel_size: 0.02 }
+    }
+
+    pub fn dag(&self) -> &SparseVoxelDag {
+        &self.dag
+    }
+}
+
+impl OctreeProvider for DagProvider {
+    /// The DAG keeps no LoD data, so the distance is not used
+    fn sample_voxel(&self, position: na::Vector3<f32>, _distance_from_camera: f32) -> VoxelData {
+        let Some([packed, material]) = self.dag.lookup(position) else {
+            return VoxelData::empty();
+        };
+        let mut data = unpack_rgba8(packed);
+        data.material = material;
+        data.emission = self.materials.get(material).map_or([0.0; 3], |m| m.radiance());
+        data
+    }
+
+    fn set_performance_target(&mut self, target_voxel_size: f32) {
+        self.base_voxel_size = target_voxel_size;
+    }
+
+    fn get_bounds(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        (self.dag.center.add_scalar(-self.dag.half_size), self.dag.center.add_scalar(self.dag.half_size))
+    }
+
+    fn get_grid_dims(&self) -> na::Vector3<u32> {
+        na::Vector3::repeat(1 << self.dag.max_depth)
+    }
+
+    /// Uploads the node, attribute and material buffers; they live as long as the bind group
+    fn bind_gpu_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+        // Storage bindings cannot be empty
+        let attributes: &[[u32; 2]] = if self.dag.attributes.is_empty() { &[[0; 2]] } else { &self.dag.attributes };
+        let material_buffer = self.materials.create_buffer(device);
+
+        let node_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("DAG Node Buffer"),
+            contents: bytemuck::cast_slice(&self.dag.nodes),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let attribute_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("DAG Attribute Buffer"),
+            contents: bytemuck::cast_slice(attributes),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let header_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("DAG Header Buffer"),
+            contents: bytemuck::cast_slice(&[self.dag.header()]),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("DAG Bind Group Layout"),
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                material::material_layout_entry(),
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("DAG Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: node_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: attribute_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: header_buffer.as_entire_binding(),
+                },
+                material::material_bind_entry(&material_buffer),
+            ],
+        });
+
+        (bind_group_layout, bind_group)
+    }
+
+    fn shader_source(&self) -> &'static str {
+        include_str!("../shaders/dag.wgsl")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::test_scenes::{self, COLORS, Rng};
+
+    /// Center of depth 3 cell (x, y, z) in the cube -1..1
+    fn cell(x: u32, y: u32, z: u32) -> na::Vector3<f32> {
+        na::Vector3::new(x, y, z).map(|i| i as f32 * 0.25 - 0.875)
+    }
+
+    /// Every depth 3 cell center paired with what the octree holds there
+    fn expected_lookups(octree: &Octree) -> Vec<(na::Vector3<f32>, Option<[u32; 2]>)> {
+        (0..512)
+            .map(|i| {
+                let position = cell(i & 7, i >> 3 & 7, i >> 6);
+                let data = octree.sample(position, 0);
+                let expected = (data.density > 0.0).then(|| [pack_rgba8(&data), octree.materials.lookup(&data)]);
+                (position, expected)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeated_octants_are_stored_once() {
+        // The same two corner voxels in each of the root's octants
+        let mut octree = Octree::new(na::Vector3::zeros(), 1.0, 3);
+        let wall = VoxelData::solid([0.73; 3]);
+        for octant in 0..8 {
+            let base = [octant & 1, octant >> 1 & 1, octant >> 2].map(|bit| bit * 4);
+            octree.insert(cell(base[0], base[1], base[2]), wall);
+            octree.insert(cell(base[0] + 3, base[1] + 3, base[2] + 3), wall);
+        }
+
+        let dag = SparseVoxelDag::from_octree(&octree);
+        for (position, expected) in expected_lookups(&octree) {
+            assert_eq!(dag.lookup(position), expected, "at {:?}", position);
+        }
+
+        // Root, one shared octant, and its two corner nodes
+        assert_eq!(dag.node_count(), 4);
+        assert_eq!(dag.attributes.len(), 1);
+        let stats = dag.stats(&octree);
+        assert_eq!(stats.octree_nodes, 1 + 8 + 8 * 8 + 16 * 8);
+        assert!(stats.compression_ratio() > 1.0);
+    }
+
+    #[test]
+    fn lookups_match_the_source_tree() {
+        // Scattered voxels over two materials, and a uniform octant
+        let mut octree = Octree::new(na::Vector3::zeros(), 1.0, 3);
+        let lamp = octree.materials.insert(material::Material::emissive([1.0, 0.0, 0.0], 4.0));
+        let mut voxels = test_scenes::random_voxels(&mut Rng::new(0x1b873593), 150, 8, lamp + 1);
+        voxels.extend(test_scenes::block([4, 4, 4], 4, VoxelData::solid(COLORS[0])));
+        test_scenes::insert_cells(&mut octree, &voxels);
+
+        let dag = SparseVoxelDag::from_octree(&octree);
+        for (position, expected) in expected_lookups(&octree) {
+            assert_eq!(dag.lookup(position), expected, "at {:?}", position);
+        }
+        assert_eq!(dag.lookup(na::Vector3::new(1.5, 0.0, 0.0)), None);
+        assert!(dag.node_count() < octree.node_count());
+    }
+
+    #[test]
+    fn empty_scene() {
+        let octree = Octree::new(na::Vector3::zeros(), 1.0, 3);
+        let dag = SparseVoxelDag::from_octree(&octree);
+        assert_eq!(dag.node_count(), 1);
+        assert!(dag.attributes.is_empty());
+        assert!(expected_lookups(&octree).iter().all(|&(position, _)| dag.lookup(position).is_none()));
+    }
+}
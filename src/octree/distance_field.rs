@@ -0,0 +1,329 @@
+use super::static_provider::VolumeTexels;
+use rayon::prelude::*;
+
+/// Distances are stored in fractions of a texel
+pub const UNITS_PER_TEXEL: f32 = 4.0;
+
+/// Distances are capped here, in texels, so they fit the R8Sint texture and
+/// an edit only reaches this far into the field
+pub const MAX_DISTANCE: f32 = 31.0;
+
+/// Squared distance standing in for "no seed on this line"; larger than any
+/// real one yet small enough for the parabola intersections to stay exact in f32
+const FAR: f32 = 1e6;
+
+/// Signed Euclidean distance field over a dense `size`³ volume, in
+/// `1 / UNITS_PER_TEXEL` texel units, rounded towards zero and capped at
+/// `MAX_DISTANCE`. An empty texel holds the distance from its center to the
+/// center of the nearest occupied texel, an occupied texel minus the distance
+/// to the nearest empty one. Any texel with a density counts as occupied, so
+/// stepping by the field never passes over a voxel the shader could hit.
+pub(super) struct DistanceField {
+    size: u32,
+    values: Vec<i8>,
+}
+
+impl DistanceField {
+    /// Exact field over a volume baked in one piece, from two separable
+    /// distance transforms (Felzenszwalb & Huttenlocher), one seeded by the
+    /// occupied texels and one by the empty ones
+    pub(super) fn from_texels(size: u32, texels: &VolumeTexels) -> Self {
+        let occupied: Vec<bool> = texels.colors.chunks_exact(4).map(|color| color[3] > 0).collect();
+        let mut values: Vec<i8> = squared_distances(size, |i| occupied[i]).into_par_iter()
+            .map(encode)
+            .collect();
+        values.par_iter_mut()
+            .zip(squared_distances(size, |i| !occupied[i]))
+            .zip(&occupied)
+            .filter(|(_, occupied)| **occupied)
+            .for_each(|((value, inside), _)| *value = -encode(inside));
+        Self { size, values }
+    }
+
+    /// Bring the field up to date with a re-baked box of texels. Texels that
+    /// became occupied lower the distances around the box, computed from the
+    /// box as a whole; texels that emptied drop to zero, which only means "no
+    /// known clearance". Both keep every stored distance a lower bound of the
+    /// true one, though inside distances and freed space only come back
+    /// exactly on a full rebuild. Returns the origin and extent that changed.
+    pub(super) fn update(&mut self, origin: [u32; 3], extent: [u32; 3], texels: &VolumeTexels) -> ([u32; 3], [u32; 3]) {
+        let size = self.size as usize;
+        let mut densities = texels.colors.chunks_exact(4).map(|color| color[3]);
+        let mut filled = false;
+        for z in origin[2]..origin[2] + extent[2] {
+            for y in origin[1]..origin[1] + extent[1] {
+                let row = (z as usize * size + y as usize) * size;
+                for x in origin[0]..origin[0] + extent[0] {
+                    let value = &mut self.values[row + x as usize];
+                    match densities.next().unwrap() > 0 {
+                        true if *value >= 0 => {
+                            *value = -(UNITS_PER_TEXEL as i8);
+                            filled = true;
+                        }
+                        false if *value < 0 => *value = 0,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if !filled {
+            return (origin, extent);
+        }
+
+        // Farther texels already hold at most MAX_DISTANCE
+        let reach = MAX_DISTANCE.ceil() as u32;
+        let min = origin.map(|texel| texel.saturating_sub(reach));
+        let max = [0, 1, 2].map(|axis| (origin[axis] + extent[axis] - 1 + reach).min(self.size - 1));
+        let gap = |texel: u32, axis: usize| {
+            let last = origin[axis] + extent[axis] - 1;
+            (origin[axis].saturating_sub(texel) + texel.saturating_sub(last)) as f32
+        };
+
+        self.values.par_chunks_mut(size * size)
+            .enumerate()
+            .skip(min[2] as usize)
+            .take((max[2] - min[2] + 1) as usize)
+            .for_each(|(z, slice)| {
+                let dz = gap(z as u32, 2);
+                for y in min[1]..=max[1] {
+                    let dy = gap(y, 1);
+                    for x in min[0]..=max[0] {
+                        let dx = gap(x, 0);
+                        let value = &mut slice[(y * self.size + x) as usize];
+                        if *value > 0 {
+                            *value = (*value).min(encode(dx * dx + dy * dy + dz * dz));
+                        }
+                    }
+                }
+            });
+
+        (min, [0, 1, 2].map(|axis| max[axis] - min[axis] + 1))
+    }
+
+    /// Values of a box as raw bytes, x fastest
+    pub(super) fn region(&self, origin: [u32; 3], extent: [u32; 3]) -> Vec<u8> {
+        let size = self.size as usize;
+        let mut bytes = Vec::with_capacity(extent.iter().map(|&e| e as usize).product());
+        for z in origin[2]..origin[2] + extent[2] {
+            for y in origin[1]..origin[1] + extent[1] {
+                let row = (z as usize * size + y as usize) * size;
+                let values = &self.values[row + origin[0] as usize..row + (origin[0] + extent[0]) as usize];
+                bytes.extend_from_slice(bytemuck::cast_slice(values));
+            }
+        }
+        bytes
+    }
+}
+
+/// Squared distance in stored units, rounded down so it never overstates
+fn encode(squared_distance: f32) -> i8 {
+    (squared_distance.sqrt().min(MAX_DISTANCE) * UNITS_PER_TEXEL) as i8
+}
+
+/// Squared distance from every texel center to the nearest seed texel center,
+/// one axis at a time. Rows along x are contiguous and transformed in place;
+/// columns along y and z are gathered, transformed and scattered back.
+fn squared_distances(size: u32, is_seed: impl Fn(usize) -> bool + Sync) -> Vec<f32> {
+    let n = size as usize;
+    let mut distances: Vec<f32> = (0..n * n * n).into_par_iter()
+        .map(|i| if is_seed(i) { 0.0 } else { FAR })
+        .collect();
+
+    distances.par_chunks_mut(n).for_each(|row| {
+        let line = row.to_vec();
+        transform_line(&line, row);
+    });
+
+    // Along y within each z slice
+    distances.par_chunks_mut(n * n).for_each(|slice| {
+        let mut line = vec![0.0; n];
+        let mut out = vec![0.0; n];
+        for x in 0..n {
+            for y in 0..n {
+                line[y] = slice[y * n + x];
+            }
+            transform_line(&line, &mut out);
+            for y in 0..n {
+                slice[y * n + x] = out[y];
+            }
+        }
+    });
+
+    // Along z, one y row of columns per task
+    let columns: Vec<Vec<f32>> = (0..n).into_par_iter()
+        .map(|y| {
+            let mut line = vec![0.0; n];
+            let mut out = vec![0.0; n * n];
+            for x in 0..n {
+                for z in 0..n {
+                    line[z] = distances[(z * n + y) * n + x];
+                }
+                transform_line(&line, &mut out[x * n..(x + 1) * n]);
+            }
+            out
+        })
+        .collect();
+    for (y, column) in columns.iter().enumerate() {
+        for x in 0..n {
+            for z in 0..n {
+                distances[(z * n + y) * n + x] = column[x * n + z];
+            }
+        }
+    }
+
+    distances
+}
+
+/// One-dimensional squared distance transform: the lower envelope of the
+/// parabolas `(q - p)² + f[p]`, sampled at every `q`
+fn transform_line(f: &[f32], out: &mut [f32]) {
+    let n = f.len();
+    let mut vertices = vec![0usize; n];
+    let mut bounds = vec![0.0f32; n + 1];
+    let mut k = 0;
+    bounds[0] = f32::NEG_INFINITY;
+    bounds[1] = f32::INFINITY;
+
+    let intersection = |p: usize, q: usize| {
+        let (p2, q2) = ((p * p) as f32, (q * q) as f32);
+        ((f[q] + q2) - (f[p] + p2)) / (2.0 * (q - p) as f32)
+    };
+    for q in 1..n {
+        let mut s = intersection(vertices[k], q);
+        while s <= bounds[k] {
+            k -= 1;
+            s = intersection(vertices[k], q);
+        }
+        k += 1;
+        vertices[k] = q;
+        bounds[k] = s;
+        bounds[k + 1] = f32::INFINITY;
+    }
+
+    k = 0;
+    for (q, out) in out.iter_mut().enumerate() {
+        while bounds[k + 1] < q as f32 {
+            k += 1;
+        }
+        let offset = q as f32 - vertices[k] as f32;
+        *out = offset * offset + f[vertices[k]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 12;
+
+    /// Texels occupied where `occupied` says so, x fastest
+    fn texels(size: u32, occupied: impl Fn([u32; 3]) -> bool) -> VolumeTexels {
+        let mut colors = Vec::new();
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    colors.extend_from_slice(&[200, 200, 200, if occupied([x, y, z]) { 255 } else { 0 }]);
+                }
+            }
+        }
+        let materials = vec![0; colors.len() / 4];
+        VolumeTexels { colors, materials }
+    }
+
+    fn texel_index(size: u32, [x, y, z]: [u32; 3]) -> usize {
+        ((z * size + y) * size + x) as usize
+    }
+
+    /// Squared distance from each texel to the nearest texel of the other kind, by brute force
+    fn brute_force(size: u32, occupied: &[bool]) -> Vec<f32> {
+        let texels: Vec<[u32; 3]> = (0..size * size * size).map(|i| [i % size, i / size % size, i / (size * size)]).collect();
+        texels.iter()
+            .map(|a| {
+                texels.iter()
+                    .filter(|b| occupied[texel_index(size, **b)] != occupied[texel_index(size, *a)])
+                    .map(|b| (0..3).map(|axis| (a[axis] as f32 - b[axis] as f32).powi(2)).sum::<f32>())
+                    .fold(FAR, f32::min)
+            })
+            .collect()
+    }
+
+    fn occupancy(texels: &VolumeTexels) -> Vec<bool> {
+        texels.colors.chunks_exact(4).map(|color| color[3] > 0).collect()
+    }
+
+    /// A sphere and a few scattered texels
+    fn scene([x, y, z]: [u32; 3]) -> bool {
+        let dx = x as f32 - 3.5;
+        let dy = y as f32 - 4.0;
+        let dz = z as f32 - 5.0;
+        dx * dx + dy * dy + dz * dz < 6.0 || [x, y, z] == [10, 1, 9] || [x, y, z] == [9, 10, 2]
+    }
+
+    #[test]
+    fn full_build_is_exact() {
+        let texels = texels(SIZE, scene);
+        let occupied = occupancy(&texels);
+        let field = DistanceField::from_texels(SIZE, &texels);
+
+        for (i, squared_distance) in brute_force(SIZE, &occupied).into_iter().enumerate() {
+            let expected = if occupied[i] { -encode(squared_distance) } else { encode(squared_distance) };
+            assert_eq!(field.values[i], expected, "texel {}", i);
+            assert_eq!(field.values[i] < 0, occupied[i], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn updates_keep_a_lower_bound() {
+        let before = texels(SIZE, scene);
+        let mut field = DistanceField::from_texels(SIZE, &before);
+        let values_before = field.values.clone();
+
+        // Fill a box in open space and carve into the sphere in one edit
+        let origin = [2, 2, 6];
+        let extent = [8, 3, 2];
+        let edited = |texel: [u32; 3]| {
+            let in_box = (0..3).all(|axis| (origin[axis]..origin[axis] + extent[axis]).contains(&texel[axis]));
+            if in_box { texel[0] >= 6 } else { scene(texel) }
+        };
+        let after = texels(SIZE, edited);
+        let mut region = VolumeTexels { colors: Vec::new(), materials: Vec::new() };
+        for z in origin[2]..origin[2] + extent[2] {
+            for y in origin[1]..origin[1] + extent[1] {
+                for x in origin[0]..origin[0] + extent[0] {
+                    let i = texel_index(SIZE, [x, y, z]);
+                    region.colors.extend_from_slice(&after.colors[i * 4..i * 4 + 4]);
+                    region.materials.push(0);
+                }
+            }
+        }
+        let (changed_origin, changed_extent) = field.update(origin, extent, &region);
+
+        let occupied = occupancy(&after);
+        let true_distances = brute_force(SIZE, &occupied);
+        for z in 0..SIZE {
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let i = texel_index(SIZE, [x, y, z]);
+                    let value = field.values[i];
+                    if occupied[i] {
+                        assert!(value < 0, "occupied texel {:?} holds {}", [x, y, z], value);
+                    } else {
+                        assert!(value >= 0, "empty texel {:?} holds {}", [x, y, z], value);
+                        assert!(value as f32 / UNITS_PER_TEXEL <= true_distances[i].sqrt(), "texel {:?} overstates", [x, y, z]);
+                    }
+
+                    let inside = (0..3).all(|axis| {
+                        let texel = [x, y, z][axis];
+                        (changed_origin[axis]..changed_origin[axis] + changed_extent[axis]).contains(&texel)
+                    });
+                    if !inside {
+                        assert_eq!(value, values_before[i], "texel {:?} changed outside the reported box", [x, y, z]);
+                    }
+                }
+            }
+        }
+
+        let region = field.region(changed_origin, changed_extent);
+        assert_eq!(region.len(), changed_extent.iter().product::<u32>() as usize);
+    }
+}
@@ -0,0 +1,617 @@
+use super::{GpuUpdate, Octree, OctreeNode, OctreeProvider, VoxelData, lod_for_distance};
+use super::linear::{GpuOctreeHeader, GpuOctreeNode, LinearOctree, leaf_material, pack_rgba8};
+use super::distance_field::DistanceField;
+use super::material::{Material, MaterialTable};
+use super::occupancy::OccupancyPyramid;
+use super::raycast::Hit;
+use super::static_provider::{self, GpuStorage, VolumeTexture};
+use nalgebra as na;
+use wgpu::*;
+use wgpu::util::DeviceExt;
+use log::info;
+
+/// Texel regions beyond this count are merged into their bounding box
+const MAX_DIRTY_REGIONS: usize = 64;
+
+/// Octree provider for scenes edited while rendering.
+/// Edits go straight into the CPU octree and are recorded as dirty texel boxes
+/// (dense texture and distance field) or dirty node slots (sparse buffer); `update_gpu_resources`
+/// then uploads only those sub-regions once per frame. Materials can be edited
+/// or added at runtime without touching the voxels that reference them.
+pub struct DynamicOctreeProvider {
+    octree: Octree,
+    base_voxel_size: f32,
+    storage: GpuStorage,
+    texture_size: u32,
+    volume: Option<VolumeTexture>,
+    /// CPU copy of the occupancy pyramid, refreshed around each dirty region
+    occupancy: Option<OccupancyPyramid>,
+    /// CPU copy of the distance field for `GpuStorage::DistanceField`, lowered
+    /// around each dirty region
+    distance_field: Option<DistanceField>,
+    dirty_regions: Vec<TexelRegion>,
+    material_buffer: Option<Buffer>,
+    /// The material table changed since it was last uploaded
+    materials_changed: bool,
+    /// CPU copy of the node buffer, only kept for `GpuStorage::SparseBuffer`
+    mirror: Option<NodeMirror>,
+    node_capacity: usize,
+    node_buffer: Option<Buffer>,
+    header_buffer: Option<Buffer>,
+}
+
+impl DynamicOctreeProvider {
+    pub fn new(octree: Octree, storage: GpuStorage) -> Self {
+        let mirror = (storage == GpuStorage::SparseBuffer).then(|| NodeMirror::new(&octree));
+
+        Self {
+            octree,
+            base_voxel_size: 0.02,
+            storage,
+            volume: None,
+            occupancy: None,
+            distance_field: None,
+            dirty_regions: Vec::new(),
+            material_buffer: None,
+            materials_changed: false,
+            mirror,
+            node_capacity: 0,
+            node_buffer: None,
+            header_buffer: None,
+            texture_size: 256,
+        }
+    }
+
+    pub fn octree(&self) -> &Octree {
+        &self.octree
+    }
+
+    /// Add a material voxels can reference from `update_voxel`; equal
+    /// materials share an index
+    pub fn add_material(&mut self, material: Material) -> u32 {
+        let count = self.octree.materials.len();
+        let index = self.octree.materials.insert(material);
+        self.materials_changed |= self.octree.materials.len() != count;
+        index
+    }
+
+    /// Change a material in place; every voxel using it updates on the next upload
+    pub fn set_material(&mut self, index: u32, material: Material) {
+        self.octree.materials.set(index, material);
+        self.materials_changed = true;
+    }
+
+    /// Upload the whole octree and the material table using the configured storage mode
+    pub fn create_gpu_resources(&mut self, device: &Device, queue: &Queue) {
+        self.material_buffer = Some(self.octree.materials.create_buffer(device));
+        self.materials_changed = false;
+
+        match self.storage {
+            GpuStorage::DenseTexture | GpuStorage::DistanceField => self.create_texture(device, queue),
+            GpuStorage::SparseBuffer => self.create_node_buffer(device, queue),
+        }
+    }
+
+    fn create_texture(&mut self, device: &Device, queue: &Queue) {
+        let size = self.texture_size;
+        let texels = static_provider::bake_texels(&self.octree, size, [0; 3], [size; 3]);
+        let volume = VolumeTexture::new(device, size, self.storage);
+        volume.write(queue, [0; 3], [size; 3], &texels);
+
+        self.distance_field = None;
+        self.occupancy = None;
+        match self.storage {
+            GpuStorage::DistanceField => {
+                let field = DistanceField::from_texels(size, &texels);
+                volume.write_distance_field(queue, &field, [0; 3], [size; 3]);
+                self.distance_field = Some(field);
+            }
+            _ => {
+                let occupancy = OccupancyPyramid::from_texels(size, &texels);
+                volume.write_pyramid(queue, &occupancy);
+                self.occupancy = Some(occupancy);
+            }
+        }
+        self.volume = Some(volume);
+        self.dirty_regions.clear();
+
+        info!("Created {}x{}x{} dynamic 3D texture for octree", size, size, size);
+    }
+
+    /// Allocate the node buffer with headroom so that edits which split nodes
+    /// can grow the tree in place for a while before it has to be recreated
+    fn create_node_buffer(&mut self, device: &Device, queue: &Queue) {
+        let mirror = self.mirror.get_or_insert_with(|| NodeMirror::new(&self.octree));
+        let nodes = &mirror.nodes;
+        self.node_capacity = (nodes.len() * 2).max(4096);
+
+        let node_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Dynamic Octree Node Buffer"),
+            size: (self.node_capacity * std::mem::size_of::<GpuOctreeNode>()) as BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&node_buffer, 0, bytemuck::cast_slice(nodes));
+
+        let header_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Dynamic Octree Header Buffer"),
+            contents: bytemuck::cast_slice(&[mirror.header]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        info!("Created dynamic sparse octree buffer: {} of {} nodes used", nodes.len(), self.node_capacity);
+
+        mirror.dirty.clear();
+        self.node_buffer = Some(node_buffer);
+        self.header_buffer = Some(header_buffer);
+    }
+
+    /// Mark the texels that sample the finest cell containing `position`.
+    /// The box is widened by a texel on each side so boundary samples are covered.
+    fn mark_texels(&mut self, position: &na::Vector3<f32>) {
+        let root = &self.octree.root;
+        let cell_size = root.half_size * 2.0 / (1u32 << self.octree.max_depth) as f32;
+        let root_min = root.center.add_scalar(-root.half_size);
+        let cell_min = root_min + ((position - root_min) / cell_size).map(f32::floor) * cell_size;
+        let cell_max = cell_min.add_scalar(cell_size);
+
+        // Inverse of the world mapping used by `bake_texels`
+        let size = self.texture_size;
+        let texel_size = root.half_size * 2.0 / size as f32;
+        let texel = |world: f32, axis: usize| (world - root_min[axis]) / texel_size;
+        let clamp = |value: f32| (value as i64).clamp(0, size as i64 - 1) as u32;
+        let region = TexelRegion {
+            min: [0, 1, 2].map(|axis| clamp(texel(cell_min[axis], axis).floor())),
+            max: [0, 1, 2].map(|axis| clamp(texel(cell_max[axis], axis).ceil())),
+        };
+
+        self.add_dirty_region(region);
+    }
+
+    fn add_dirty_region(&mut self, mut region: TexelRegion) {
+        // Absorb every region the new one touches, so the list stays disjoint
+        self.dirty_regions.retain(|existing| {
+            if region.touches(existing) {
+                region.merge(existing);
+                false
+            } else {
+                true
+            }
+        });
+        self.dirty_regions.push(region);
+
+        if self.dirty_regions.len() > MAX_DIRTY_REGIONS {
+            let mut bounds = self.dirty_regions[0];
+            for region in &self.dirty_regions[1..] {
+                bounds.merge(region);
+            }
+            self.dirty_regions = vec![bounds];
+        }
+    }
+
+    fn upload_texels(&mut self, queue: &Queue) -> GpuUpdate {
+        let Some(volume) = &self.volume else {
+            return GpuUpdate::Unchanged;
+        };
+        if self.dirty_regions.is_empty() {
+            return GpuUpdate::Unchanged;
+        }
+
+        for region in self.dirty_regions.drain(..) {
+            let extent = region.extent();
+            let texels = static_provider::bake_texels(&self.octree, self.texture_size, region.min, extent);
+            volume.write(queue, region.min, extent, &texels);
+            if let Some(ref mut occupancy) = self.occupancy {
+                for (level, (origin, extent)) in occupancy.update(region.min, extent, &texels).into_iter().enumerate() {
+                    volume.write_occupancy(queue, occupancy, level as u32, origin, extent);
+                }
+            }
+            if let Some(ref mut field) = self.distance_field {
+                let (origin, extent) = field.update(region.min, extent, &texels);
+                volume.write_distance_field(queue, field, origin, extent);
+            }
+        }
+        GpuUpdate::ContentChanged
+    }
+
+    /// Write dirty node slots, coalescing nearby slots into one copy. The
+    /// buffer is recreated when the tree outgrew it.
+    fn upload_nodes(&mut self, device: &Device, queue: &Queue) -> GpuUpdate {
+        let (Some(mirror), Some(node_buffer)) = (&mut self.mirror, &self.node_buffer) else {
+            return GpuUpdate::Unchanged;
+        };
+        if mirror.dirty.is_empty() {
+            return GpuUpdate::Unchanged;
+        }
+
+        if mirror.nodes.len() > self.node_capacity {
+            self.create_node_buffer(device, queue);
+            return GpuUpdate::Recreated;
+        }
+
+        let node_size = std::mem::size_of::<GpuOctreeNode>();
+        for (first, last) in mirror.take_dirty_ranges() {
+            queue.write_buffer(
+                node_buffer,
+                (first * node_size) as BufferAddress,
+                bytemuck::cast_slice(&mirror.nodes[first..=last]),
+            );
+        }
+
+        queue.write_buffer(self.header_buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(&[mirror.header]));
+        GpuUpdate::ContentChanged
+    }
+}
+
+impl OctreeProvider for DynamicOctreeProvider {
+    fn sample_voxel(&self, position: na::Vector3<f32>, distance_from_camera: f32) -> VoxelData {
+        let lod_level = lod_for_distance(distance_from_camera);
+        self.octree.sample(position, lod_level.min(self.octree.max_depth))
+    }
+
+    fn set_performance_target(&mut self, target_voxel_size: f32) {
+        self.base_voxel_size = target_voxel_size;
+    }
+
+    fn get_bounds(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let center = self.octree.root.center;
+        let half = self.octree.root.half_size;
+        (
+            center - na::Vector3::new(half, half, half),
+            center + na::Vector3::new(half, half, half),
+        )
+    }
+
+    fn get_grid_dims(&self) -> na::Vector3<u32> {
+        match self.storage {
+            GpuStorage::DenseTexture | GpuStorage::DistanceField => na::Vector3::repeat(self.texture_size),
+            GpuStorage::SparseBuffer => na::Vector3::repeat(1 << self.octree.max_depth),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        true
+    }
+
+    fn update_voxel(&mut self, position: na::Vector3<f32>, data: VoxelData) -> Result<(), String> {
+        if !self.octree.root.contains(&position) {
+            return Err(format!("position ({}, {}, {}) is outside the octree", position.x, position.y, position.z));
+        }
+
+        if data.density > 0.0 {
+            self.octree.insert(position, data);
+        } else {
+            self.octree.remove(position);
+        }
+
+        match self.mirror {
+            Some(ref mut mirror) => mirror.sync_path(&self.octree.root, 0, &position, &self.octree.materials),
+            None => self.mark_texels(&position),
+        }
+        Ok(())
+    }
+
+    fn raycast(&self, origin: na::Vector3<f32>, direction: na::Vector3<f32>, max_t: f32) -> Option<Hit> {
+        self.octree.raycast(origin, direction, max_t)
+    }
+
+    fn bind_gpu_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+        let material_buffer = self.material_buffer.as_ref().unwrap();
+        match self.storage {
+            GpuStorage::DenseTexture | GpuStorage::DistanceField => self.volume.as_ref().unwrap().bind(device, material_buffer),
+            GpuStorage::SparseBuffer => static_provider::bind_node_buffer(
+                device,
+                self.node_buffer.as_ref().unwrap(),
+                self.header_buffer.as_ref().unwrap(),
+                material_buffer,
+            ),
+        }
+    }
+
+    fn shader_source(&self) -> &'static str {
+        self.storage.shader_source()
+    }
+
+    fn update_gpu_resources(&mut self, device: &Device, queue: &Queue) -> GpuUpdate {
+        // Edits fit the existing buffer; a grown table needs a new one and a re-bind
+        let mut materials = GpuUpdate::Unchanged;
+        if std::mem::take(&mut self.materials_changed)
+            && let Some(ref buffer) = self.material_buffer
+        {
+            materials = if self.octree.materials.write_buffer(queue, buffer) {
+                GpuUpdate::ContentChanged
+            } else {
+                self.material_buffer = Some(self.octree.materials.create_buffer(device));
+                GpuUpdate::Recreated
+            };
+        }
+
+        let voxels = match self.storage {
+            GpuStorage::DenseTexture | GpuStorage::DistanceField => self.upload_texels(queue),
+            GpuStorage::SparseBuffer => self.upload_nodes(device, queue),
+        };
+        voxels.max(materials)
+    }
+}
+
+/// Inclusive box of texels awaiting upload
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct TexelRegion {
+    min: [u32; 3],
+    max: [u32; 3],
+}
+
+impl TexelRegion {
+    /// True when the boxes overlap or share a face
+    fn touches(&self, other: &TexelRegion) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] + 1 && other.min[axis] <= self.max[axis] + 1)
+    }
+
+    fn merge(&mut self, other: &TexelRegion) {
+        for axis in 0..3 {
+            self.min[axis] = self.min[axis].min(other.min[axis]);
+            self.max[axis] = self.max[axis].max(other.max[axis]);
+        }
+    }
+
+    fn extent(&self) -> [u32; 3] {
+        [0, 1, 2].map(|axis| self.max[axis] - self.min[axis] + 1)
+    }
+}
+
+/// CPU copy of the GPU node buffer in the `LinearOctree` layout, kept in sync
+/// with the octree one edit path at a time. Child blocks released by collapsed
+/// nodes are recycled, so edits never move existing nodes.
+struct NodeMirror {
+    header: GpuOctreeHeader,
+    nodes: Vec<GpuOctreeNode>,
+    free_blocks: Vec<u32>,
+    dirty: Vec<u32>,
+}
+
+impl NodeMirror {
+    fn new(octree: &Octree) -> Self {
+        let linear = LinearOctree::from_octree(octree);
+        Self {
+            header: linear.header,
+            nodes: linear.nodes,
+            free_blocks: Vec::new(),
+            dirty: Vec::new(),
+        }
+    }
+
+    /// Drain the dirty slots as inclusive ranges of slots to copy, coalescing nearby slots
+    fn take_dirty_ranges(&mut self) -> Vec<(usize, usize)> {
+        let mut dirty = std::mem::take(&mut self.dirty);
+        dirty.sort_unstable();
+        dirty.dedup();
+
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        while start < dirty.len() {
+            // Gaps of up to one child block are cheaper to re-send than to split on
+            let mut end = start;
+            while end + 1 < dirty.len() && dirty[end + 1] - dirty[end] <= 8 {
+                end += 1;
+            }
+            ranges.push((dirty[start] as usize, dirty[end] as usize));
+            start = end + 1;
+        }
+        ranges
+    }
+
+    /// Re-sync the slots on the path from `node` down to `position`. Only that
+    /// path can change on a single-voxel edit, apart from child blocks created
+    /// by splits (written in full) and released by collapses.
+    fn sync_path(&mut self, node: &OctreeNode, slot: u32, position: &na::Vector3<f32>, materials: &MaterialTable) {
+        self.set_payload(slot, node.voxel_data.as_ref().map_or(0, pack_rgba8), leaf_material(node, materials));
+
+        match (&node.children, self.nodes[slot as usize].first_child) {
+            (Some(children), 0) => {
+                let block = self.write_children(children, materials);
+                self.set_first_child(slot, block);
+            }
+            (Some(children), first_child) => {
+                let index = node.get_child_index(position);
+                self.sync_path(&children[index], first_child + index as u32, position, materials);
+            }
+            (None, 0) => {}
+            (None, first_child) => {
+                self.free_block(first_child);
+                self.set_first_child(slot, 0);
+            }
+        }
+    }
+
+    fn write_subtree(&mut self, node: &OctreeNode, slot: u32, materials: &MaterialTable) {
+        self.set_payload(slot, node.voxel_data.as_ref().map_or(0, pack_rgba8), leaf_material(node, materials));
+        let first_child = match node.children {
+            Some(ref children) => self.write_children(children, materials),
+            None => 0,
+        };
+        self.set_first_child(slot, first_child);
+    }
+
+    fn write_children(&mut self, children: &[OctreeNode; 8], materials: &MaterialTable) -> u32 {
+        let block = self.allocate_block();
+        for (i, child) in children.iter().enumerate() {
+            self.write_subtree(child, block + i as u32, materials);
+        }
+        block
+    }
+
+    fn allocate_block(&mut self) -> u32 {
+        if let Some(block) = self.free_blocks.pop() {
+            return block;
+        }
+        let block = self.nodes.len() as u32;
+        self.nodes.resize(self.nodes.len() + 8, GpuOctreeNode::default());
+        self.header.node_count = self.nodes.len() as u32;
+        block
+    }
+
+    fn free_block(&mut self, block: u32) {
+        for slot in block..block + 8 {
+            let first_child = self.nodes[slot as usize].first_child;
+            if first_child != 0 {
+                self.free_block(first_child);
+            }
+        }
+        self.free_blocks.push(block);
+    }
+
+    fn set_payload(&mut self, slot: u32, payload: u32, material: u32) {
+        let node = &mut self.nodes[slot as usize];
+        if node.payload != payload || node.material != material {
+            node.payload = payload;
+            node.material = material;
+            self.dirty.push(slot);
+        }
+    }
+
+    fn set_first_child(&mut self, slot: u32, first_child: u32) {
+        let node = &mut self.nodes[slot as usize];
+        if node.first_child != first_child {
+            node.first_child = first_child;
+            self.dirty.push(slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Depth 3 octree over the cube -1..1 with a floor slab and a 2³ block
+    /// that collapses into one leaf
+    fn scene() -> Octree {
+        let mut octree = Octree::new(na::Vector3::zeros(), 1.0, 3);
+        for x in 0..8 {
+            for z in 0..8 {
+                octree.insert(cell(x, 0, z), VoxelData::solid([0.5, 0.5, 0.5]));
+            }
+        }
+        for i in 0..8 {
+            octree.insert(cell(4 + (i & 1), 4 + (i >> 1 & 1), 4 + (i >> 2)), VoxelData::solid([1.0, 0.0, 0.0]));
+        }
+        octree
+    }
+
+    /// Center of depth 3 cell (x, y, z)
+    fn cell(x: usize, y: usize, z: usize) -> na::Vector3<f32> {
+        na::Vector3::new(x, y, z).map(|i| i as f32 * 0.25 - 0.875)
+    }
+
+    /// Edits that split the collapsed block, grow new subtrees, collapse them
+    /// again and clear part of the floor
+    fn edit(provider: &mut DynamicOctreeProvider) {
+        let green = VoxelData::solid([0.0, 1.0, 0.0]);
+        provider.update_voxel(cell(5, 5, 5), VoxelData::empty()).unwrap();
+        provider.update_voxel(cell(1, 6, 2), green).unwrap();
+        for i in 0..8 {
+            provider.update_voxel(cell(i & 1, 2 + (i >> 1 & 1), 6 + (i >> 2)), green).unwrap();
+        }
+        provider.update_voxel(cell(0, 2, 6), VoxelData::empty()).unwrap();
+        for x in 2..6 {
+            provider.update_voxel(cell(x, 0, 3), VoxelData::empty()).unwrap();
+        }
+        assert!(provider.update_voxel(na::Vector3::new(0.0, 2.0, 0.0), green).is_err());
+    }
+
+    /// Walk two node buffers from their roots and compare the trees they encode
+    fn assert_same_tree(a: &[GpuOctreeNode], a_slot: u32, b: &[GpuOctreeNode], b_slot: u32) {
+        let (a_node, b_node) = (a[a_slot as usize], b[b_slot as usize]);
+        assert_eq!((a_node.payload, a_node.material), (b_node.payload, b_node.material), "slots {} and {}", a_slot, b_slot);
+        assert_eq!(a_node.first_child == 0, b_node.first_child == 0, "slots {} and {}", a_slot, b_slot);
+        if a_node.first_child != 0 {
+            for i in 0..8 {
+                assert_same_tree(a, a_node.first_child + i, b, b_node.first_child + i);
+            }
+        }
+    }
+
+    #[test]
+    fn texel_regions_touch_across_faces_and_corners() {
+        let region = TexelRegion { min: [2, 2, 2], max: [3, 3, 3] };
+        assert!(region.touches(&TexelRegion { min: [4, 2, 2], max: [5, 3, 3] }));
+        assert!(region.touches(&TexelRegion { min: [4, 4, 4], max: [4, 4, 4] }));
+        assert!(region.touches(&TexelRegion { min: [0, 0, 0], max: [1, 1, 1] }));
+        assert!(!region.touches(&TexelRegion { min: [5, 2, 2], max: [6, 3, 3] }));
+        assert!(!region.touches(&TexelRegion { min: [2, 2, 0], max: [3, 3, 0] }));
+
+        let mut merged = region;
+        merged.merge(&TexelRegion { min: [0, 4, 3], max: [1, 6, 3] });
+        assert_eq!(merged, TexelRegion { min: [0, 2, 2], max: [3, 6, 3] });
+        assert_eq!(merged.extent(), [4, 5, 2]);
+    }
+
+    #[test]
+    fn dirty_regions_coalesce() {
+        let mut provider = DynamicOctreeProvider::new(scene(), GpuStorage::DenseTexture);
+        let a = TexelRegion { min: [0, 0, 0], max: [3, 3, 3] };
+        let b = TexelRegion { min: [6, 6, 6], max: [7, 7, 7] };
+        provider.add_dirty_region(a);
+        provider.add_dirty_region(b);
+        provider.add_dirty_region(a);
+        assert_eq!(provider.dirty_regions, vec![b, a]);
+
+        // A region touching both absorbs them
+        provider.add_dirty_region(TexelRegion { min: [4, 4, 4], max: [5, 5, 5] });
+        assert_eq!(provider.dirty_regions, vec![TexelRegion { min: [0, 0, 0], max: [7, 7, 7] }]);
+
+        // Past the limit everything collapses into the bounding box
+        for i in 0..MAX_DIRTY_REGIONS as u32 {
+            provider.add_dirty_region(TexelRegion { min: [10 + i * 2, 0, 0], max: [10 + i * 2, 0, 0] });
+        }
+        assert_eq!(provider.dirty_regions, vec![TexelRegion { min: [0, 0, 0], max: [8 + MAX_DIRTY_REGIONS as u32 * 2, 7, 7] }]);
+    }
+
+    #[test]
+    fn dirty_texels_match_a_full_bake() {
+        let mut provider = DynamicOctreeProvider::new(scene(), GpuStorage::DenseTexture);
+        // Texels smaller than cells, so edits must cover every texel of a cell
+        provider.texture_size = 16;
+        let size = provider.texture_size;
+        let mut texels = static_provider::bake_texels(provider.octree(), size, [0; 3], [size; 3]);
+
+        edit(&mut provider);
+        assert!(!provider.dirty_regions.is_empty());
+        for region in &provider.dirty_regions {
+            let extent = region.extent();
+            let update = static_provider::bake_texels(provider.octree(), size, region.min, extent);
+            for z in 0..extent[2] {
+                for y in 0..extent[1] {
+                    for x in 0..extent[0] {
+                        let from = ((z * extent[1] + y) * extent[0] + x) as usize;
+                        let to = (((region.min[2] + z) * size + region.min[1] + y) * size + region.min[0] + x) as usize;
+                        texels.colors[to * 4..to * 4 + 4].copy_from_slice(&update.colors[from * 4..from * 4 + 4]);
+                        texels.materials[to] = update.materials[from];
+                    }
+                }
+            }
+        }
+
+        let expected = static_provider::bake_texels(provider.octree(), size, [0; 3], [size; 3]);
+        assert!(texels.colors == expected.colors);
+        assert!(texels.materials == expected.materials);
+    }
+
+    #[test]
+    fn dirty_nodes_match_a_rebuilt_tree() {
+        let mut provider = DynamicOctreeProvider::new(scene(), GpuStorage::SparseBuffer);
+        let mut buffer = provider.mirror.as_ref().unwrap().nodes.clone();
+
+        edit(&mut provider);
+        // Apply only the copies `upload_nodes` would make
+        let mirror = provider.mirror.as_mut().unwrap();
+        buffer.resize(mirror.nodes.len(), GpuOctreeNode::default());
+        for (first, last) in mirror.take_dirty_ranges() {
+            buffer[first..=last].copy_from_slice(&mirror.nodes[first..=last]);
+        }
+        assert!(mirror.dirty.is_empty());
+        assert_eq!(mirror.header.node_count as usize, buffer.len());
+
+        let rebuilt = LinearOctree::from_octree(provider.octree());
+        assert_same_tree(&buffer, 0, &rebuilt.nodes, 0);
+    }
+}
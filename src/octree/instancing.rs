@@ -0,0 +1,529 @@
+use super::{GpuUpdate, Octree, OctreeProvider, VoxelData, lod_for_distance};
+use super::linear::{GpuOctreeNode, LinearOctree};
+use super::material::{self, MaterialTable};
+use bytemuck::{Pod, Zeroable};
+use nalgebra as na;
+use wgpu::*;
+use wgpu::util::DeviceExt;
+use log::info;
+
+/// Instances per BVH leaf
+const BVH_LEAF_SIZE: usize = 2;
+
+/// Placement of an instance: object space is scaled, then rotated about its
+/// origin, then translated. Scale is uniform so distances map between the two
+/// spaces by a single factor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: na::Vector3<f32>,
+    pub rotation: na::Rotation3<f32>,
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: na::Vector3::zeros(),
+            rotation: na::Rotation3::identity(),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn to_world(&self, position: &na::Vector3<f32>) -> na::Vector3<f32> {
+        self.rotation * position * self.scale + self.translation
+    }
+
+    pub fn to_object(&self, position: &na::Vector3<f32>) -> na::Vector3<f32> {
+        self.rotation.inverse_transform_vector(&(position - self.translation)) / self.scale
+    }
+
+    /// World-space box around the object-space cube `center ± half_size`
+    pub fn world_bounds(&self, center: &na::Vector3<f32>, half_size: f32) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let world_center = self.to_world(center);
+        let extent = self.rotation.matrix().abs() * na::Vector3::repeat(half_size * self.scale);
+        (world_center - extent, world_center + extent)
+    }
+}
+
+/// One placement of a shared asset
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VoxelInstance {
+    /// Index into `InstancedScene::assets`
+    pub asset: usize,
+    pub transform: Transform,
+}
+
+/// Top-level scene of voxel models placed any number of times. Each asset is
+/// stored once in its own space; instances only add a transform.
+#[derive(Clone, Default)]
+pub struct InstancedScene {
+    pub assets: Vec<Octree>,
+    pub instances: Vec<VoxelInstance>,
+}
+
+impl InstancedScene {
+    /// The octree as a single asset placed where it was built
+    pub fn from_octree(octree: Octree) -> Self {
+        Self {
+            assets: vec![octree],
+            instances: vec![VoxelInstance { asset: 0, transform: Transform::default() }],
+        }
+    }
+
+    /// World-space box around the asset's root cube as placed by the instance
+    pub fn instance_bounds(&self, instance: &VoxelInstance) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let root = &self.assets[instance.asset].root;
+        instance.transform.world_bounds(&root.center, root.half_size)
+    }
+}
+
+/// Node of the instance BVH as laid out in the GPU storage buffer. Interior
+/// nodes have `count == 0` and their two children at `first` and `first + 1`;
+/// leaves hold instances `first..first + count` of the reordered instance list.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct GpuBvhNode {
+    pub bounds_min: [f32; 3],
+    pub first: u32,
+    pub bounds_max: [f32; 3],
+    pub count: u32,
+}
+
+/// Bounding volume hierarchy over instance boxes, split at the median along
+/// the longest axis of the box centers. The tree shape only depends on the
+/// instance count, so moving instances never changes the node count.
+pub struct InstanceBvh {
+    pub nodes: Vec<GpuBvhNode>,
+    /// Instance indices in leaf order
+    pub order: Vec<u32>,
+}
+
+impl InstanceBvh {
+    pub fn build(boxes: &[(na::Vector3<f32>, na::Vector3<f32>)]) -> Self {
+        let mut bvh = Self {
+            nodes: vec![GpuBvhNode::default()],
+            order: Vec::with_capacity(boxes.len()),
+        };
+        let mut indices: Vec<u32> = (0..boxes.len() as u32).collect();
+        bvh.build_node(0, &mut indices, boxes);
+        bvh
+    }
+
+    fn build_node(&mut self, slot: usize, indices: &mut [u32], boxes: &[(na::Vector3<f32>, na::Vector3<f32>)]) {
+        // An empty tree gets an inverted root box that no ray or point enters
+        let (min, max) = indices.iter()
+            .map(|&i| boxes[i as usize])
+            .fold((na::Vector3::repeat(f32::MAX), na::Vector3::repeat(f32::MIN)), |(min, max), (lo, hi)| {
+                (min.inf(&lo), max.sup(&hi))
+            });
+        self.nodes[slot].bounds_min = min.into();
+        self.nodes[slot].bounds_max = max.into();
+
+        if indices.len() <= BVH_LEAF_SIZE {
+            self.nodes[slot].first = self.order.len() as u32;
+            self.nodes[slot].count = indices.len() as u32;
+            self.order.extend_from_slice(indices);
+            return;
+        }
+
+        let center = |i: u32| (boxes[i as usize].0 + boxes[i as usize].1) * 0.5;
+        let (center_min, center_max) = indices.iter()
+            .fold((na::Vector3::repeat(f32::MAX), na::Vector3::repeat(f32::MIN)), |(min, max), &i| {
+                (min.inf(&center(i)), max.sup(&center(i)))
+            });
+        let axis = (center_max - center_min).imax();
+        let middle = indices.len() / 2;
+        indices.select_nth_unstable_by(middle, |&a, &b| center(a)[axis].total_cmp(&center(b)[axis]));
+
+        let first = self.nodes.len();
+        self.nodes[slot].first = first as u32;
+        self.nodes.resize(first + 2, GpuBvhNode::default());
+        let (left, right) = indices.split_at_mut(middle);
+        self.build_node(first, left, boxes);
+        self.build_node(first + 1, right, boxes);
+    }
+
+    /// Instances whose boxes overlap `min..max`; a point query passes the point twice
+    pub fn instances_in(&self, min: &na::Vector3<f32>, max: &na::Vector3<f32>) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = vec![0usize];
+        while let Some(slot) = stack.pop() {
+            let node = &self.nodes[slot];
+            let overlaps = (0..3).all(|axis| node.bounds_min[axis] <= max[axis] && min[axis] <= node.bounds_max[axis]);
+            if !overlaps {
+                continue;
+            }
+            if node.count == 0 {
+                stack.extend([node.first as usize, node.first as usize + 1]);
+            } else {
+                let leaf = &self.order[node.first as usize..(node.first + node.count) as usize];
+                found.extend(leaf.iter().map(|&i| i as usize));
+            }
+        }
+        found
+    }
+}
+
+/// Where an asset's linearized octree sits in the shared node buffer, for `instanced.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuAssetHeader {
+    pub center: [f32; 3],
+    pub half_size: f32,
+    pub max_depth: u32,
+    /// Slot of the asset's root; child references are relative to it
+    pub first_node: u32,
+    pub _padding: [u32; 2],
+}
+
+/// Instance as laid out for `instanced.wgsl`, in BVH leaf order
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuInstance {
+    /// Object-to-world rotation, one padded column per row
+    pub rotation: [[f32; 4]; 3],
+    pub translation: [f32; 3],
+    pub scale: f32,
+    pub bounds_min: [f32; 3],
+    pub asset: u32,
+    pub bounds_max: [f32; 3],
+    pub _padding: u32,
+}
+
+/// Octree provider for instanced scenes. Every asset is uploaded once as a
+/// linearized octree; the shader walks the instance BVH and looks positions up
+/// in object space, so instances can be moved with `set_transform` without
+/// touching the voxel data. Materials of all assets are merged into one table.
+pub struct InstancedProvider {
+    scene: InstancedScene,
+    materials: MaterialTable,
+    /// Per asset, the merged index of each entry of the asset's own table
+    material_remaps: Vec<Vec<u32>>,
+    bounds: Vec<(na::Vector3<f32>, na::Vector3<f32>)>,
+    bvh: InstanceBvh,
+    base_voxel_size: f32,
+    /// Transforms changed since the instances were last uploaded
+    transforms_changed: bool,
+    node_buffer: Option<Buffer>,
+    asset_buffer: Option<Buffer>,
+    instance_buffer: Option<Buffer>,
+    bvh_buffer: Option<Buffer>,
+    material_buffer: Option<Buffer>,
+}
+
+impl InstancedProvider {
+    pub fn new(scene: InstancedScene) -> Self {
+        let mut materials = MaterialTable::default();
+        let material_remaps = scene.assets.iter()
+            .map(|asset| asset.materials.materials().iter().map(|m| materials.insert(*m)).collect())
+            .collect();
+
+        let bounds: Vec<_> = scene.instances.iter().map(|instance| scene.instance_bounds(instance)).collect();
+        let bvh = InstanceBvh::build(&bounds);
+        info!("Instanced scene: {} assets, {} instances, {} BVH nodes",
+              scene.assets.len(), scene.instances.len(), bvh.nodes.len());
+
+        Self {
+            scene,
+            materials,
+            material_remaps,
+            bounds,
+            bvh,
+            base_voxel_size: 0.02,
+            transforms_changed: false,
+            node_buffer: None,
+            asset_buffer: None,
+            instance_buffer: None,
+            bvh_buffer: None,
+            material_buffer: None,
+        }
+    }
+
+    pub fn scene(&self) -> &InstancedScene {
+        &self.scene
+    }
+
+    pub fn bvh(&self) -> &InstanceBvh {
+        &self.bvh
+    }
+
+    /// Move an instance and rebuild the BVH; the GPU copy follows on the next
+    /// `update_gpu_resources`
+    pub fn set_transform(&mut self, instance: usize, transform: Transform) {
+        self.scene.instances[instance].transform = transform;
+        self.bounds[instance] = self.scene.instance_bounds(&self.scene.instances[instance]);
+        self.bvh = InstanceBvh::build(&self.bounds);
+        self.transforms_changed = true;
+    }
+
+    /// Upload the assets, instances, BVH and merged material table
+    pub fn create_gpu_resources(&mut self, device: &Device) {
+        let mut nodes = Vec::new();
+        let mut headers = Vec::new();
+        for (asset, remap) in self.scene.assets.iter().zip(&self.material_remaps) {
+            let linear = LinearOctree::from_octree(asset);
+            headers.push(GpuAssetHeader {
+                center: linear.header.center,
+                half_size: linear.header.half_size,
+                max_depth: linear.header.max_depth,
+                first_node: nodes.len() as u32,
+                _padding: [0; 2],
+            });
+            nodes.extend(linear.nodes.into_iter().map(|node| GpuOctreeNode {
+                material: remap.get(node.material as usize).copied().unwrap_or(0),
+                ..node
+            }));
+        }
+
+        // Storage bindings cannot be empty
+        if nodes.is_empty() {
+            nodes.push(GpuOctreeNode::default());
+        }
+        if headers.is_empty() {
+            headers.push(GpuAssetHeader::zeroed());
+        }
+
+        self.node_buffer = Some(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Instanced Asset Node Buffer"),
+            contents: bytemuck::cast_slice(&nodes),
+            usage: BufferUsages::STORAGE,
+        }));
+        self.asset_buffer = Some(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Instanced Asset Header Buffer"),
+            contents: bytemuck::cast_slice(&headers),
+            usage: BufferUsages::STORAGE,
+        }));
+        self.material_buffer = Some(self.materials.create_buffer(device));
+        self.create_instance_buffers(device);
+        self.transforms_changed = false;
+
+        info!("Uploaded {} asset nodes ({:.1} MB) for {} instances",
+              nodes.len(), (nodes.len() * std::mem::size_of::<GpuOctreeNode>()) as f64 / 1e6,
+              self.scene.instances.len());
+    }
+
+    fn gpu_instances(&self) -> Vec<GpuInstance> {
+        let mut instances: Vec<GpuInstance> = self.bvh.order.iter().map(|&i| {
+            let instance = &self.scene.instances[i as usize];
+            let rotation = instance.transform.rotation.matrix();
+            let (bounds_min, bounds_max) = self.bounds[i as usize];
+            GpuInstance {
+                rotation: [0, 1, 2].map(|column| [rotation[(0, column)], rotation[(1, column)], rotation[(2, column)], 0.0]),
+                translation: instance.transform.translation.into(),
+                scale: instance.transform.scale,
+                bounds_min: bounds_min.into(),
+                asset: instance.asset as u32,
+                bounds_max: bounds_max.into(),
+                _padding: 0,
+            }
+        }).collect();
+
+        // Storage bindings cannot be empty
+        if instances.is_empty() {
+            instances.push(GpuInstance::zeroed());
+        }
+        instances
+    }
+
+    fn create_instance_buffers(&mut self, device: &Device) {
+        self.instance_buffer = Some(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Instance Buffer"),
+            contents: bytemuck::cast_slice(&self.gpu_instances()),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        }));
+        self.bvh_buffer = Some(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Instance BVH Buffer"),
+            contents: bytemuck::cast_slice(&self.bvh.nodes),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        }));
+    }
+}
+
+impl OctreeProvider for InstancedProvider {
+    /// Sample the first instance with an occupied voxel at `position`
+    fn sample_voxel(&self, position: na::Vector3<f32>, distance_from_camera: f32) -> VoxelData {
+        let lod_level = lod_for_distance(distance_from_camera);
+
+        for index in self.bvh.instances_in(&position, &position) {
+            let instance = &self.scene.instances[index];
+            let asset = &self.scene.assets[instance.asset];
+            let local = instance.transform.to_object(&position);
+            if !asset.root.contains(&local) {
+                continue;
+            }
+
+            let mut data = asset.sample(local, lod_level.min(asset.max_depth));
+            if data.density > 0.0 {
+                data.material = self.material_remaps[instance.asset][asset.materials.lookup(&data) as usize];
+                return data;
+            }
+        }
+        VoxelData::empty()
+    }
+
+    fn set_performance_target(&mut self, target_voxel_size: f32) {
+        self.base_voxel_size = target_voxel_size;
+    }
+
+    fn get_bounds(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        if self.bounds.is_empty() {
+            return (na::Vector3::zeros(), na::Vector3::zeros());
+        }
+        let root = &self.bvh.nodes[0];
+        (root.bounds_min.into(), root.bounds_max.into())
+    }
+
+    /// Resolution of the finest placed asset across the whole bounds
+    fn get_grid_dims(&self) -> na::Vector3<u32> {
+        let finest = self.scene.instances.iter()
+            .map(|instance| {
+                let asset = &self.scene.assets[instance.asset];
+                asset.root.half_size * 2.0 / (1u32 << asset.max_depth) as f32 * instance.transform.scale
+            })
+            .fold(f32::INFINITY, f32::min);
+        let (min, max) = self.get_bounds();
+        if !finest.is_finite() {
+            return na::Vector3::repeat(1);
+        }
+        (max - min).map(|extent| ((extent / finest).ceil() as u32).max(1))
+    }
+
+    fn bind_gpu_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Instanced Bind Group Layout"),
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                storage_entry(2),
+                storage_entry(3),
+                material::material_layout_entry(),
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Instanced Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: self.node_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.asset_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: self.instance_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: self.bvh_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                material::material_bind_entry(self.material_buffer.as_ref().unwrap()),
+            ],
+        });
+
+        (bind_group_layout, bind_group)
+    }
+
+    fn shader_source(&self) -> &'static str {
+        include_str!("../shaders/instanced.wgsl")
+    }
+
+    /// Rewrite the instance and BVH buffers after instances moved. Reports the
+    /// resources as recreated so the renderer picks up the new scene bounds.
+    fn update_gpu_resources(&mut self, _device: &Device, queue: &Queue) -> GpuUpdate {
+        if !std::mem::take(&mut self.transforms_changed) {
+            return GpuUpdate::Unchanged;
+        }
+
+        if let (Some(instance_buffer), Some(bvh_buffer)) = (&self.instance_buffer, &self.bvh_buffer) {
+            queue.write_buffer(instance_buffer, 0, bytemuck::cast_slice(&self.gpu_instances()));
+            queue.write_buffer(bvh_buffer, 0, bytemuck::cast_slice(&self.bvh.nodes));
+        }
+        GpuUpdate::Recreated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three copies of a unit cube asset with one voxel in its +X+Y+Z corner, spread along X
+    fn provider() -> InstancedProvider {
+        let mut asset = Octree::new(na::Vector3::zeros(), 0.5, 1);
+        asset.insert(na::Vector3::new(0.25, 0.25, 0.25), VoxelData::solid([1.0, 0.0, 0.0]));
+        let instances = (0..3)
+            .map(|i| VoxelInstance {
+                asset: 0,
+                transform: Transform { translation: na::Vector3::new(i as f32 * 2.0, 0.0, 0.0), ..Default::default() },
+            })
+            .collect();
+        InstancedProvider::new(InstancedScene { assets: vec![asset], instances })
+    }
+
+    /// Every BVH node's box must contain the boxes of the instances below it
+    fn assert_bvh_covers(provider: &InstancedProvider, slot: usize) -> Vec<usize> {
+        let node = provider.bvh.nodes[slot];
+        let instances = if node.count == 0 {
+            let mut instances = assert_bvh_covers(provider, node.first as usize);
+            instances.extend(assert_bvh_covers(provider, node.first as usize + 1));
+            instances
+        } else {
+            provider.bvh.order[node.first as usize..(node.first + node.count) as usize].iter().map(|&i| i as usize).collect()
+        };
+        for &instance in &instances {
+            let (min, max) = provider.scene.instance_bounds(&provider.scene.instances[instance]);
+            assert!((0..3).all(|axis| node.bounds_min[axis] <= min[axis] && max[axis] <= node.bounds_max[axis]));
+        }
+        instances
+    }
+
+    #[test]
+    fn moving_an_instance_rebuilds_the_bvh() {
+        let mut provider = provider();
+        let nodes = provider.bvh.nodes.len();
+        let voxel = na::Vector3::new(2.25, 0.25, 0.25);
+        assert_eq!(provider.sample_voxel(voxel, 0.0).color, [1.0, 0.0, 0.0]);
+        assert!(!provider.transforms_changed);
+
+        // Lift the middle copy and turn it half a turn about Y
+        let transform = Transform {
+            translation: na::Vector3::new(2.0, 4.0, 0.0),
+            rotation: na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), std::f32::consts::PI),
+            scale: 2.0,
+        };
+        provider.set_transform(1, transform);
+        assert!(provider.transforms_changed);
+        assert_eq!(provider.scene().instances[1].transform, transform);
+
+        let mut covered = assert_bvh_covers(&provider, 0);
+        covered.sort_unstable();
+        assert_eq!(covered, vec![0, 1, 2]);
+        assert_eq!(provider.bvh.nodes.len(), nodes);
+        let (min, max) = provider.get_bounds();
+        assert!((min - na::Vector3::new(-0.5, -0.5, -1.0)).norm() < 1e-5);
+        assert!((max - na::Vector3::new(4.5, 5.0, 1.0)).norm() < 1e-5);
+
+        // The voxel moved with the instance; the rotation sends +X+Z to -X-Z
+        assert_eq!(provider.sample_voxel(voxel, 0.0), VoxelData::empty());
+        let moved = provider.sample_voxel(na::Vector3::new(1.5, 4.5, -0.5), 0.0);
+        assert_eq!(moved.color, [1.0, 0.0, 0.0]);
+        let point = na::Vector3::new(1.5, 4.5, -0.5);
+        assert!(provider.bvh.instances_in(&point, &point).contains(&1));
+    }
+}
@@ -0,0 +1,98 @@
+use super::{Octree, OctreeNode, VoxelData};
+use super::material::MaterialTable;
+use bytemuck::{Pod, Zeroable};
+use std::collections::VecDeque;
+
+/// Node of the linearized sparse voxel octree as laid out in the GPU storage buffer.
+/// The eight children of a subdivided node occupy consecutive slots starting at
+/// `first_child`. The root always lives in slot 0, so `first_child == 0` marks a leaf.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct GpuOctreeNode {
+    pub first_child: u32,
+    pub payload: u32,   // RGBA8: color in rgb, density in alpha
+    pub material: u32,  // index into the material table, 0 for interior nodes
+}
+
+/// Uniform header describing where the linearized octree sits in world space
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuOctreeHeader {
+    pub center: [f32; 3],
+    pub half_size: f32,
+    pub max_depth: u32,
+    pub node_count: u32,
+    pub _padding: [u32; 2],
+}
+
+/// Breadth-first flattening of an `Octree` into a compact node array
+pub struct LinearOctree {
+    pub header: GpuOctreeHeader,
+    pub nodes: Vec<GpuOctreeNode>,
+}
+
+impl LinearOctree {
+    /// Flatten the octree breadth-first
+    pub fn from_octree(octree: &Octree) -> Self {
+        let mut nodes = vec![GpuOctreeNode::default()];
+        let mut queue: VecDeque<(&OctreeNode, usize)> = VecDeque::new();
+        queue.push_back((&octree.root, 0));
+
+        while let Some((node, slot)) = queue.pop_front() {
+            nodes[slot].payload = node.voxel_data.as_ref().map_or(0, pack_rgba8);
+            nodes[slot].material = leaf_material(node, &octree.materials);
+
+            if let Some(ref children) = node.children {
+                let first_child = nodes.len();
+                nodes[slot].first_child = first_child as u32;
+                nodes.resize(first_child + 8, GpuOctreeNode::default());
+
+                for (i, child) in children.iter().enumerate() {
+                    queue.push_back((child, first_child + i));
+                }
+            }
+        }
+
+        let header = GpuOctreeHeader {
+            center: octree.root.center.into(),
+            half_size: octree.root.half_size,
+            max_depth: octree.max_depth as u32,
+            node_count: nodes.len() as u32,
+            _padding: [0; 2],
+        };
+
+        Self { header, nodes }
+    }
+
+    /// Size of the node buffer in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.nodes.len() * std::mem::size_of::<GpuOctreeNode>()
+    }
+}
+
+/// Material index of a leaf; the shader never stops at interior nodes, so
+/// their majority-vote material is not uploaded
+pub fn leaf_material(node: &OctreeNode, materials: &MaterialTable) -> u32 {
+    match node.voxel_data {
+        Some(ref data) if node.is_leaf() => materials.lookup(data),
+        _ => 0,
+    }
+}
+
+/// Pack color and density as RGBA8, matching the dense texture encoding.
+/// Byte order is little-endian so the shader can decode with `unpack4x8unorm`.
+pub fn pack_rgba8(data: &VoxelData) -> u32 {
+    let r = (data.color[0] * 255.0) as u8 as u32;
+    let g = (data.color[1] * 255.0) as u8 as u32;
+    let b = (data.color[2] * 255.0) as u8 as u32;
+    let a = (data.density * 255.0) as u8 as u32;
+    r | (g << 8) | (b << 16) | (a << 24)
+}
+
+/// Inverse of `pack_rgba8`; emission and material are not stored
+pub fn unpack_rgba8(packed: u32) -> VoxelData {
+    let channel = |shift: u32| ((packed >> shift) & 0xff) as f32 / 255.0;
+    let mut data = VoxelData::solid([channel(0), channel(8), channel(16)]);
+    data.density = channel(24);
+    data
+}
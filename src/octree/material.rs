@@ -0,0 +1,230 @@
+use super::VoxelData;
+use bytemuck::{Pod, Zeroable};
+use std::collections::HashMap;
+use wgpu::*;
+use wgpu::util::DeviceExt;
+
+/// Binding of the material table in every provider's group 3, declared once
+/// in `ray_march.wgsl`. Backends return indices into it from `octree_lookup`.
+pub const MATERIAL_BINDING: u32 = 8;
+
+/// Physically based shading parameters shared by every voxel that references
+/// the material. Laid out to match `Material` in `ray_march.wgsl`, so the table
+/// is uploaded as is.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct Material {
+    /// Multiplies the voxel's own color; white leaves it unchanged
+    pub albedo: [f32; 3],
+    pub roughness: f32,
+    /// Emitted color, scaled by `emission_strength` into linear HDR radiance
+    pub emission: [f32; 3],
+    pub emission_strength: f32,
+    pub metalness: f32,
+    pub ior: f32,
+    /// Fraction of light refracted through the surface instead of reflected
+    pub transmission: f32,
+    pub _padding: f32,
+}
+
+impl Default for Material {
+    /// Rough white diffuse
+    fn default() -> Self {
+        Self {
+            albedo: [1.0; 3],
+            roughness: 1.0,
+            emission: [0.0; 3],
+            emission_strength: 0.0,
+            metalness: 0.0,
+            ior: 1.5,
+            transmission: 0.0,
+            _padding: 0.0,
+        }
+    }
+}
+
+impl Material {
+    pub fn metal(roughness: f32) -> Self {
+        Self { metalness: 1.0, roughness, ..Default::default() }
+    }
+
+    pub fn glass(ior: f32) -> Self {
+        Self { transmission: 1.0, roughness: 0.0, ior, ..Default::default() }
+    }
+
+    pub fn emissive(emission: [f32; 3], strength: f32) -> Self {
+        Self { emission, emission_strength: strength, ..Default::default() }
+    }
+
+    /// Preset for a `material_type` of version 1 files: 0 diffuse, 1 metallic,
+    /// 2 glass, 3 emissive. Version 1 stored emission per voxel.
+    pub fn legacy(material_type: u32, emission: [f32; 3]) -> Self {
+        let material = match material_type {
+            1 => Self::metal(0.2),
+            2 => Self::glass(1.5),
+            _ => Self::default(),
+        };
+        if emission.iter().any(|&e| e > 0.0) {
+            Self { emission, emission_strength: 1.0, ..material }
+        } else {
+            material
+        }
+    }
+
+    /// Linear HDR radiance leaving an emissive surface
+    pub fn radiance(&self) -> [f32; 3] {
+        self.emission.map(|e| e * self.emission_strength)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.radiance().iter().any(|&e| e > 0.0)
+    }
+
+    /// Dominant behaviour, for reports
+    pub fn kind(&self) -> &'static str {
+        if self.is_emissive() {
+            "emissive"
+        } else if self.transmission > 0.5 {
+            "glass"
+        } else if self.metalness > 0.5 {
+            "metallic"
+        } else {
+            "diffuse"
+        }
+    }
+}
+
+/// Material palette of a scene. Voxels store an index into it next to their
+/// own color, so a material can be changed without touching the octree.
+/// Index 0 is always the default material, which empty space and interior LoD
+/// nodes also use. Equal materials share one entry.
+#[derive(Clone, Debug)]
+pub struct MaterialTable {
+    materials: Vec<Material>,
+    indices: HashMap<[u32; 12], u32>,
+}
+
+impl Default for MaterialTable {
+    fn default() -> Self {
+        let mut table = Self {
+            materials: Vec::new(),
+            indices: HashMap::new(),
+        };
+        table.insert(Material::default());
+        table
+    }
+}
+
+impl MaterialTable {
+    /// Table holding `materials` in order, as read back from a file. Entries
+    /// made equal by `set` keep their own indices. An empty list gives the
+    /// default table.
+    pub fn from_materials(materials: Vec<Material>) -> Self {
+        if materials.is_empty() {
+            return Self::default();
+        }
+        let mut indices = HashMap::new();
+        for (index, material) in materials.iter().enumerate() {
+            indices.entry(key(material)).or_insert(index as u32);
+        }
+        Self { materials, indices }
+    }
+
+    /// Index of a material, adding it if it is new
+    pub fn insert(&mut self, material: Material) -> u32 {
+        *self.indices.entry(key(&material)).or_insert_with(|| {
+            self.materials.push(material);
+            self.materials.len() as u32 - 1
+        })
+    }
+
+    /// Index of an existing entry equal to `material`
+    pub fn find(&self, material: &Material) -> Option<u32> {
+        self.indices.get(&key(material)).copied()
+    }
+
+    pub fn get(&self, index: u32) -> Option<&Material> {
+        self.materials.get(index as usize)
+    }
+
+    /// Replace the material at `index`; voxels referencing it pick up the change
+    pub fn set(&mut self, index: u32, material: Material) {
+        let Some(slot) = self.materials.get_mut(index as usize) else {
+            return;
+        };
+        let old = std::mem::replace(slot, material);
+        if self.indices.get(&key(&old)) == Some(&index) {
+            self.indices.remove(&key(&old));
+        }
+        self.indices.entry(key(&material)).or_insert(index);
+    }
+
+    /// Index to render an occupied voxel with, 0 for empty voxels or
+    /// indices outside the table
+    pub fn lookup(&self, data: &VoxelData) -> u32 {
+        if data.density > 0.0 && (data.material as usize) < self.materials.len() { data.material } else { 0 }
+    }
+
+    /// Material of an occupied voxel, the default one otherwise
+    pub fn material_of(&self, data: &VoxelData) -> &Material {
+        &self.materials[self.lookup(data) as usize]
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    /// Never true: the default material is always present
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    pub fn create_buffer(&self, device: &Device) -> Buffer {
+        device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Material Table Buffer"),
+            contents: bytemuck::cast_slice(&self.materials),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        })
+    }
+
+    /// Write the table into `buffer` if it still fits; returns false when the
+    /// table has grown and a new buffer is needed
+    pub fn write_buffer(&self, queue: &Queue, buffer: &Buffer) -> bool {
+        let bytes: &[u8] = bytemuck::cast_slice(&self.materials);
+        if bytes.len() as u64 > buffer.size() {
+            return false;
+        }
+        queue.write_buffer(buffer, 0, bytes);
+        true
+    }
+}
+
+fn key(material: &Material) -> [u32; 12] {
+    bytemuck::cast(*material)
+}
+
+/// Layout entry for the material table at `MATERIAL_BINDING`
+pub fn material_layout_entry() -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding: MATERIAL_BINDING,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Bind group entry for a buffer made by `MaterialTable::create_buffer`
+pub fn material_bind_entry(buffer: &Buffer) -> BindGroupEntry<'_> {
+    BindGroupEntry {
+        binding: MATERIAL_BINDING,
+        resource: buffer.as_entire_binding(),
+    }
+}
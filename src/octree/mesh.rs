@@ -0,0 +1,355 @@
+use super::material::{Material, MaterialTable};
+use super::scene_file::invalid;
+use super::{Octree, VoxelData};
+use nalgebra as na;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Most cells the bounding box of a mesh may span when voxelized. Filling the
+/// interior keeps a flag for every one of them.
+pub const MAX_VOXELIZE_CELLS: u64 = 1 << 30;
+
+/// Triangle referencing an entry of `Mesh::materials`
+#[derive(Copy, Clone, Debug)]
+pub struct Triangle {
+    pub vertices: [na::Vector3<f32>; 3],
+    pub material: usize,
+}
+
+/// Triangle soup loaded from an OBJ or STL file
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+    /// Material 0 is the default used by STL files and faces without `usemtl`
+    pub materials: Vec<VoxelData>,
+    /// Palette the `materials` voxels refer to
+    pub material_table: MaterialTable,
+}
+
+impl Mesh {
+    /// Load an `.obj` (with its `.mtl` libraries) or `.stl` file, chosen by extension
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Mesh> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" => Self::load_obj(path),
+            "stl" => Self::load_stl(path),
+            _ => Err(invalid(&format!("unsupported mesh format: {}", path.display()))),
+        }
+    }
+
+    pub fn load_obj(path: impl AsRef<Path>) -> io::Result<Mesh> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+
+        let mut mesh = Mesh::empty();
+        let mut material_names: HashMap<String, usize> = HashMap::new();
+        let mut positions: Vec<na::Vector3<f32>> = Vec::new();
+        let mut current_material = 0;
+
+        for (line_number, line) in text.lines().enumerate() {
+            let error = |message: &str| invalid(&format!("{}:{}: {}", path.display(), line_number + 1, message));
+            let mut tokens = line.split_whitespace();
+
+            match tokens.next() {
+                Some("v") => {
+                    let coords = parse_floats(tokens, 3).ok_or_else(|| error("bad vertex"))?;
+                    positions.push(na::Vector3::new(coords[0], coords[1], coords[2]));
+                }
+                Some("f") => {
+                    let indices = tokens
+                        .map(|token| resolve_index(token, positions.len()))
+                        .collect::<Option<Vec<usize>>>()
+                        .ok_or_else(|| error("bad face index"))?;
+                    if indices.len() < 3 {
+                        return Err(error("face needs at least three vertices"));
+                    }
+                    // Triangulate polygons as a fan around the first vertex
+                    for i in 1..indices.len() - 1 {
+                        mesh.triangles.push(Triangle {
+                            vertices: [positions[indices[0]], positions[indices[i]], positions[indices[i + 1]]],
+                            material: current_material,
+                        });
+                    }
+                }
+                Some("mtllib") => {
+                    let directory = path.parent().unwrap_or(Path::new(""));
+                    for library in tokens {
+                        for (name, material) in load_mtl(&directory.join(library), &mut mesh.material_table)? {
+                            material_names.insert(name, mesh.materials.len());
+                            mesh.materials.push(material);
+                        }
+                    }
+                }
+                Some("usemtl") => {
+                    let name = tokens.next().unwrap_or("");
+                    current_material = material_names.get(name).copied().unwrap_or(0);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    pub fn load_stl(path: impl AsRef<Path>) -> io::Result<Mesh> {
+        let bytes = fs::read(path)?;
+        let mut mesh = Mesh::empty();
+
+        // Binary STL: 80-byte header, triangle count, then 50 bytes per triangle
+        let binary_count = bytes.get(80..84).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+        if let Some(count) = binary_count && bytes.len() == 84 + count * 50 {
+            for record in bytes[84..].chunks_exact(50) {
+                // Skip the 12-byte normal; the attribute word at the end is unused
+                let vertex = |i: usize| {
+                    let offset = 12 + i * 12;
+                    let value = |j: usize| f32::from_le_bytes(record[offset + j * 4..offset + j * 4 + 4].try_into().unwrap());
+                    na::Vector3::new(value(0), value(1), value(2))
+                };
+                mesh.triangles.push(Triangle { vertices: [vertex(0), vertex(1), vertex(2)], material: 0 });
+            }
+            return Ok(mesh);
+        }
+
+        let text = String::from_utf8(bytes).map_err(|_| invalid("STL is neither binary nor ASCII"))?;
+        let mut corners = Vec::with_capacity(3);
+        for (line_number, line) in text.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            if tokens.next() == Some("vertex") {
+                let coords = parse_floats(tokens, 3)
+                    .ok_or_else(|| invalid(&format!("line {}: bad vertex", line_number + 1)))?;
+                corners.push(na::Vector3::new(coords[0], coords[1], coords[2]));
+                if corners.len() == 3 {
+                    mesh.triangles.push(Triangle { vertices: [corners[0], corners[1], corners[2]], material: 0 });
+                    corners.clear();
+                }
+            }
+        }
+        Ok(mesh)
+    }
+
+    /// Apply an affine transform to every vertex
+    pub fn transform(&mut self, matrix: &na::Matrix4<f32>) {
+        for triangle in &mut self.triangles {
+            for vertex in &mut triangle.vertices {
+                *vertex = matrix.transform_point(&na::Point3::from(*vertex)).coords;
+            }
+        }
+    }
+
+    /// Voxelize into grid cells of `voxel_size`. Every cell the surface touches is
+    /// included (conservative rasterization); with `solid` the enclosed interior is
+    /// filled too, taking the material of the surface voxel that bounds it along -X.
+    /// Fails if the mesh spans more than `MAX_VOXELIZE_CELLS` cells.
+    pub fn voxelize_cells(&self, voxel_size: f32, solid: bool) -> io::Result<Vec<(na::Vector3<i32>, VoxelData)>> {
+        self.check_grid_size(voxel_size)?;
+        let half = voxel_size * 0.5;
+        let mut surface: HashMap<na::Vector3<i32>, usize> = HashMap::new();
+
+        for triangle in &self.triangles {
+            let min = triangle.vertices.iter().fold(na::Vector3::repeat(f32::MAX), |m, v| m.inf(v));
+            let max = triangle.vertices.iter().fold(na::Vector3::repeat(f32::MIN), |m, v| m.sup(v));
+            let first = min.map(|v| (v / voxel_size).floor() as i32);
+            let last = max.map(|v| (v / voxel_size).floor() as i32);
+
+            for x in first.x..=last.x {
+                for y in first.y..=last.y {
+                    for z in first.z..=last.z {
+                        let cell = na::Vector3::new(x, y, z);
+                        let center = cell.map(|v| (v as f32 + 0.5) * voxel_size);
+                        if triangle_box_overlap(&center, half, &triangle.vertices) {
+                            surface.entry(cell).or_insert(triangle.material);
+                        }
+                    }
+                }
+            }
+        }
+
+        if solid {
+            fill_interior(&mut surface);
+        }
+
+        Ok(surface.into_iter()
+            .map(|(cell, material)| (cell, self.materials[material]))
+            .collect())
+    }
+
+    /// Voxelize into an octree aligned to the `voxel_size` grid
+    pub fn voxelize(&self, voxel_size: f32, solid: bool) -> io::Result<Octree> {
+        let mut octree = Octree::from_cells(&self.voxelize_cells(voxel_size, solid)?, voxel_size)?;
+        octree.materials = self.material_table.clone();
+        Ok(octree)
+    }
+
+    /// Reject voxel sizes and vertices that would make the grid too large to rasterize
+    fn check_grid_size(&self, voxel_size: f32) -> io::Result<()> {
+        if !(voxel_size > 0.0 && voxel_size.is_finite()) {
+            return Err(invalid(&format!("voxel size must be positive, got {}", voxel_size)));
+        }
+        let vertices = self.triangles.iter().flat_map(|triangle| triangle.vertices.iter());
+        if vertices.clone().any(|vertex| !vertex.iter().all(|v| v.is_finite())) {
+            return Err(invalid("mesh has non-finite vertices"));
+        }
+
+        let Some((min, max)) = vertices.fold(None, |bounds: Option<(na::Vector3<f32>, na::Vector3<f32>)>, v| {
+            Some(bounds.map_or((*v, *v), |(min, max)| (min.inf(v), max.sup(v))))
+        }) else {
+            return Ok(());
+        };
+        let limit = (i32::MAX / 2) as f32;
+        if min.iter().chain(max.iter()).any(|v| (v / voxel_size).abs() > limit) {
+            return Err(invalid("mesh lies too far from the origin for this voxel size"));
+        }
+
+        let extent = (max - min).map(|v| (v / voxel_size).floor() as u64 + 2);
+        let cells = extent.iter().try_fold(1u64, |cells, &v| cells.checked_mul(v));
+        if cells.is_none_or(|cells| cells > MAX_VOXELIZE_CELLS) {
+            return Err(invalid(&format!(
+                "mesh spans {}x{}x{} voxels at voxel size {}, more than {}; use a larger voxel size",
+                extent.x, extent.y, extent.z, voxel_size, MAX_VOXELIZE_CELLS,
+            )));
+        }
+        Ok(())
+    }
+
+    fn empty() -> Self {
+        Mesh { triangles: Vec::new(), materials: vec![default_material()], material_table: MaterialTable::default() }
+    }
+}
+
+fn default_material() -> VoxelData {
+    VoxelData::solid([0.73, 0.73, 0.73])
+}
+
+fn parse_floats<'a>(tokens: impl Iterator<Item = &'a str>, count: usize) -> Option<Vec<f32>> {
+    let values: Vec<f32> = tokens.take(count).map(|t| t.parse().ok()).collect::<Option<_>>()?;
+    (values.len() == count).then_some(values)
+}
+
+/// Resolve an OBJ `v/vt/vn` reference (1-based, negative counts from the end)
+fn resolve_index(token: &str, vertex_count: usize) -> Option<usize> {
+    let index: i64 = token.split('/').next()?.parse().ok()?;
+    let resolved = if index < 0 { vertex_count as i64 + index } else { index - 1 };
+    (0..vertex_count as i64).contains(&resolved).then_some(resolved as usize)
+}
+
+/// Read `newmtl` entries into `table`: color from `Kd`, emission from `Ke`,
+/// roughness from `Pr`, metalness from `Pm`, IOR from `Ni` and transmission
+/// from `d` (dissolve)
+fn load_mtl(path: &Path, table: &mut MaterialTable) -> io::Result<Vec<(String, VoxelData)>> {
+    let text = fs::read_to_string(path)?;
+    let mut materials: Vec<(String, [f32; 3], Material)> = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        let color = |tokens| parse_floats(tokens, 3)
+            .map(|c| [c[0], c[1], c[2]])
+            .ok_or_else(|| invalid(&format!("{}:{}: bad color", path.display(), line_number + 1)));
+
+        let number = |tokens| parse_floats(tokens, 1)
+            .map(|v| v[0])
+            .ok_or_else(|| invalid(&format!("{}:{}: bad value", path.display(), line_number + 1)));
+
+        match (keyword, materials.last_mut()) {
+            (Some("newmtl"), _) => materials.push((tokens.next().unwrap_or("").to_string(), [0.8; 3], Material::default())),
+            (Some("Kd"), Some(material)) => material.1 = color(tokens)?,
+            (Some("Ke"), Some(material)) => {
+                material.2.emission = color(tokens)?;
+                material.2.emission_strength = 1.0;
+            }
+            (Some("Pr"), Some(material)) => material.2.roughness = number(tokens)?,
+            (Some("Pm"), Some(material)) => material.2.metalness = number(tokens)?,
+            (Some("Ni"), Some(material)) => material.2.ior = number(tokens)?,
+            (Some("d"), Some(material)) => material.2.transmission = 1.0 - number(tokens)?,
+            _ => {}
+        }
+    }
+
+    Ok(materials.into_iter().map(|(name, diffuse, material)| {
+        let index = table.insert(material);
+        (name, VoxelData::with_material(diffuse, index, table))
+    }).collect())
+}
+
+/// Separating axis test between a triangle and an axis-aligned cube
+/// (Akenine-Möller). The cube is inflated slightly so that triangles lying
+/// exactly on a cell boundary mark both neighbours.
+fn triangle_box_overlap(center: &na::Vector3<f32>, half_size: f32, triangle: &[na::Vector3<f32>; 3]) -> bool {
+    let half_size = half_size * 1.0001;
+    let v = triangle.map(|p| p - center);
+    let edges = [v[1] - v[0], v[2] - v[1], v[0] - v[2]];
+
+    let separated = |axis: na::Vector3<f32>| {
+        let projections = v.map(|p| axis.dot(&p));
+        let min = projections.iter().copied().fold(f32::MAX, f32::min);
+        let max = projections.iter().copied().fold(f32::MIN, f32::max);
+        let radius = half_size * (axis.x.abs() + axis.y.abs() + axis.z.abs());
+        min > radius || max < -radius
+    };
+
+    // Cube face normals, then the triangle normal, then the nine edge cross products
+    let box_axes = [na::Vector3::x(), na::Vector3::y(), na::Vector3::z()];
+    if box_axes.iter().any(|axis| separated(*axis)) {
+        return false;
+    }
+    if separated(edges[0].cross(&edges[1])) {
+        return false;
+    }
+    !box_axes.iter().any(|axis| edges.iter().any(|edge| {
+        let cross = axis.cross(edge);
+        cross.norm_squared() > 1e-12 && separated(cross)
+    }))
+}
+
+/// Flood-fill the outside of the surface shell and mark every unreached cell as interior
+fn fill_interior(surface: &mut HashMap<na::Vector3<i32>, usize>) {
+    if surface.is_empty() {
+        return;
+    }
+
+    // Pad by one cell so the outside is connected around the whole shell
+    let min = surface.keys().fold(na::Vector3::repeat(i32::MAX), |m, c| m.inf(c)).add_scalar(-1);
+    let max = surface.keys().fold(na::Vector3::repeat(i32::MIN), |m, c| m.sup(c)).add_scalar(1);
+    let dims = (max - min).add_scalar(1).map(|v| v as usize);
+    let index = |cell: &na::Vector3<i32>| {
+        let local = (cell - min).map(|v| v as usize);
+        (local.z * dims.y + local.y) * dims.x + local.x
+    };
+
+    let mut outside = vec![false; dims.x * dims.y * dims.z];
+    let mut queue = VecDeque::from([min]);
+    outside[index(&min)] = true;
+
+    while let Some(cell) = queue.pop_front() {
+        for axis in 0..3 {
+            for step in [-1, 1] {
+                let mut next = cell;
+                next[axis] += step;
+                if next[axis] < min[axis] || next[axis] > max[axis] {
+                    continue;
+                }
+                let i = index(&next);
+                if !outside[i] && !surface.contains_key(&next) {
+                    outside[i] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    for z in min.z..=max.z {
+        for y in min.y..=max.y {
+            let mut material = 0;
+            for x in min.x..=max.x {
+                let cell = na::Vector3::new(x, y, z);
+                if let Some(&surface_material) = surface.get(&cell) {
+                    material = surface_material;
+                } else if !outside[index(&cell)] {
+                    surface.insert(cell, material);
+                }
+            }
+        }
+    }
+}
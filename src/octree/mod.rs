@@ -1,16 +1,35 @@
+use material::MaterialTable;
+use raycast::Hit;
 use nalgebra as na;
 use wgpu::*;
 
+pub mod brick_map;
+pub mod bulk;
+pub mod dag;
+pub mod distance_field;
+pub mod dynamic_provider;
+pub mod instancing;
+pub mod linear;
+pub mod material;
+pub mod mesh;
+pub mod occupancy;
+pub mod query;
+pub mod raycast;
+pub mod scene_description;
+pub mod scene_file;
 pub mod static_provider;
+pub mod stats;
+pub mod streaming;
+pub mod vox;
 
 /// Represents voxel data returned from the octree
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct VoxelData {
     pub color: [f32; 3],
     pub density: f32,
-    pub emission: [f32; 3],
-    pub material_type: u32,  // 0 = diffuse, 1 = metallic, 2 = glass, 3 = emissive
+    pub emission: [f32; 3],  // the material's radiance when the voxel was made, for CPU-side LoD and reports
+    pub material: u32,       // index into the octree's MaterialTable
 }
 
 impl VoxelData {
@@ -19,7 +38,7 @@ impl VoxelData {
             color: [0.0, 0.0, 0.0],
             density: 0.0,
             emission: [0.0, 0.0, 0.0],
-            material_type: 0,
+            material: 0,
         }
     }
 
@@ -28,18 +47,67 @@ impl VoxelData {
             color,
             density: 1.0,
             emission: [0.0, 0.0, 0.0],
-            material_type: 0,
+            material: 0,
         }
     }
 
-    pub fn emissive(color: [f32; 3], emission: [f32; 3]) -> Self {
+    /// Solid voxel referencing entry `material` of `materials`
+    pub fn with_material(color: [f32; 3], material: u32, materials: &MaterialTable) -> Self {
         Self {
             color,
             density: 1.0,
-            emission,
-            material_type: 3,
+            emission: materials.get(material).map_or([0.0; 3], |m| m.radiance()),
+            material,
         }
     }
+
+    /// Combine the eight children of a node into a single coarse voxel.
+    /// Color is averaged over occupied children, density is weighted by how many
+    /// of the eight octants are covered, emission is summed and the most common
+    /// material wins. Returns `None` when every child is empty.
+    pub fn aggregate(children: &[Option<VoxelData>]) -> Option<VoxelData> {
+        let occupied: Vec<&VoxelData> = children.iter().flatten().collect();
+        if occupied.is_empty() {
+            return None;
+        }
+
+        let count = occupied.len() as f32;
+        let mut color = [0.0; 3];
+        let mut density = 0.0;
+        let mut emission = [0.0; 3];
+        let mut materials: Vec<(u32, u32)> = Vec::with_capacity(4);
+
+        for voxel in &occupied {
+            for i in 0..3 {
+                color[i] += voxel.color[i] / count;
+                emission[i] += voxel.emission[i];
+            }
+            density += voxel.density;
+
+            match materials.iter_mut().find(|(material, _)| *material == voxel.material) {
+                Some((_, votes)) => *votes += 1,
+                None => materials.push((voxel.material, 1)),
+            }
+        }
+
+        // Ties go to the material seen first
+        let material = materials.iter()
+            .fold((0, 0), |best, &(material, votes)| if votes > best.1 { (material, votes) } else { best })
+            .0;
+
+        Some(VoxelData {
+            color,
+            density: density / children.len() as f32,
+            emission,
+            material,
+        })
+    }
+}
+
+/// LoD level for CPU sampling at a distance from the camera: every 5 units
+/// halves the resolution
+pub fn lod_for_distance(distance: f32) -> u8 {
+    (distance / 5.0).floor() as u8
 }
 
 /// Core trait for octree implementations
@@ -62,25 +130,55 @@ pub trait OctreeProvider: Send + Sync {
     /// Get the bounding box of the scene
     fn get_bounds(&self) -> (na::Vector3<f32>, na::Vector3<f32>);
 
+    /// Number of voxels the GPU data resolves along each axis of `get_bounds`
+    fn get_grid_dims(&self) -> na::Vector3<u32>;
+
     /// Check if provider supports dynamic updates
     fn is_dynamic(&self) -> bool {
         false
     }
 
-    /// Update voxel data (for dynamic providers)
+    /// Update voxel data (for dynamic providers). Data with zero density clears the voxel.
     fn update_voxel(&mut self, _position: na::Vector3<f32>, _data: VoxelData) -> Result<(), String> {
         Err("This provider does not support dynamic updates".to_string())
     }
 
+    /// First occupied voxel along a ray, for providers that keep their octree
+    /// on the CPU. Used to pick voxels to edit.
+    fn raycast(&self, _origin: na::Vector3<f32>, _direction: na::Vector3<f32>, _max_t: f32) -> Option<Hit> {
+        None
+    }
+
     /// Bind GPU resources for this provider
     fn bind_gpu_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup);
 
-    /// Update GPU resources if needed (called each frame)
-    fn update_gpu_resources(&mut self, _queue: &Queue) {
+    /// WGSL source declaring this provider's group 3 bindings and its `octree_lookup`
+    /// function. It is prepended to `ray_march.wgsl` when the compute pipeline is built.
+    fn shader_source(&self) -> &'static str;
+
+    /// Called whenever the camera moves, for providers that load data around the viewer
+    fn update_view(&mut self, _eye: na::Point3<f32>) {}
+
+    /// Update GPU resources if needed (called each frame) and report what changed
+    fn update_gpu_resources(&mut self, _device: &Device, _queue: &Queue) -> GpuUpdate {
         // Default: no updates needed
+        GpuUpdate::Unchanged
     }
 }
 
+/// What `OctreeProvider::update_gpu_resources` changed, ordered so that
+/// combining two updates is their `max`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GpuUpdate {
+    Unchanged,
+    /// Voxel or material data was written into the existing resources, so
+    /// frames rendered before it are stale
+    ContentChanged,
+    /// Resources were recreated or the scene bounds moved; `bind_gpu_resources`
+    /// must be called again
+    Recreated,
+}
+
 /// Octree node structure for spatial subdivision
 #[derive(Clone, Debug)]
 pub struct OctreeNode {
@@ -102,7 +200,9 @@ impl OctreeNode {
         }
     }
 
-    /// Subdivide this node into 8 children
+    /// Subdivide this node into 8 children.
+    /// Any data held by the node is copied into every child, so re-splitting a
+    /// collapsed leaf keeps the region's contents intact.
     pub fn subdivide(&mut self) {
         if self.children.is_some() {
             return;
@@ -118,11 +218,13 @@ impl OctreeNode {
                 if i & 2 != 0 { new_half_size } else { -new_half_size },
                 if i & 4 != 0 { new_half_size } else { -new_half_size },
             );
-            children.push(OctreeNode::new(
+            let mut child = OctreeNode::new(
                 self.center + offset,
                 new_half_size,
                 new_level,
-            ));
+            );
+            child.voxel_data = self.voxel_data;
+            children.push(child);
         }
 
         self.children = Some(Box::new(children.try_into().unwrap()));
@@ -137,6 +239,42 @@ impl OctreeNode {
         index
     }
 
+    /// Recompute this node's LoD data from its direct children
+    pub fn aggregate_children(&mut self) {
+        if let Some(ref children) = self.children {
+            let child_data: [Option<VoxelData>; 8] = std::array::from_fn(|i| children[i].voxel_data);
+            self.voxel_data = VoxelData::aggregate(&child_data);
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_none()
+    }
+
+    /// Merge the children into this node when all eight are leaves holding
+    /// identical data. Returns true if the node was collapsed.
+    pub fn try_collapse(&mut self) -> bool {
+        let uniform = match self.children {
+            Some(ref children) => {
+                children.iter().all(|child| child.is_leaf() && child.voxel_data == children[0].voxel_data)
+            }
+            None => false,
+        };
+
+        if uniform {
+            let children = self.children.take().unwrap();
+            self.voxel_data = children[0].voxel_data;
+        }
+        uniform
+    }
+
+    /// Number of nodes in this subtree, including this one
+    pub fn node_count(&self) -> usize {
+        1 + self.children.as_ref().map_or(0, |children| {
+            children.iter().map(|child| child.node_count()).sum()
+        })
+    }
+
     /// Check if a position is within this node's bounds
     pub fn contains(&self, position: &na::Vector3<f32>) -> bool {
         (position.x - self.center.x).abs() <= self.half_size &&
@@ -145,11 +283,82 @@ impl OctreeNode {
     }
 }
 
+/// Region used by the clearing and query operations on `Octree`
+#[derive(Copy, Clone, Debug)]
+enum Region {
+    Aabb { min: na::Vector3<f32>, max: na::Vector3<f32> },
+    Sphere { center: na::Vector3<f32>, radius: f32 },
+}
+
+/// How a node's cube relates to a `Region`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Overlap {
+    Outside,
+    Inside,
+    Partial,
+}
+
+impl Region {
+    fn classify(&self, center: &na::Vector3<f32>, half_size: f32) -> Overlap {
+        let half = na::Vector3::new(half_size, half_size, half_size);
+        let node_min = center - half;
+        let node_max = center + half;
+
+        match *self {
+            Region::Aabb { min, max } => {
+                if (0..3).any(|i| node_max[i] < min[i] || node_min[i] > max[i]) {
+                    Overlap::Outside
+                } else if (0..3).all(|i| node_min[i] >= min[i] && node_max[i] <= max[i]) {
+                    Overlap::Inside
+                } else {
+                    Overlap::Partial
+                }
+            }
+            Region::Sphere { center: sphere_center, radius } => {
+                let closest = sphere_center.sup(&node_min).inf(&node_max);
+                let offset = (sphere_center - center).abs() + half;
+                if (closest - sphere_center).norm() > radius {
+                    Overlap::Outside
+                } else if offset.norm() <= radius {
+                    Overlap::Inside
+                } else {
+                    Overlap::Partial
+                }
+            }
+        }
+    }
+
+    fn contains(&self, position: &na::Vector3<f32>) -> bool {
+        match *self {
+            Region::Aabb { min, max } => {
+                (0..3).all(|i| position[i] >= min[i] && position[i] <= max[i])
+            }
+            Region::Sphere { center, radius } => (position - center).norm() <= radius,
+        }
+    }
+}
+
+/// Node counts and memory use before and after `Octree::optimize`
+#[derive(Copy, Clone, Debug)]
+pub struct CollapseStats {
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+/// Deepest octree supported, set by the 21 bits per axis of the Morton codes
+/// `Octree::from_voxels` sorts cells by
+pub const MAX_DEPTH: u8 = 21;
+
 /// Basic octree structure
+#[derive(Clone)]
 pub struct Octree {
     pub root: OctreeNode,
     pub max_depth: u8,
     pub base_voxel_size: f32,
+    /// Palette the voxels' `material` indices refer to
+    pub materials: MaterialTable,
 }
 
 impl Octree {
@@ -158,10 +367,40 @@ impl Octree {
             root: OctreeNode::new(center, half_size, 0),
             max_depth,
             base_voxel_size: half_size * 2.0 / (1 << max_depth) as f32,
+            materials: MaterialTable::default(),
+        }
+    }
+
+    /// Build an octree whose leaves line up with an integer voxel grid.
+    /// Cell `(i, j, k)` is centered at `(i + 0.5) * voxel_size`, and the root is
+    /// the smallest power-of-two cube of cells covering all of them. Fails when
+    /// the cells span more than `2^MAX_DEPTH` along an axis.
+    pub fn from_cells(cells: &[(na::Vector3<i32>, VoxelData)], voxel_size: f32) -> std::io::Result<Self> {
+        let (min, max) = cells.iter().fold(
+            (na::Vector3::repeat(i32::MAX), na::Vector3::repeat(i32::MIN)),
+            |(min, max), (cell, _)| (min.inf(cell), max.sup(cell)),
+        );
+
+        let extent = if cells.is_empty() { 1 } else { (max.cast::<i64>() - min.cast::<i64>()).max() + 1 };
+        if extent > 1 << MAX_DEPTH {
+            return Err(scene_file::invalid(&format!("voxels span {} cells, more than the {} an octree can hold", extent, 1 << MAX_DEPTH)));
         }
+        let max_depth = (extent as u32).next_power_of_two().trailing_zeros() as u8;
+        let half_size = (1u32 << max_depth) as f32 * voxel_size * 0.5;
+        let origin = if cells.is_empty() { na::Vector3::zeros() } else { min.map(|v| v as f32 * voxel_size) };
+
+        let voxels = cells.iter()
+            .map(|(cell, data)| {
+                let local = cell - min;
+                ([local.x as u32, local.y as u32, local.z as u32], *data)
+            })
+            .collect();
+        Octree::from_voxels(origin.add_scalar(half_size), half_size, max_depth, voxels)
     }
 
-    /// Insert voxel data at a specific position
+    /// Insert voxel data at a specific position.
+    /// Collapsed leaves on the path are re-split, and on the way back up uniform
+    /// children are collapsed again and ancestor LoD data is refreshed.
     pub fn insert(&mut self, position: na::Vector3<f32>, data: VoxelData) {
         Self::insert_recursive(&mut self.root, position, data, 0, self.max_depth);
     }
@@ -177,6 +416,10 @@ impl Octree {
         }
 
         if node.children.is_none() {
+            // A collapsed leaf already holding this data needs no change
+            if node.voxel_data == Some(data) {
+                return;
+            }
             node.subdivide();
         }
 
@@ -187,32 +430,450 @@ impl Octree {
             if position.z > node.center.z { index |= 4; }
             Self::insert_recursive(&mut children[index], position, data, depth + 1, max_depth);
         }
+
+        if !node.try_collapse() {
+            node.aggregate_children();
+        }
     }
 
-    /// Sample voxel data at a position with optional LoD
-    pub fn sample(&self, position: na::Vector3<f32>, min_level: u8) -> VoxelData {
-        self.sample_recursive(&self.root, position, 0, min_level)
+    /// Remove the voxel at a position, returning its data if it was occupied.
+    /// Subtrees left empty are pruned.
+    pub fn remove(&mut self, position: na::Vector3<f32>) -> Option<VoxelData> {
+        Self::remove_recursive(&mut self.root, position, 0, self.max_depth)
     }
 
-    fn sample_recursive(&self, node: &OctreeNode, position: na::Vector3<f32>, depth: u8, min_level: u8) -> VoxelData {
+    fn remove_recursive(node: &mut OctreeNode, position: na::Vector3<f32>, depth: u8, max_depth: u8) -> Option<VoxelData> {
         if !node.contains(&position) {
-            return VoxelData::empty();
+            return None;
+        }
+
+        if depth >= max_depth {
+            return node.voxel_data.take();
+        }
+
+        if node.children.is_none() {
+            // Nothing stored here, or a collapsed leaf that must be split first
+            node.voxel_data?;
+            node.subdivide();
+        }
+
+        let index = node.get_child_index(&position);
+        let removed = match node.children {
+            Some(ref mut children) => {
+                Self::remove_recursive(&mut children[index], position, depth + 1, max_depth)
+            }
+            None => None,
+        };
+
+        if !node.try_collapse() {
+            node.aggregate_children();
+        }
+        removed
+    }
+
+    /// Remove every voxel whose center lies inside the box `min..=max`
+    pub fn clear_aabb(&mut self, min: na::Vector3<f32>, max: na::Vector3<f32>) {
+        let region = Region::Aabb { min, max };
+        Self::clear_recursive(&mut self.root, &region, 0, self.max_depth);
+    }
+
+    /// Remove every voxel whose center lies inside the sphere
+    pub fn clear_sphere(&mut self, center: na::Vector3<f32>, radius: f32) {
+        let region = Region::Sphere { center, radius };
+        Self::clear_recursive(&mut self.root, &region, 0, self.max_depth);
+    }
+
+    fn clear_recursive(node: &mut OctreeNode, region: &Region, depth: u8, max_depth: u8) {
+        if depth >= max_depth {
+            if region.contains(&node.center) {
+                node.voxel_data = None;
+            }
+            return;
+        }
+
+        match region.classify(&node.center, node.half_size) {
+            Overlap::Outside => return,
+            Overlap::Inside => {
+                node.children = None;
+                node.voxel_data = None;
+                return;
+            }
+            Overlap::Partial => {}
+        }
+
+        if node.children.is_none() {
+            if node.voxel_data.is_none() {
+                return;
+            }
+            node.subdivide();
+        }
+
+        if let Some(ref mut children) = node.children {
+            for child in children.iter_mut() {
+                Self::clear_recursive(child, region, depth + 1, max_depth);
+            }
+        }
+
+        if !node.try_collapse() {
+            node.aggregate_children();
+        }
+    }
+
+    /// Collapse every subtree whose children are uniform, bottom-up
+    pub fn optimize(&mut self) -> CollapseStats {
+        let nodes_before = self.node_count();
+        Self::optimize_recursive(&mut self.root);
+        let nodes_after = self.node_count();
+
+        CollapseStats {
+            nodes_before,
+            nodes_after,
+            bytes_before: nodes_before * std::mem::size_of::<OctreeNode>(),
+            bytes_after: nodes_after * std::mem::size_of::<OctreeNode>(),
         }
+    }
 
-        // Use this node's data if we've reached the minimum level or max depth
-        if depth >= min_level.min(self.max_depth) {
-            if let Some(data) = node.voxel_data {
-                return data;
+    fn optimize_recursive(node: &mut OctreeNode) {
+        if let Some(ref mut children) = node.children {
+            for child in children.iter_mut() {
+                Self::optimize_recursive(child);
+            }
+            if !node.try_collapse() {
+                node.aggregate_children();
             }
         }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.root.node_count()
+    }
+
+    /// Estimated memory held by the node hierarchy in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.node_count() * std::mem::size_of::<OctreeNode>()
+    }
+
+    /// Fill every interior node with data aggregated bottom-up from its children
+    pub fn build_lod(&mut self) {
+        Self::build_lod_recursive(&mut self.root);
+    }
+
+    fn build_lod_recursive(node: &mut OctreeNode) {
+        if let Some(ref mut children) = node.children {
+            for child in children.iter_mut() {
+                Self::build_lod_recursive(child);
+            }
+            node.aggregate_children();
+        }
+    }
+
+    /// Sample voxel data at a position with optional LoD.
+    /// `lod_level` 0 is full resolution; each level above stops one octree level
+    /// earlier and returns the aggregated data of that coarser node.
+    pub fn sample(&self, position: na::Vector3<f32>, lod_level: u8) -> VoxelData {
+        let stop_depth = self.max_depth.saturating_sub(lod_level);
+        self.sample_recursive(&self.root, position, 0, stop_depth)
+    }
+
+    fn sample_recursive(&self, node: &OctreeNode, position: na::Vector3<f32>, depth: u8, stop_depth: u8) -> VoxelData {
+        if !node.contains(&position) {
+            return VoxelData::empty();
+        }
+
+        // Use this node's aggregated data once we've reached the LoD depth
+        if depth >= stop_depth && let Some(data) = node.voxel_data {
+            return data;
+        }
 
         // Recurse into children if they exist
         if let Some(ref children) = node.children {
             let child_index = node.get_child_index(&position);
-            return self.sample_recursive(&children[child_index], position, depth + 1, min_level);
+            return self.sample_recursive(&children[child_index], position, depth + 1, stop_depth);
         }
 
         // Return node's data or empty if no data
         node.voxel_data.unwrap_or_else(VoxelData::empty)
     }
-}
\ No newline at end of file
+}
+
+/// Reproducible random scenes shared by the octree tests
+#[cfg(test)]
+pub(crate) mod test_scenes {
+    use super::{Octree, VoxelData};
+    use nalgebra as na;
+
+    /// Colors the random scenes pick from
+    pub const COLORS: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.73, 0.73, 0.73]];
+
+    /// Small xorshift generator
+    pub struct Rng(u32);
+
+    impl Rng {
+        pub fn new(seed: u32) -> Self {
+            Self(seed)
+        }
+
+        pub fn next_u32(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        /// Value in `0..range`
+        pub fn below(&mut self, range: u32) -> u32 {
+            self.next_u32() % range
+        }
+
+        /// Value in `0..=1`
+        pub fn unit(&mut self) -> f32 {
+            self.next_u32() as f32 / u32::MAX as f32
+        }
+    }
+
+    /// `count` voxels in random cells of `0..range` along each axis, repeats
+    /// included, colored from `COLORS` and using materials below `materials`
+    pub fn random_voxels(rng: &mut Rng, count: usize, range: u32, materials: u32) -> Vec<([u32; 3], VoxelData)> {
+        (0..count)
+            .map(|_| {
+                let cell = [rng.below(range), rng.below(range), rng.below(range)];
+                let data = VoxelData { material: rng.below(materials), ..VoxelData::solid(COLORS[rng.below(3) as usize]) };
+                (cell, data)
+            })
+            .collect()
+    }
+
+    /// Uniform `size`³ block of cells from `origin`; it collapses into one
+    /// leaf when aligned to its size
+    pub fn block(origin: [u32; 3], size: u32, data: VoxelData) -> Vec<([u32; 3], VoxelData)> {
+        (0..size * size * size)
+            .map(|i| ([origin[0] + i % size, origin[1] + i / size % size, origin[2] + i / (size * size)], data))
+            .collect()
+    }
+
+    /// Insert cells one at a time, placed as by `Octree::from_voxels`; cells
+    /// past the root are skipped
+    pub fn insert_cells(octree: &mut Octree, voxels: &[([u32; 3], VoxelData)]) {
+        let root_min = octree.root.center.add_scalar(-octree.root.half_size);
+        let cell_size = octree.root.half_size * 2.0 / (1u32 << octree.max_depth) as f32;
+        for (cell, data) in voxels {
+            octree.insert(root_min + na::Vector3::from(*cell).map(|v| (v as f32 + 0.5) * cell_size), *data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Octree over the cube -1..1 with voxels of size `2 / 2^max_depth`
+    fn octree(max_depth: u8) -> Octree {
+        Octree::new(na::Vector3::zeros(), 1.0, max_depth)
+    }
+
+    fn voxel(color: [f32; 3], emission: [f32; 3], material: u32) -> VoxelData {
+        VoxelData { color, density: 1.0, emission, material }
+    }
+
+    /// Center of child `index` of a node at the origin with half size 1
+    fn octant(index: usize) -> na::Vector3<f32> {
+        na::Vector3::new(
+            if index & 1 != 0 { 0.5 } else { -0.5 },
+            if index & 2 != 0 { 0.5 } else { -0.5 },
+            if index & 4 != 0 { 0.5 } else { -0.5 },
+        )
+    }
+
+    #[test]
+    fn aggregate_averages_occupied_children() {
+        let children = [
+            Some(voxel([1.0, 0.0, 0.0], [1.0, 2.0, 3.0], 2)),
+            None,
+            Some(voxel([0.0, 1.0, 0.0], [0.0; 3], 1)),
+            Some(voxel([0.0, 0.0, 1.0], [0.5; 3], 2)),
+            None, None, None, None,
+        ];
+        let coarse = VoxelData::aggregate(&children).unwrap();
+        assert_eq!(coarse.color, [1.0 / 3.0; 3]);
+        assert_eq!(coarse.density, 3.0 / 8.0);
+        assert_eq!(coarse.emission, [1.5, 2.5, 3.5]);
+        assert_eq!(coarse.material, 2);
+
+        assert_eq!(VoxelData::aggregate(&[None; 8]), None);
+    }
+
+    #[test]
+    fn aggregate_breaks_material_ties_by_first_seen() {
+        let mut children = [None; 8];
+        children[3] = Some(voxel([1.0; 3], [0.0; 3], 5));
+        children[6] = Some(voxel([1.0; 3], [0.0; 3], 4));
+        assert_eq!(VoxelData::aggregate(&children).unwrap().material, 5);
+    }
+
+    #[test]
+    fn insert_refreshes_coarse_levels() {
+        let mut octree = octree(2);
+        let light = voxel([1.0; 3], [4.0, 2.0, 1.0], 1);
+        let position = na::Vector3::new(0.75, 0.75, 0.75);
+        octree.insert(position, light);
+
+        assert_eq!(octree.sample(position, 0), light);
+        assert_eq!(octree.sample(position, 1).density, 1.0 / 8.0);
+        assert_eq!(octree.sample(position, 2).density, 1.0 / 64.0);
+        assert_eq!(octree.sample(position, 2).emission, light.emission);
+
+        // An empty neighbour at full resolution still sees the coarse voxel
+        let neighbour = na::Vector3::new(0.25, 0.25, 0.25);
+        assert_eq!(octree.sample(neighbour, 0), VoxelData::empty());
+        assert_eq!(octree.sample(neighbour, 1).density, 1.0 / 8.0);
+        assert_eq!(octree.sample(neighbour, 1).material, 1);
+    }
+
+    #[test]
+    fn build_lod_fills_interior_nodes() {
+        let mut octree = octree(1);
+        octree.root.subdivide();
+        let children = octree.root.children.as_mut().unwrap();
+        children[0].voxel_data = Some(voxel([1.0; 3], [0.0; 3], 0));
+        children[7].voxel_data = Some(voxel([0.0; 3], [1.0; 3], 0));
+        assert_eq!(octree.root.voxel_data, None);
+
+        octree.build_lod();
+        let root = octree.root.voxel_data.unwrap();
+        assert_eq!(root.color, [0.5; 3]);
+        assert_eq!(root.density, 2.0 / 8.0);
+        assert_eq!(root.emission, [1.0; 3]);
+    }
+
+    #[test]
+    fn identical_children_collapse_and_resplit() {
+        let mut octree = octree(1);
+        let wall = VoxelData::solid([0.73; 3]);
+        for index in 0..8 {
+            octree.insert(octant(index), wall);
+        }
+        assert!(octree.root.is_leaf());
+        assert_eq!(octree.node_count(), 1);
+        assert_eq!(octree.root.voxel_data, Some(wall));
+
+        // Re-inserting what the collapsed leaf already holds changes nothing
+        octree.insert(octant(3), wall);
+        assert_eq!(octree.node_count(), 1);
+
+        let red = VoxelData::solid([1.0, 0.0, 0.0]);
+        octree.insert(octant(7), red);
+        assert_eq!(octree.node_count(), 9);
+        for index in 0..7 {
+            assert_eq!(octree.sample(octant(index), 0), wall);
+        }
+        assert_eq!(octree.sample(octant(7), 0), red);
+    }
+
+    #[test]
+    fn collapse_propagates_up_several_levels() {
+        let mut octree = octree(2);
+        let wall = VoxelData::solid([0.73; 3]);
+        for index in 0..64 {
+            let cell = na::Vector3::new(index % 4, index / 4 % 4, index / 16).map(|v| v as f32 * 0.5 - 0.75);
+            octree.insert(cell, wall);
+        }
+        assert_eq!(octree.node_count(), 1);
+    }
+
+    #[test]
+    fn optimize_reports_collapsed_nodes() {
+        let wall = VoxelData::solid([0.73; 3]);
+        let mut octree = octree(2);
+        octree.root.voxel_data = Some(wall);
+        octree.root.subdivide();
+        for child in octree.root.children.as_mut().unwrap().iter_mut() {
+            child.subdivide();
+        }
+        let children = octree.root.children.as_mut().unwrap();
+        children[0].children.as_mut().unwrap()[0].voxel_data = Some(VoxelData::solid([1.0, 0.0, 0.0]));
+
+        // Only the child holding the red voxel stays split
+        let stats = octree.optimize();
+        let node = std::mem::size_of::<OctreeNode>();
+        assert_eq!((stats.nodes_before, stats.nodes_after), (73, 17));
+        assert_eq!((stats.bytes_before, stats.bytes_after), (73 * node, 17 * node));
+        assert_eq!(octree.memory_bytes(), 17 * node);
+        assert!(octree.root.children.as_ref().unwrap()[1].is_leaf());
+    }
+
+    /// Octree of depth 2 with every one of its 64 voxels filled
+    fn solid_octree(data: VoxelData) -> Octree {
+        let mut octree = octree(2);
+        octree.root.voxel_data = Some(data);
+        octree
+    }
+
+    /// Number of depth 2 voxels covered by occupied leaves
+    fn voxel_count(octree: &Octree) -> f32 {
+        octree.leaves().map(|(_, half_size, _)| (half_size * 4.0).powi(3)).sum()
+    }
+
+    #[test]
+    fn remove_prunes_back_to_an_empty_root() {
+        let mut octree = octree(2);
+        let wall = VoxelData::solid([0.73; 3]);
+        let a = na::Vector3::new(0.75, 0.75, 0.75);
+        let b = na::Vector3::new(-0.25, 0.25, -0.75);
+        octree.insert(a, wall);
+        octree.insert(b, wall);
+
+        assert_eq!(octree.remove(a), Some(wall));
+        assert_eq!(octree.remove(a), None);
+        assert_eq!(octree.sample(a, 1), VoxelData::empty());
+        assert_eq!(octree.root.voxel_data.unwrap().density, 1.0 / 64.0);
+
+        assert_eq!(octree.remove(b), Some(wall));
+        assert!(octree.root.is_leaf());
+        assert_eq!(octree.root.voxel_data, None);
+        assert_eq!(octree.node_count(), 1);
+    }
+
+    #[test]
+    fn remove_splits_a_collapsed_leaf() {
+        let wall = VoxelData::solid([0.73; 3]);
+        let mut octree = solid_octree(wall);
+        let position = na::Vector3::new(0.75, -0.75, 0.25);
+
+        assert_eq!(octree.remove(position), Some(wall));
+        assert_eq!(octree.sample(position, 0), VoxelData::empty());
+        assert_eq!(voxel_count(&octree), 63.0);
+        assert_eq!(octree.node_count(), 1 + 8 + 8);
+        assert_eq!(octree.root.voxel_data.unwrap().density, 7.875 / 8.0);
+    }
+
+    #[test]
+    fn clear_regions() {
+        let wall = VoxelData::solid([0.73; 3]);
+        let mut octree = solid_octree(wall);
+
+        // Half the volume, including the voxels whose centers sit on the box face
+        octree.clear_aabb(na::Vector3::new(-2.0, -2.0, -2.0), na::Vector3::new(-0.25, 2.0, 2.0));
+        assert_eq!(voxel_count(&octree), 32.0);
+        assert_eq!(octree.sample(na::Vector3::new(-0.25, 0.25, 0.25), 0), VoxelData::empty());
+        assert_eq!(octree.sample(na::Vector3::new(0.25, 0.25, 0.25), 0), wall);
+
+        // Only the voxel at (0.75, 0.75, 0.75) has its center this close
+        octree.clear_sphere(na::Vector3::new(0.8, 0.8, 0.8), 0.2);
+        assert_eq!(voxel_count(&octree), 31.0);
+
+        octree.clear_sphere(na::Vector3::zeros(), 2.0);
+        assert!(octree.root.is_leaf());
+        assert_eq!(octree.root.voxel_data, None);
+    }
+
+    #[test]
+    fn from_cells_rejects_spans_past_max_depth() {
+        let wall = VoxelData::solid([0.73; 3]);
+        let cells = |far: i32| [(na::Vector3::new(-1, 0, 0), wall), (na::Vector3::new(far, 0, 0), wall)];
+
+        let octree = Octree::from_cells(&cells((1 << MAX_DEPTH) - 2), 0.5).unwrap();
+        assert_eq!(octree.max_depth, MAX_DEPTH);
+        assert!(Octree::from_cells(&cells((1 << MAX_DEPTH) - 1), 0.5).is_err());
+        assert!(Octree::from_cells(&[(na::Vector3::repeat(i32::MIN), wall), (na::Vector3::repeat(i32::MAX), wall)], 0.5).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use super::static_provider::VolumeTexels;
+use rayon::prelude::*;
+
+/// Max-density mip chain over a dense `size`³ volume, `size` a power of two.
+/// Level 0 holds each texel's density and every coarser level the maximum of
+/// the 2³ texels below it, down to a single texel. A zero at any level means
+/// the whole box it covers is empty, so the shader can leap over it at once.
+pub(super) struct OccupancyPyramid {
+    size: u32,
+    levels: Vec<Vec<u8>>,
+}
+
+impl OccupancyPyramid {
+    pub(super) fn level_count(size: u32) -> u32 {
+        size.ilog2() + 1
+    }
+
+    /// Pyramid over a volume baked in one piece
+    pub(super) fn from_texels(size: u32, texels: &VolumeTexels) -> Self {
+        let mut levels = vec![texels.colors.chunks_exact(4).map(|color| color[3]).collect()];
+        for level in 1..Self::level_count(size) {
+            levels.push(vec![0; ((size >> level) as usize).pow(3)]);
+        }
+
+        let mut pyramid = Self { size, levels };
+        for level in 1..Self::level_count(size) {
+            pyramid.reduce(level, [0; 3], [pyramid.level_size(level); 3]);
+        }
+        pyramid
+    }
+
+    /// Texels along each axis of a level
+    pub(super) fn level_size(&self, level: u32) -> u32 {
+        self.size >> level
+    }
+
+    /// Copy the densities of a re-baked box of level 0 in and refresh the
+    /// levels above it. Returns the origin and extent touched on each level,
+    /// finest first.
+    pub(super) fn update(&mut self, origin: [u32; 3], extent: [u32; 3], texels: &VolumeTexels) -> Vec<([u32; 3], [u32; 3])> {
+        let size = self.size as usize;
+        let mut densities = texels.colors.chunks_exact(4).map(|color| color[3]);
+        for z in origin[2]..origin[2] + extent[2] {
+            for y in origin[1]..origin[1] + extent[1] {
+                let row = (z as usize * size + y as usize) * size;
+                for x in origin[0]..origin[0] + extent[0] {
+                    self.levels[0][row + x as usize] = densities.next().unwrap();
+                }
+            }
+        }
+
+        let mut min = origin;
+        let mut max = [0, 1, 2].map(|axis| origin[axis] + extent[axis] - 1);
+        let mut touched = vec![(origin, extent)];
+        for level in 1..Self::level_count(self.size) {
+            min = min.map(|texel| texel / 2);
+            max = max.map(|texel| texel / 2);
+            let extent = [0, 1, 2].map(|axis| max[axis] - min[axis] + 1);
+            self.reduce(level, min, extent);
+            touched.push((min, extent));
+        }
+        touched
+    }
+
+    /// Texels of a box on one level, x fastest
+    pub(super) fn region(&self, level: u32, origin: [u32; 3], extent: [u32; 3]) -> Vec<u8> {
+        let size = self.level_size(level) as usize;
+        let data = &self.levels[level as usize];
+        let mut texels = Vec::with_capacity(extent.iter().map(|&e| e as usize).product());
+        for z in origin[2]..origin[2] + extent[2] {
+            for y in origin[1]..origin[1] + extent[1] {
+                let row = (z as usize * size + y as usize) * size;
+                texels.extend_from_slice(&data[row + origin[0] as usize..row + (origin[0] + extent[0]) as usize]);
+            }
+        }
+        texels
+    }
+
+    /// Recompute a box of `level` from the level below, one z slice per thread
+    fn reduce(&mut self, level: u32, origin: [u32; 3], extent: [u32; 3]) {
+        let size = self.level_size(level) as usize;
+        let (finer, coarser) = self.levels.split_at_mut(level as usize);
+        let below = &finer[level as usize - 1];
+
+        coarser[0].par_chunks_mut(size * size)
+            .enumerate()
+            .skip(origin[2] as usize)
+            .take(extent[2] as usize)
+            .for_each(|(z, slice)| {
+                for y in origin[1] as usize..(origin[1] + extent[1]) as usize {
+                    for x in origin[0] as usize..(origin[0] + extent[0]) as usize {
+                        let mut density = 0;
+                        for child in 0..8 {
+                            let (cx, cy, cz) = (2 * x + (child & 1), 2 * y + (child >> 1 & 1), 2 * z + (child >> 2));
+                            density = density.max(below[(cz * 2 * size + cy) * 2 * size + cx]);
+                        }
+                        slice[y * size + x] = density;
+                    }
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 16;
+
+    /// Texels with the density `density` gives them, x fastest
+    fn texels(size: u32, density: impl Fn([u32; 3]) -> u8) -> VolumeTexels {
+        let mut colors = Vec::new();
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    colors.extend_from_slice(&[200, 200, 200, density([x, y, z])]);
+                }
+            }
+        }
+        let materials = vec![0; colors.len() / 4];
+        VolumeTexels { colors, materials }
+    }
+
+    /// A sphere of mixed densities and a few scattered texels
+    fn scene([x, y, z]: [u32; 3]) -> u8 {
+        let dx = x as f32 - 5.5;
+        let dy = y as f32 - 6.0;
+        let dz = z as f32 - 9.0;
+        if dx * dx + dy * dy + dz * dz < 12.0 {
+            (60 + 40 * (x % 4)) as u8
+        } else if [x, y, z] == [13, 2, 11] || [x, y, z] == [1, 14, 3] {
+            255
+        } else {
+            0
+        }
+    }
+
+    #[test]
+    fn updates_match_a_full_build() {
+        let mut pyramid = OccupancyPyramid::from_texels(SIZE, &texels(SIZE, scene));
+        let levels_before = pyramid.levels.clone();
+
+        // Carve into the sphere and fill open space in one unaligned edit
+        let origin = [3, 5, 7];
+        let extent = [7, 2, 6];
+        let edited = |texel: [u32; 3]| {
+            let in_box = (0..3).all(|axis| (origin[axis]..origin[axis] + extent[axis]).contains(&texel[axis]));
+            if in_box { if texel[0] >= 7 { 30 } else { 0 } } else { scene(texel) }
+        };
+        let after = texels(SIZE, edited);
+        let mut region = VolumeTexels { colors: Vec::new(), materials: Vec::new() };
+        for z in origin[2]..origin[2] + extent[2] {
+            for y in origin[1]..origin[1] + extent[1] {
+                for x in origin[0]..origin[0] + extent[0] {
+                    let i = ((z * SIZE + y) * SIZE + x) as usize;
+                    region.colors.extend_from_slice(&after.colors[i * 4..i * 4 + 4]);
+                    region.materials.push(0);
+                }
+            }
+        }
+        let touched = pyramid.update(origin, extent, &region);
+        assert_eq!(touched.len(), OccupancyPyramid::level_count(SIZE) as usize);
+
+        let rebuilt = OccupancyPyramid::from_texels(SIZE, &after);
+        for (level, (changed_origin, changed_extent)) in touched.into_iter().enumerate() {
+            assert_eq!(pyramid.levels[level], rebuilt.levels[level], "level {}", level);
+
+            let size = pyramid.level_size(level as u32);
+            for i in 0..size * size * size {
+                let texel = [i % size, i / size % size, i / (size * size)];
+                let inside = (0..3).all(|axis| {
+                    (changed_origin[axis]..changed_origin[axis] + changed_extent[axis]).contains(&texel[axis])
+                });
+                if !inside {
+                    assert_eq!(pyramid.levels[level][i as usize], levels_before[level][i as usize],
+                               "level {} texel {:?} changed outside the reported box", level, texel);
+                }
+            }
+        }
+        assert_ne!(pyramid.levels, levels_before);
+    }
+}
@@ -0,0 +1,146 @@
+use super::{Octree, OctreeNode, Overlap, Region, VoxelData};
+use nalgebra as na;
+
+/// Occupied leaf as `(center, half_size, data)`
+pub type Leaf = (na::Vector3<f32>, f32, VoxelData);
+
+/// Depth-first iterator over occupied leaves, optionally restricted to a region.
+/// Subtrees that do not touch the region are never visited; leaves are yielded
+/// when their cube overlaps the region, so collapsed leaves may extend past it.
+pub struct LeafIter<'a> {
+    stack: Vec<&'a OctreeNode>,
+    region: Option<Region>,
+}
+
+impl<'a> LeafIter<'a> {
+    fn new(root: &'a OctreeNode, region: Option<Region>) -> Self {
+        Self {
+            stack: vec![root],
+            region,
+        }
+    }
+}
+
+impl Iterator for LeafIter<'_> {
+    type Item = Leaf;
+
+    fn next(&mut self) -> Option<Leaf> {
+        while let Some(node) = self.stack.pop() {
+            if let Some(ref region) = self.region
+                && region.classify(&node.center, node.half_size) == Overlap::Outside {
+                continue;
+            }
+
+            match node.children {
+                Some(ref children) => self.stack.extend(children.iter().rev()),
+                None => {
+                    if let Some(data) = node.voxel_data {
+                        return Some((node.center, node.half_size, data));
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Octree {
+    /// Every occupied leaf in the octree
+    pub fn leaves(&self) -> LeafIter<'_> {
+        LeafIter::new(&self.root, None)
+    }
+
+    /// Occupied leaves overlapping the box `min..=max`
+    pub fn query_aabb(&self, min: na::Vector3<f32>, max: na::Vector3<f32>) -> LeafIter<'_> {
+        LeafIter::new(&self.root, Some(Region::Aabb { min, max }))
+    }
+
+    /// Occupied leaves overlapping the sphere
+    pub fn query_sphere(&self, center: na::Vector3<f32>, radius: f32) -> LeafIter<'_> {
+        LeafIter::new(&self.root, Some(Region::Sphere { center, radius }))
+    }
+
+    /// Occupied leaves referencing entry `material` of the octree's material table
+    pub fn query_material(&self, material: u32) -> impl Iterator<Item = Leaf> + '_ {
+        self.leaves().filter(move |(_, _, data)| data.material == material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Depth 3 octree with a scattered pattern of voxels over three materials,
+    /// plus a solid octant that collapses into a single coarse leaf
+    fn scene() -> Octree {
+        let mut octree = Octree::new(na::Vector3::zeros(), 1.0, 3);
+        for i in 0..8 {
+            for j in 0..8 {
+                for k in 0..8 {
+                    let position = na::Vector3::new(i, j, k).map(|v| v as f32 * 0.25 - 0.875);
+                    let material = (i + j + k) as u32 % 3;
+                    if i < 4 && j < 4 && k < 4 {
+                        octree.insert(position, VoxelData::solid([0.73; 3]));
+                    } else if (i * 7 + j * 3 + k) % 5 == 0 {
+                        octree.insert(position, VoxelData { material, ..VoxelData::solid([0.5; 3]) });
+                    }
+                }
+            }
+        }
+        octree
+    }
+
+    /// Closest point of a leaf's cube to `point`
+    fn closest(leaf: &Leaf, point: na::Vector3<f32>) -> na::Vector3<f32> {
+        let (center, half_size, _) = leaf;
+        point.sup(&center.add_scalar(-half_size)).inf(&center.add_scalar(*half_size))
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let octree = scene();
+        let leaves: Vec<Leaf> = octree.leaves().collect();
+        assert!(leaves.iter().any(|(_, half_size, _)| *half_size > 0.125));
+
+        let boxes = [
+            (na::Vector3::new(-0.3, -0.6, -1.0), na::Vector3::new(0.4, 0.1, 0.2)),
+            (na::Vector3::new(-2.0, -2.0, -2.0), na::Vector3::new(2.0, 2.0, 2.0)),
+            (na::Vector3::new(0.5, 0.5, 0.5), na::Vector3::new(0.6, 0.6, 0.6)),
+        ];
+        for (min, max) in boxes {
+            let expected: Vec<Leaf> = leaves.iter()
+                .filter(|(center, half_size, _)| {
+                    (0..3).all(|i| center[i] + half_size >= min[i] && center[i] - half_size <= max[i])
+                })
+                .copied()
+                .collect();
+            assert_eq!(octree.query_aabb(min, max).collect::<Vec<_>>(), expected);
+        }
+
+        for (center, radius) in [(na::Vector3::new(0.1, -0.2, 0.3), 0.45), (na::Vector3::new(-1.2, 0.0, 0.0), 0.3)] {
+            let expected: Vec<Leaf> = leaves.iter()
+                .filter(|leaf| (closest(leaf, center) - center).norm() <= radius)
+                .copied()
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(octree.query_sphere(center, radius).collect::<Vec<_>>(), expected);
+        }
+
+        for material in 0..3 {
+            let expected: Vec<Leaf> = leaves.iter().filter(|(_, _, data)| data.material == material).copied().collect();
+            assert!(!expected.is_empty());
+            assert_eq!(octree.query_material(material).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn leaves_cover_every_occupied_voxel() {
+        let octree = scene();
+        let volume: f32 = octree.leaves().map(|(_, half_size, _)| (half_size * 8.0).powi(3)).sum();
+        let occupied = (0..512)
+            .map(|index| na::Vector3::new(index % 8, index / 8 % 8, index / 64).map(|v| v as f32 * 0.25 - 0.875))
+            .filter(|position| octree.sample(*position, 0).density > 0.0)
+            .count();
+        assert_eq!(volume, occupied as f32);
+    }
+}
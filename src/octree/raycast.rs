@@ -0,0 +1,244 @@
+use super::{Octree, OctreeNode, VoxelData};
+use nalgebra as na;
+
+/// Result of a successful `Octree::raycast`
+#[derive(Copy, Clone, Debug)]
+pub struct Hit {
+    pub position: na::Vector3<f32>,
+    /// Normal of the face the ray entered through; zero if the ray starts inside the voxel
+    pub normal: na::Vector3<f32>,
+    pub t: f32,
+    /// Octree level of the node that was hit (collapsed leaves sit above `max_depth`)
+    pub level: u8,
+    pub data: VoxelData,
+}
+
+/// Ray with precomputed reciprocal direction for slab tests
+struct Ray {
+    origin: na::Vector3<f32>,
+    direction: na::Vector3<f32>,
+    inv_direction: na::Vector3<f32>,
+    /// Child index bits of the axes the ray runs down
+    octant_mask: usize,
+}
+
+impl Ray {
+    /// Slab test against a node's cube, returning entry t, exit t and entry axis
+    fn intersect(&self, node: &OctreeNode) -> Option<(f32, f32, usize)> {
+        let mut t_enter = f32::NEG_INFINITY;
+        let mut t_exit = f32::INFINITY;
+        let mut axis = 0;
+
+        for i in 0..3 {
+            let near = node.center[i] - node.half_size;
+            let far = node.center[i] + node.half_size;
+
+            if self.direction[i] == 0.0 {
+                // Parallel to this slab: either always inside or never
+                if self.origin[i] < near || self.origin[i] > far {
+                    return None;
+                }
+                continue;
+            }
+
+            let t0 = (near - self.origin[i]) * self.inv_direction[i];
+            let t1 = (far - self.origin[i]) * self.inv_direction[i];
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if t0 > t_enter {
+                t_enter = t0;
+                axis = i;
+            }
+            t_exit = t_exit.min(t1);
+        }
+
+        if t_enter > t_exit {
+            return None;
+        }
+        Some((t_enter, t_exit, axis))
+    }
+}
+
+impl Octree {
+    /// Cast a ray through the octree and return the first occupied voxel it hits.
+    /// `direction` does not need to be normalized; `t` is measured in world units.
+    pub fn raycast(&self, origin: na::Vector3<f32>, direction: na::Vector3<f32>, max_t: f32) -> Option<Hit> {
+        let length = direction.norm();
+        if length == 0.0 {
+            return None;
+        }
+
+        let direction = direction / length;
+        let ray = Ray {
+            origin,
+            direction,
+            inv_direction: direction.map(|d| 1.0 / d),
+            octant_mask: (0..3).filter(|&i| direction[i] < 0.0).fold(0, |mask, i| mask | 1 << i),
+        };
+
+        Self::raycast_recursive(&self.root, &ray, max_t)
+    }
+
+    fn raycast_recursive(node: &OctreeNode, ray: &Ray, max_t: f32) -> Option<Hit> {
+        let (t_enter, t_exit, axis) = ray.intersect(node)?;
+        if t_exit < 0.0 || t_enter > max_t {
+            return None;
+        }
+
+        let Some(ref children) = node.children else {
+            let data = node.voxel_data.filter(|data| data.density > 0.0)?;
+            let t = t_enter.max(0.0);
+            let mut normal = na::Vector3::zeros();
+            if t_enter >= 0.0 {
+                normal[axis] = -ray.direction[axis].signum();
+            }
+
+            return Some(Hit {
+                position: ray.origin + ray.direction * t,
+                normal,
+                t,
+                level: node.level,
+                data,
+            });
+        };
+
+        // Visit the children front to back. With the octant bits flipped for the
+        // axes the ray runs down, it only ever crosses into children with more
+        // bits set, so ascending order never visits one before a child in front
+        (0..8).find_map(|i| Self::raycast_recursive(&children[i ^ ray.octant_mask], ray, max_t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::test_scenes::Rng;
+
+    /// Octree over the cube -1..1 with voxels of size 0.5
+    fn octree() -> Octree {
+        Octree::new(na::Vector3::zeros(), 1.0, 2)
+    }
+
+    #[test]
+    fn axis_aligned_hits_report_the_entry_face() {
+        let mut octree = octree();
+        let wall = VoxelData::solid([0.73; 3]);
+        octree.insert(na::Vector3::new(0.75, 0.25, 0.25), wall);
+
+        let hit = octree.raycast(na::Vector3::new(-2.0, 0.25, 0.25), na::Vector3::new(3.0, 0.0, 0.0), 10.0).unwrap();
+        assert_eq!(hit.t, 2.5);
+        assert_eq!(hit.position, na::Vector3::new(0.5, 0.25, 0.25));
+        assert_eq!(hit.normal, na::Vector3::new(-1.0, 0.0, 0.0));
+        assert_eq!(hit.level, 2);
+        assert_eq!(hit.data, wall);
+
+        let hit = octree.raycast(na::Vector3::new(2.0, 0.25, 0.25), na::Vector3::new(-1.0, 0.0, 0.0), 10.0).unwrap();
+        assert_eq!(hit.t, 1.0);
+        assert_eq!(hit.normal, na::Vector3::new(1.0, 0.0, 0.0));
+
+        let hit = octree.raycast(na::Vector3::new(0.75, -2.0, 0.25), na::Vector3::new(0.0, 1.0, 0.0), 10.0).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.normal, na::Vector3::new(0.0, -1.0, 0.0));
+    }
+
+    #[test]
+    fn nearest_voxel_wins_in_either_direction() {
+        let mut octree = octree();
+        let near = VoxelData::solid([1.0, 0.0, 0.0]);
+        let far = VoxelData::solid([0.0, 1.0, 0.0]);
+        octree.insert(na::Vector3::new(-0.75, -0.25, 0.75), near);
+        octree.insert(na::Vector3::new(0.75, -0.25, 0.75), far);
+
+        let origin = na::Vector3::new(-2.0, -0.25, 0.75);
+        assert_eq!(octree.raycast(origin, na::Vector3::new(1.0, 0.0, 0.0), 10.0).unwrap().data, near);
+        let origin = na::Vector3::new(2.0, -0.25, 0.75);
+        assert_eq!(octree.raycast(origin, na::Vector3::new(-1.0, 0.0, 0.0), 10.0).unwrap().data, far);
+
+        // Diagonal ray crossing both
+        let hit = octree.raycast(na::Vector3::new(-1.5, -0.5, 0.75), na::Vector3::new(3.0, 0.5, 0.0), 10.0).unwrap();
+        assert_eq!(hit.data, near);
+        assert_eq!(hit.normal, na::Vector3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn origin_inside_a_voxel() {
+        let mut octree = octree();
+        octree.insert(na::Vector3::new(0.75, 0.25, 0.25), VoxelData::solid([0.73; 3]));
+
+        let origin = na::Vector3::new(0.7, 0.3, 0.2);
+        let hit = octree.raycast(origin, na::Vector3::new(0.0, 0.0, 1.0), 10.0).unwrap();
+        assert_eq!(hit.t, 0.0);
+        assert_eq!(hit.position, origin);
+        assert_eq!(hit.normal, na::Vector3::zeros());
+    }
+
+    #[test]
+    fn max_t_cuts_off_hits() {
+        let mut octree = octree();
+        octree.insert(na::Vector3::new(0.75, 0.25, 0.25), VoxelData::solid([0.73; 3]));
+
+        let origin = na::Vector3::new(-2.0, 0.25, 0.25);
+        let direction = na::Vector3::new(1.0, 0.0, 0.0);
+        assert!(octree.raycast(origin, direction, 2.4).is_none());
+        assert_eq!(octree.raycast(origin, direction, 2.5).unwrap().t, 2.5);
+    }
+
+    #[test]
+    fn collapsed_leaves_are_hit_at_their_level() {
+        let mut octree = octree();
+        let wall = VoxelData::solid([0.73; 3]);
+        for index in 0..8 {
+            let offset = na::Vector3::new(index & 1, index >> 1 & 1, index >> 2 & 1).map(|v| v as f32 * 0.5);
+            octree.insert(na::Vector3::new(0.25, 0.25, 0.25) + offset, wall);
+        }
+
+        let hit = octree.raycast(na::Vector3::new(0.6, 0.4, -3.0), na::Vector3::new(0.0, 0.0, 1.0), 10.0).unwrap();
+        assert_eq!(hit.level, 1);
+        assert_eq!(hit.t, 3.0);
+        assert_eq!(hit.normal, na::Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn misses() {
+        let mut octree = octree();
+        octree.insert(na::Vector3::new(0.75, 0.25, 0.25), VoxelData::solid([0.73; 3]));
+
+        // Pointing away, passing beside the voxel, outside the root, and no direction at all
+        let origin = na::Vector3::new(-2.0, 0.25, 0.25);
+        assert!(octree.raycast(origin, na::Vector3::new(-1.0, 0.0, 0.0), 10.0).is_none());
+        assert!(octree.raycast(na::Vector3::new(-2.0, -0.25, 0.25), na::Vector3::new(1.0, 0.0, 0.0), 10.0).is_none());
+        assert!(octree.raycast(na::Vector3::new(-2.0, 3.0, 0.25), na::Vector3::new(1.0, 0.0, 0.0), 10.0).is_none());
+        assert!(octree.raycast(origin, na::Vector3::zeros(), 10.0).is_none());
+    }
+
+    #[test]
+    fn matches_brute_force_over_leaves() {
+        let mut rng = Rng::new(0x2545f491);
+        let mut random = move || rng.unit();
+
+        let mut octree = Octree::new(na::Vector3::zeros(), 1.0, 4);
+        for _ in 0..300 {
+            let position = na::Vector3::new(random(), random(), random()).map(|v| v * 2.0 - 1.0);
+            octree.insert(position, VoxelData::solid([random(); 3]));
+        }
+        let leaves: Vec<_> = octree.leaves().collect();
+
+        for _ in 0..500 {
+            let origin = na::Vector3::new(random(), random(), random()).map(|v| v * 6.0 - 3.0);
+            let direction = na::Vector3::new(random(), random(), random()).map(|v| v * 2.0 - 1.0).normalize();
+            let ray = Ray { origin, direction, inv_direction: direction.map(|d| 1.0 / d), octant_mask: 0 };
+
+            let nearest = leaves.iter()
+                .filter_map(|(center, half_size, _)| {
+                    let node = OctreeNode::new(*center, *half_size, 0);
+                    ray.intersect(&node).filter(|&(_, t_exit, _)| t_exit >= 0.0).map(|(t, _, _)| t.max(0.0))
+                })
+                .min_by(f32::total_cmp);
+            // Neighbouring leaves can share the entry point up to rounding
+            match (octree.raycast(origin, direction, f32::INFINITY), nearest) {
+                (Some(hit), Some(t)) => assert!((hit.t - t).abs() < 1e-5, "{} != {}", hit.t, t),
+                (hit, t) => assert_eq!(hit.map(|hit| hit.t), t),
+            }
+        }
+    }
+}
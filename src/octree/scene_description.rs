@@ -0,0 +1,1010 @@
+use super::{Octree, VoxelData};
+use super::instancing::{InstancedScene, Transform, VoxelInstance};
+use super::material::{Material, MaterialTable};
+use log::info;
+use nalgebra as na;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::time::Instant;
+
+/// Text scene format. One statement per line, `#` starts a comment:
+///
+/// ```text
+/// bounds center 0 0 0 half_size 2 max_depth 8
+/// camera eye 0 1 -3.8 target 0 1 1
+/// material white color 0.73 0.73 0.73
+/// material lamp color 1 1 0.95 emission 5 5 4.75
+/// material brass color 0.9 0.7 0.3 metalness 1 roughness 0.3
+///
+/// box min -1 -0.05 0 max 1 0.05 2 material white
+/// box center 0 0.3 1 size 0.3 0.6 0.3 rotate 0 -17 0 material white
+/// sphere center 0 1 1 radius 0.25 color 0.9 0.9 0.2 type metal
+/// plane normal 0 1 0 offset 0 thickness 0.05 material white
+/// subtract {
+///     box center 0 1 1 size 0.5 0.5 0.5 material white
+///     sphere center 0 1 1 radius 0.3
+/// }
+///
+/// model crate center 0 0 0 half_size 0.25 max_depth 5 {
+///     box center 0 0 0 size 0.4 0.4 0.4 material white
+/// }
+/// instance crate translate 0.5 0.2 1 rotate 0 30 0 scale 1.5
+/// ```
+///
+/// Boxes take either `min`/`max` or `center`/`size`, plus an optional `rotate`
+/// (XYZ Euler angles in degrees). Planes are slabs of `thickness` around
+/// `normal · p = offset`. `union { ... }` and `subtract { ... }` group shapes;
+/// subtract keeps the first shape minus all the others. Shapes take their look
+/// from a named `material`, overridden by inline properties: `color`, `type`
+/// (`diffuse`, `metal`, `glass`, `emissive` presets), `emission` with an
+/// optional `strength` (1 by default), `roughness`, `metalness`, `ior` and
+/// `transmission`. Where shapes overlap the one listed first wins.
+///
+/// A `model` groups shapes in their own space and extent (`bounds` properties,
+/// defaulting like `bounds`); each `instance` places it with an optional
+/// `translate`, `rotate` (degrees, as for boxes) and uniform `scale`. Shapes
+/// outside models win over instances.
+pub struct SceneDescription {
+    pub bounds: SceneBounds,
+    pub camera: Option<SceneCamera>,
+    pub shapes: Vec<Shape>,
+    pub models: Vec<SceneModel>,
+    pub instances: Vec<SceneInstance>,
+    /// Palette the shapes' voxels refer to, one entry per distinct look
+    pub materials: MaterialTable,
+}
+
+/// Deepest `max_depth` scene bounds may ask for; voxelizing samples every
+/// finest-level cell the shapes cover
+pub const MAX_SCENE_DEPTH: u8 = 12;
+
+/// Octree extent the scene is voxelized into
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SceneBounds {
+    pub center: na::Vector3<f32>,
+    pub half_size: f32,
+    pub max_depth: u8,
+}
+
+impl Default for SceneBounds {
+    fn default() -> Self {
+        Self {
+            center: na::Vector3::zeros(),
+            half_size: 2.0,
+            max_depth: 8,
+        }
+    }
+}
+
+/// Shapes voxelized into an octree of their own, to be placed by instances
+#[derive(Clone, Debug)]
+pub struct SceneModel {
+    pub name: String,
+    pub bounds: SceneBounds,
+    pub shapes: Vec<Shape>,
+}
+
+impl SceneModel {
+    /// Material at a position in the model's own space
+    pub fn sample(&self, position: &na::Vector3<f32>) -> Option<VoxelData> {
+        let offset = position - self.bounds.center;
+        if offset.iter().any(|v| v.abs() > self.bounds.half_size) {
+            return None;
+        }
+        self.shapes.iter().find_map(|shape| shape.sample(position))
+    }
+}
+
+/// Placement of a model
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SceneInstance {
+    /// Index into `SceneDescription::models`
+    pub model: usize,
+    pub transform: Transform,
+}
+
+/// Default viewpoint stored with the scene
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SceneCamera {
+    pub eye: na::Point3<f32>,
+    pub target: na::Point3<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Shape {
+    /// Box with half extents `half_size`, rotated about its center
+    Cuboid {
+        center: na::Vector3<f32>,
+        half_size: na::Vector3<f32>,
+        rotation: na::Rotation3<f32>,
+        material: VoxelData,
+    },
+    Sphere {
+        center: na::Vector3<f32>,
+        radius: f32,
+        material: VoxelData,
+    },
+    /// Slab of `thickness` centered on the plane `normal · p = offset`
+    Plane {
+        normal: na::Vector3<f32>,
+        offset: f32,
+        thickness: f32,
+        material: VoxelData,
+    },
+    Union(Vec<Shape>),
+    /// Points inside the first shape and outside all of the others
+    Subtract(Box<Shape>, Vec<Shape>),
+}
+
+impl Shape {
+    /// Material at `position`, or `None` when the point is outside the shape
+    pub fn sample(&self, position: &na::Vector3<f32>) -> Option<VoxelData> {
+        match self {
+            Shape::Cuboid { center, half_size, rotation, material } => {
+                let local = rotation.inverse_transform_vector(&(position - center));
+                let inside = (0..3).all(|axis| local[axis].abs() <= half_size[axis]);
+                inside.then_some(*material)
+            }
+            Shape::Sphere { center, radius, material } => {
+                ((position - center).norm_squared() <= radius * radius).then_some(*material)
+            }
+            Shape::Plane { normal, offset, thickness, material } => {
+                ((normal.dot(position) - offset).abs() <= thickness * 0.5).then_some(*material)
+            }
+            Shape::Union(shapes) => shapes.iter().find_map(|shape| shape.sample(position)),
+            Shape::Subtract(base, cutters) => {
+                if cutters.iter().any(|cutter| cutter.sample(position).is_some()) {
+                    None
+                } else {
+                    base.sample(position)
+                }
+            }
+        }
+    }
+
+    /// Axis-aligned bounds as `(min, max)`, or `None` for unbounded shapes
+    pub fn bounds(&self) -> Option<(na::Vector3<f32>, na::Vector3<f32>)> {
+        match self {
+            Shape::Cuboid { center, half_size, rotation, .. } => {
+                let extent = rotation.matrix().abs() * half_size;
+                Some((center - extent, center + extent))
+            }
+            Shape::Sphere { center, radius, .. } => {
+                Some((center.add_scalar(-radius), center.add_scalar(*radius)))
+            }
+            Shape::Plane { .. } => None,
+            Shape::Union(shapes) => shapes.iter().try_fold(
+                (na::Vector3::repeat(f32::MAX), na::Vector3::repeat(f32::MIN)),
+                |(min, max), shape| shape.bounds().map(|(lo, hi)| (min.inf(&lo), max.sup(&hi))),
+            ),
+            Shape::Subtract(base, _) => base.bounds(),
+        }
+    }
+}
+
+impl SceneDescription {
+    /// The Cornell box shipped with the renderer
+    pub fn cornell_box() -> Self {
+        Self::parse(include_str!("../../scenes/cornell_box.scene"))
+            .expect("built-in Cornell box scene must parse")
+    }
+
+    /// Read and parse a scene file; parse errors carry the file name, line and column
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text)
+            .map_err(|error| Error::new(ErrorKind::InvalidData, format!("{}:{}", path.display(), error)))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        Parser::new(text).parse_document()
+    }
+
+    /// Material at `position`, taken from the first shape that contains it,
+    /// then from the first instance whose model has a voxel there
+    pub fn sample(&self, position: &na::Vector3<f32>) -> Option<VoxelData> {
+        self.shapes.iter().find_map(|shape| shape.sample(position)).or_else(|| {
+            self.instances.iter().find_map(|instance| {
+                self.models[instance.model].sample(&instance.transform.to_object(position))
+            })
+        })
+    }
+
+    /// World-space box around an instance's model
+    pub fn instance_bounds(&self, instance: &SceneInstance) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let bounds = &self.models[instance.model].bounds;
+        instance.transform.world_bounds(&bounds.center, bounds.half_size)
+    }
+
+    /// Sample every finest-level cell center inside the shapes' and instances'
+    /// bounds in parallel and bulk-build an octree spanning the scene bounds
+    /// from the occupied ones. Instances are baked into the world octree.
+    /// Fails when bounds are deeper than `MAX_SCENE_DEPTH`.
+    pub fn voxelize(&self) -> io::Result<Octree> {
+        let scene_min = self.bounds.center.add_scalar(-self.bounds.half_size);
+        let scene_max = self.bounds.center.add_scalar(self.bounds.half_size);
+
+        // Unbounded shapes (planes) fall back to the whole scene
+        let regions = self.shapes.iter()
+            .map(|shape| shape.bounds().unwrap_or((scene_min, scene_max)))
+            .chain(self.instances.iter().map(|instance| self.instance_bounds(instance)));
+        let mut octree = voxelize_region(self.bounds, regions, |position| self.sample(position))?;
+        octree.materials = self.materials.clone();
+        Ok(octree)
+    }
+
+    /// Voxelize the loose shapes and every model into assets of their own and
+    /// place them, for `InstancedProvider`. The loose shapes become an asset
+    /// placed where it was built. All assets share the scene's material table.
+    /// Fails when any bounds are deeper than `MAX_SCENE_DEPTH`.
+    pub fn to_instanced(&self) -> io::Result<InstancedScene> {
+        let mut scene = InstancedScene::default();
+
+        if !self.shapes.is_empty() {
+            let scene_min = self.bounds.center.add_scalar(-self.bounds.half_size);
+            let scene_max = self.bounds.center.add_scalar(self.bounds.half_size);
+            let regions = self.shapes.iter().map(|shape| shape.bounds().unwrap_or((scene_min, scene_max)));
+            scene.assets.push(voxelize_region(self.bounds, regions, |position| {
+                self.shapes.iter().find_map(|shape| shape.sample(position))
+            })?);
+            scene.instances.push(VoxelInstance { asset: 0, transform: Transform::default() });
+        }
+
+        let first_model = scene.assets.len();
+        for model in &self.models {
+            let model_min = model.bounds.center.add_scalar(-model.bounds.half_size);
+            let model_max = model.bounds.center.add_scalar(model.bounds.half_size);
+            let regions = model.shapes.iter().map(|shape| shape.bounds().unwrap_or((model_min, model_max)));
+            scene.assets.push(voxelize_region(model.bounds, regions, |position| model.sample(position))?);
+        }
+        scene.instances.extend(self.instances.iter().map(|instance| VoxelInstance {
+            asset: first_model + instance.model,
+            transform: instance.transform,
+        }));
+
+        for asset in &mut scene.assets {
+            asset.materials = self.materials.clone();
+        }
+        Ok(scene)
+    }
+}
+
+/// Sample every finest-level cell center of `bounds` that lies inside one of
+/// `regions` in parallel and bulk-build an octree from the occupied ones
+fn voxelize_region(
+    bounds: SceneBounds,
+    regions: impl Iterator<Item = (na::Vector3<f32>, na::Vector3<f32>)>,
+    sample: impl Fn(&na::Vector3<f32>) -> Option<VoxelData> + Sync,
+) -> io::Result<Octree> {
+    let SceneBounds { center, half_size, max_depth } = bounds;
+    if !(1..=MAX_SCENE_DEPTH).contains(&max_depth) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("max_depth must be from 1 to {}, got {}", MAX_SCENE_DEPTH, max_depth),
+        ));
+    }
+    let voxel_size = half_size * 2.0 / (1u32 << max_depth) as f32;
+    let scene_min = center.add_scalar(-half_size);
+    let scene_max = center.add_scalar(half_size);
+
+    let (min, max) = regions.fold((scene_max, scene_min), |(min, max), (lo, hi)| (min.inf(&lo), max.sup(&hi)));
+
+    let cells = 1i64 << max_depth;
+    let first = (min - scene_min).map(|v| ((v / voxel_size).floor() as i64).clamp(0, cells) as u32);
+    let last = (max - scene_min).map(|v| ((v / voxel_size).ceil() as i64).clamp(0, cells) as u32);
+
+    // Sample the shapes one x slab per task, then build the tree in one pass
+    let start = Instant::now();
+    let voxels: Vec<([u32; 3], VoxelData)> = (first.x..last.x)
+        .into_par_iter()
+        .flat_map_iter(|x| {
+            (first.y..last.y).flat_map(move |y| (first.z..last.z).map(move |z| [x, y, z]))
+                .filter_map(|cell| {
+                    let cell_center = scene_min + na::Vector3::from(cell).map(|i| (i as f32 + 0.5) * voxel_size);
+                    sample(&cell_center).map(|voxel| (cell, voxel))
+                })
+        })
+        .collect();
+    let sampled = start.elapsed();
+
+    let voxel_count = voxels.len();
+    let octree = Octree::from_voxels(center, half_size, max_depth, voxels)?;
+
+    info!("Voxelized {} voxels ({} nodes): sampled in {:.1?}, built in {:.1?}",
+          voxel_count, octree.node_count(), sampled, start.elapsed() - sampled);
+    Ok(octree)
+}
+
+/// Parse failure with a 1-based source position
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    OpenBrace,
+    CloseBrace,
+    Newline,
+    End,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(word) => format!("`{}`", word),
+            TokenKind::OpenBrace => "`{`".to_string(),
+            TokenKind::CloseBrace => "`}`".to_string(),
+            TokenKind::Newline => "end of line".to_string(),
+            TokenKind::End => "end of file".to_string(),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut line_count = 0;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        line_count = line_number;
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let start = i;
+            let kind = match chars[i] {
+                '#' => break,
+                c if c.is_whitespace() => {
+                    i += 1;
+                    continue;
+                }
+                '{' => {
+                    i += 1;
+                    TokenKind::OpenBrace
+                }
+                '}' => {
+                    i += 1;
+                    TokenKind::CloseBrace
+                }
+                _ => {
+                    while i < chars.len() && !chars[i].is_whitespace() && !matches!(chars[i], '{' | '}' | '#') {
+                        i += 1;
+                    }
+                    TokenKind::Word(chars[start..i].iter().collect())
+                }
+            };
+            tokens.push(Token { kind, line: line_number, column: start + 1 });
+        }
+
+        tokens.push(Token { kind: TokenKind::Newline, line: line_number, column: chars.len() + 1 });
+    }
+
+    tokens.push(Token { kind: TokenKind::End, line: line_count + 1, column: 1 });
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    looks: HashMap<String, Look>,
+    materials: MaterialTable,
+}
+
+impl Parser {
+    fn new(text: &str) -> Self {
+        Self {
+            tokens: tokenize(text),
+            position: 0,
+            looks: HashMap::new(),
+            materials: MaterialTable::default(),
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek().kind == TokenKind::Newline {
+            self.advance();
+        }
+    }
+
+    /// Property keyword, or `None` at the end of the statement
+    fn property(&mut self) -> Option<(String, Token)> {
+        match &self.peek().kind {
+            TokenKind::Word(word) => {
+                let word = word.clone();
+                Some((word, self.advance()))
+            }
+            _ => None,
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<(String, Token), ParseError> {
+        let token = self.advance();
+        match &token.kind {
+            TokenKind::Word(word) => Ok((word.clone(), token)),
+            _ => Err(token.error(format!("expected {}, found {}", what, token.describe()))),
+        }
+    }
+
+    fn number(&mut self) -> Result<f32, ParseError> {
+        let (word, token) = self.word("a number")?;
+        word.parse()
+            .ok()
+            .filter(|value: &f32| value.is_finite())
+            .ok_or_else(|| token.error(format!("expected a number, found `{}`", word)))
+    }
+
+    fn vector(&mut self) -> Result<na::Vector3<f32>, ParseError> {
+        Ok(na::Vector3::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), ParseError> {
+        match self.peek().kind {
+            TokenKind::Newline => {
+                self.advance();
+                Ok(())
+            }
+            TokenKind::CloseBrace | TokenKind::End => Ok(()),
+            _ => Err(self.peek().error(format!("unexpected {}", self.peek().describe()))),
+        }
+    }
+
+    fn parse_document(mut self) -> Result<SceneDescription, ParseError> {
+        let mut scene = SceneDescription {
+            bounds: SceneBounds::default(),
+            camera: None,
+            shapes: Vec::new(),
+            models: Vec::new(),
+            instances: Vec::new(),
+            materials: MaterialTable::default(),
+        };
+
+        loop {
+            self.skip_newlines();
+            let token = self.peek().clone();
+            match &token.kind {
+                TokenKind::End => break,
+                TokenKind::Word(word) if word == "bounds" => {
+                    self.advance();
+                    scene.bounds = self.parse_bounds(&token)?;
+                }
+                TokenKind::Word(word) if word == "camera" => {
+                    self.advance();
+                    scene.camera = Some(self.parse_camera(&token)?);
+                }
+                TokenKind::Word(word) if word == "material" => {
+                    self.advance();
+                    let (name, _) = self.word("a material name")?;
+                    let look = self.parse_material()?;
+                    self.looks.insert(name, look);
+                }
+                TokenKind::Word(word) if word == "model" => {
+                    self.advance();
+                    let (name, name_token) = self.word("a model name")?;
+                    if scene.models.iter().any(|model| model.name == name) {
+                        return Err(name_token.error(format!("model `{}` is already defined", name)));
+                    }
+                    let bounds = self.parse_bounds(&token)?;
+                    let shapes = self.parse_block(&token)?;
+                    scene.models.push(SceneModel { name, bounds, shapes });
+                }
+                TokenKind::Word(word) if word == "instance" => {
+                    self.advance();
+                    let instance = self.parse_instance(&scene.models)?;
+                    scene.instances.push(instance);
+                }
+                _ => scene.shapes.push(self.parse_shape()?),
+            }
+            self.end_of_statement()?;
+        }
+
+        scene.materials = std::mem::take(&mut self.materials);
+        Ok(scene)
+    }
+
+    fn parse_bounds(&mut self, keyword: &Token) -> Result<SceneBounds, ParseError> {
+        let mut bounds = SceneBounds::default();
+        while let Some((key, token)) = self.property() {
+            match key.as_str() {
+                "center" => bounds.center = self.vector()?,
+                "half_size" => bounds.half_size = self.number()?,
+                "max_depth" => {
+                    let depth = self.number()?;
+                    if depth.fract() != 0.0 || !(1.0..=MAX_SCENE_DEPTH as f32).contains(&depth) {
+                        return Err(token.error(format!("max_depth must be a whole number from 1 to {}", MAX_SCENE_DEPTH)));
+                    }
+                    bounds.max_depth = depth as u8;
+                }
+                _ => return Err(token.error(format!("unknown bounds property `{}`", key))),
+            }
+        }
+        if bounds.half_size <= 0.0 {
+            return Err(keyword.error("bounds half_size must be positive"));
+        }
+        Ok(bounds)
+    }
+
+    fn parse_camera(&mut self, keyword: &Token) -> Result<SceneCamera, ParseError> {
+        let (mut eye, mut target) = (None, None);
+        while let Some((key, token)) = self.property() {
+            match key.as_str() {
+                "eye" => eye = Some(self.vector()?),
+                "target" => target = Some(self.vector()?),
+                _ => return Err(token.error(format!("unknown camera property `{}`", key))),
+            }
+        }
+        match (eye, target) {
+            (Some(eye), Some(target)) => Ok(SceneCamera { eye: eye.into(), target: target.into() }),
+            _ => Err(keyword.error("camera needs `eye` and `target`")),
+        }
+    }
+
+    fn parse_instance(&mut self, models: &[SceneModel]) -> Result<SceneInstance, ParseError> {
+        let (name, name_token) = self.word("a model name")?;
+        let model = models.iter()
+            .position(|model| model.name == name)
+            .ok_or_else(|| name_token.error(format!("unknown model `{}`", name)))?;
+
+        let mut transform = Transform::default();
+        while let Some((key, token)) = self.property() {
+            match key.as_str() {
+                "translate" => transform.translation = self.vector()?,
+                "rotate" => {
+                    let degrees = self.vector()?;
+                    transform.rotation = na::Rotation3::from_euler_angles(
+                        degrees.x.to_radians(),
+                        degrees.y.to_radians(),
+                        degrees.z.to_radians(),
+                    );
+                }
+                "scale" => {
+                    transform.scale = self.number()?;
+                    if transform.scale <= 0.0 {
+                        return Err(token.error("instance scale must be positive"));
+                    }
+                }
+                _ => return Err(token.error(format!("unknown instance property `{}`", key))),
+            }
+        }
+        Ok(SceneInstance { model, transform })
+    }
+
+    /// Properties of a `material` statement; a named base material is allowed
+    fn parse_material(&mut self) -> Result<Look, ParseError> {
+        let mut builder = MaterialBuilder::default();
+        while let Some((key, token)) = self.property() {
+            if !builder.property(self, &key)? {
+                return Err(token.error(format!("unknown material property `{}`", key)));
+            }
+        }
+        Ok(builder.look())
+    }
+
+    /// Voxel for a finished shape, adding its material to the palette
+    fn voxel(&mut self, builder: &MaterialBuilder) -> VoxelData {
+        let look = builder.look();
+        let material = self.materials.insert(look.material);
+        VoxelData::with_material(look.color, material, &self.materials)
+    }
+
+    fn parse_shape(&mut self) -> Result<Shape, ParseError> {
+        let (keyword, token) = self.word("a shape")?;
+        match keyword.as_str() {
+            "box" => self.parse_box(&token),
+            "sphere" => self.parse_sphere(&token),
+            "plane" => self.parse_plane(&token),
+            "union" => Ok(Shape::Union(self.parse_block(&token)?)),
+            "subtract" => {
+                let mut shapes = self.parse_block(&token)?.into_iter();
+                let base = shapes.next().ok_or_else(|| token.error("subtract needs at least one shape"))?;
+                Ok(Shape::Subtract(Box::new(base), shapes.collect()))
+            }
+            _ => Err(token.error(format!("unknown statement `{}`", keyword))),
+        }
+    }
+
+    fn parse_block(&mut self, keyword: &Token) -> Result<Vec<Shape>, ParseError> {
+        let open = self.advance();
+        if open.kind != TokenKind::OpenBrace {
+            return Err(open.error(format!("expected `{{` after {}, found {}", keyword.describe(), open.describe())));
+        }
+
+        let mut shapes = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek().kind {
+                TokenKind::CloseBrace => {
+                    self.advance();
+                    return Ok(shapes);
+                }
+                TokenKind::End => return Err(open.error("unclosed `{`")),
+                _ => {
+                    shapes.push(self.parse_shape()?);
+                    self.end_of_statement()?;
+                }
+            }
+        }
+    }
+
+    fn parse_box(&mut self, keyword: &Token) -> Result<Shape, ParseError> {
+        let (mut min, mut max, mut center, mut size) = (None, None, None, None);
+        let mut rotation = na::Rotation3::identity();
+        let mut material = MaterialBuilder::default();
+
+        while let Some((key, token)) = self.property() {
+            match key.as_str() {
+                "min" => min = Some(self.vector()?),
+                "max" => max = Some(self.vector()?),
+                "center" => center = Some(self.vector()?),
+                "size" => size = Some(self.vector()?),
+                "rotate" => {
+                    let degrees = self.vector()?;
+                    rotation = na::Rotation3::from_euler_angles(
+                        degrees.x.to_radians(),
+                        degrees.y.to_radians(),
+                        degrees.z.to_radians(),
+                    );
+                }
+                _ => material.shape_property(self, &key, &token, "box")?,
+            }
+        }
+
+        let (center, half_size) = match (min, max, center, size) {
+            (Some(min), Some(max), None, None) => ((min + max) * 0.5, (max - min) * 0.5),
+            (None, None, Some(center), Some(size)) => (center, size * 0.5),
+            _ => return Err(keyword.error("box needs either `min` and `max` or `center` and `size`")),
+        };
+        if half_size.iter().any(|&v| v < 0.0) {
+            return Err(keyword.error("box has negative size"));
+        }
+
+        Ok(Shape::Cuboid { center, half_size, rotation, material: self.voxel(&material) })
+    }
+
+    fn parse_sphere(&mut self, keyword: &Token) -> Result<Shape, ParseError> {
+        let (mut center, mut radius) = (None, None);
+        let mut material = MaterialBuilder::default();
+
+        while let Some((key, token)) = self.property() {
+            match key.as_str() {
+                "center" => center = Some(self.vector()?),
+                "radius" => radius = Some(self.number()?),
+                _ => material.shape_property(self, &key, &token, "sphere")?,
+            }
+        }
+
+        match (center, radius) {
+            (Some(center), Some(radius)) if radius >= 0.0 => {
+                Ok(Shape::Sphere { center, radius, material: self.voxel(&material) })
+            }
+            (Some(_), Some(_)) => Err(keyword.error("sphere radius must not be negative")),
+            _ => Err(keyword.error("sphere needs `center` and `radius`")),
+        }
+    }
+
+    fn parse_plane(&mut self, keyword: &Token) -> Result<Shape, ParseError> {
+        let (mut normal, mut offset, mut thickness) = (None, 0.0, None);
+        let mut material = MaterialBuilder::default();
+
+        while let Some((key, token)) = self.property() {
+            match key.as_str() {
+                "normal" => {
+                    let value = self.vector()?;
+                    if value.norm_squared() == 0.0 {
+                        return Err(token.error("plane normal must not be zero"));
+                    }
+                    normal = Some(value.normalize());
+                }
+                "offset" => offset = self.number()?,
+                "thickness" => thickness = Some(self.number()?),
+                _ => material.shape_property(self, &key, &token, "plane")?,
+            }
+        }
+
+        match (normal, thickness) {
+            (Some(normal), Some(thickness)) => {
+                Ok(Shape::Plane { normal, offset, thickness, material: self.voxel(&material) })
+            }
+            _ => Err(keyword.error("plane needs `normal` and `thickness`")),
+        }
+    }
+}
+
+/// Voxel color plus the shading parameters of a named or inline material
+#[derive(Copy, Clone, Debug)]
+struct Look {
+    color: [f32; 3],
+    material: Material,
+}
+
+/// Accumulates a shape's look: a named base material plus inline overrides
+#[derive(Default)]
+struct MaterialBuilder {
+    base: Option<Look>,
+    color: Option<[f32; 3]>,
+    preset: Option<Preset>,
+    emission: Option<[f32; 3]>,
+    strength: Option<f32>,
+    roughness: Option<f32>,
+    metalness: Option<f32>,
+    ior: Option<f32>,
+    transmission: Option<f32>,
+}
+
+/// Starting points selected by `type`
+#[derive(Copy, Clone, Debug)]
+enum Preset {
+    Diffuse,
+    Metal,
+    Glass,
+    Emissive,
+}
+
+impl MaterialBuilder {
+    /// Consume the values of a material property; returns false for unknown keys
+    fn property(&mut self, parser: &mut Parser, key: &str) -> Result<bool, ParseError> {
+        match key {
+            "material" => {
+                let (name, name_token) = parser.word("a material name")?;
+                let look = parser.looks.get(&name)
+                    .ok_or_else(|| name_token.error(format!("unknown material `{}`", name)))?;
+                self.base = Some(*look);
+            }
+            "color" => self.color = Some(parser.vector()?.into()),
+            "emission" => self.emission = Some(parser.vector()?.into()),
+            "strength" => self.strength = Some(parser.number()?),
+            "roughness" => self.roughness = Some(parser.number()?),
+            "metalness" => self.metalness = Some(parser.number()?),
+            "ior" => self.ior = Some(parser.number()?),
+            "transmission" => self.transmission = Some(parser.number()?),
+            "type" => {
+                let (name, name_token) = parser.word("a material type")?;
+                self.preset = Some(match name.as_str() {
+                    "diffuse" => Preset::Diffuse,
+                    "metal" => Preset::Metal,
+                    "glass" => Preset::Glass,
+                    "emissive" => Preset::Emissive,
+                    _ => return Err(name_token.error(format!("unknown material type `{}`", name))),
+                });
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn shape_property(&mut self, parser: &mut Parser, key: &str, token: &Token, shape: &str) -> Result<(), ParseError> {
+        if self.property(parser, key)? {
+            Ok(())
+        } else {
+            Err(token.error(format!("unknown {} property `{}`", shape, key)))
+        }
+    }
+
+    /// Shapes without any material are the default white diffuse. A `type`
+    /// replaces the base material's parameters before the inline ones apply;
+    /// an emissive type without `emission` glows in the voxel color.
+    fn look(&self) -> Look {
+        let base = self.base.unwrap_or(Look { color: [0.73, 0.73, 0.73], material: Material::default() });
+        let color = self.color.unwrap_or(base.color);
+
+        let mut material = match self.preset {
+            None => base.material,
+            Some(Preset::Diffuse) => Material::default(),
+            Some(Preset::Metal) => Material::metal(0.2),
+            Some(Preset::Glass) => Material::glass(1.5),
+            Some(Preset::Emissive) => Material::emissive(color, 1.0),
+        };
+        if let Some(emission) = self.emission {
+            material.emission = emission;
+            if material.emission_strength == 0.0 {
+                material.emission_strength = 1.0;
+            }
+        }
+        if let Some(strength) = self.strength { material.emission_strength = strength; }
+        if let Some(roughness) = self.roughness { material.roughness = roughness; }
+        if let Some(metalness) = self.metalness { material.metalness = metalness; }
+        if let Some(ior) = self.ior { material.ior = ior; }
+        if let Some(transmission) = self.transmission { material.transmission = transmission; }
+
+        Look { color, material }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        SceneDescription::parse(text).err().expect("scene should not parse").to_string()
+    }
+
+    fn point(x: f32, y: f32, z: f32) -> na::Vector3<f32> {
+        na::Vector3::new(x, y, z)
+    }
+
+    #[test]
+    fn minimal_scene() {
+        let scene = SceneDescription::parse("box min 0 0 0 max 1 1 1\n").unwrap();
+        assert_eq!(scene.bounds, SceneBounds::default());
+        assert_eq!(scene.camera, None);
+        assert_eq!(scene.shapes.len(), 1);
+        assert_eq!(scene.materials.len(), 1);
+
+        let voxel = scene.sample(&point(0.5, 0.5, 0.5)).unwrap();
+        assert_eq!(voxel.color, [0.73; 3]);
+        assert_eq!(voxel.material, 0);
+        assert_eq!(scene.sample(&point(1.5, 0.5, 0.5)), None);
+
+        let empty = SceneDescription::parse("# nothing here\n\n").unwrap();
+        assert!(empty.shapes.is_empty());
+    }
+
+    #[test]
+    fn voxelize_fills_cells_inside_shapes() {
+        let scene = SceneDescription::parse("
+            bounds center 0 0 0 half_size 1 max_depth 3
+            box min 0 0 0 max 1 1 1
+            sphere center -0.375 -0.375 -0.375 radius 0.1 color 1 0 0
+        ").unwrap();
+        let octree = scene.voxelize().unwrap();
+        assert_eq!(octree.max_depth, 3);
+
+        // The box's octant collapses into one leaf, the sphere takes one cell
+        let volume: f32 = octree.leaves().map(|(_, half_size, _)| (half_size * 8.0).powi(3)).sum();
+        assert_eq!(volume, 64.0 + 1.0);
+        assert_eq!(octree.sample(point(-0.375, -0.375, -0.375), 0).color, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn voxelize_rejects_hand_built_deep_bounds() {
+        let mut scene = SceneDescription::parse("
+            model crate max_depth 2 { box min -0.5 -0.5 -0.5 max 0.5 0.5 0.5 }
+            box min 0 0 0 max 1 1 1
+        ").unwrap();
+        for depth in [0, MAX_SCENE_DEPTH + 1, 22, 32, u8::MAX] {
+            scene.bounds.max_depth = depth;
+            assert!(scene.voxelize().is_err(), "depth {}", depth);
+            assert!(scene.to_instanced().is_err(), "depth {}", depth);
+        }
+
+        scene.bounds.max_depth = 3;
+        scene.models[0].bounds.max_depth = 40;
+        assert!(scene.to_instanced().is_err());
+    }
+
+    #[test]
+    fn cornell_box_example() {
+        let scene = SceneDescription::cornell_box();
+        assert_eq!(scene.bounds, SceneBounds { center: na::Vector3::zeros(), half_size: 2.0, max_depth: 8 });
+        assert_eq!(scene.camera.unwrap().eye, na::Point3::new(0.0, 1.0, -3.8));
+        assert_eq!(scene.shapes.len(), 8);
+
+        // The walls share the default material; only the light adds one
+        assert_eq!(scene.materials.len(), 2);
+        let light = scene.sample(&point(0.0, 2.0, 1.0)).unwrap();
+        assert_eq!(scene.materials.material_of(&light).radiance(), [17.0, 12.0, 4.0]);
+        assert_eq!(scene.sample(&point(0.5, 2.0, 1.0)).unwrap().color, [0.73; 3]);
+        assert_eq!(scene.sample(&point(-1.0, 1.0, 1.0)).unwrap().color, [0.65, 0.05, 0.05]);
+        assert_eq!(scene.sample(&point(0.0, 1.0, 1.0)), None);
+    }
+
+    #[test]
+    fn errors_point_at_the_bad_token() {
+        assert_eq!(error("box min 0 0 0 max 1 x 1"), "1:21: expected a number, found `x`");
+        assert_eq!(error("bounds half_size 2\n\n  sphere center 0 0 0 radius"), "3:29: expected a number, found end of line");
+        assert_eq!(error("union {\n  box min 0 0 0 max 1 1 1\n"), "1:7: unclosed `{`");
+        assert_eq!(error("box min 0 0 0 max 1 1 1 }"), "1:25: expected a shape, found `}`");
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(error("sphere center 0 0 0 radius 1 colour 1 0 0"), "1:30: unknown sphere property `colour`");
+        assert_eq!(error("cone center 0 0 0"), "1:1: unknown statement `cone`");
+        assert_eq!(error("camera eye 0 0 0 look 0 0 1"), "1:18: unknown camera property `look`");
+        assert_eq!(error("box min 0 0 0 max 1 1 1 material steel"), "1:34: unknown material `steel`");
+        assert_eq!(error("sphere center 0 0 0 radius 1 type plastic"), "1:35: unknown material type `plastic`");
+    }
+
+    #[test]
+    fn max_depth_out_of_range() {
+        for depth in ["0", "13", "2.5", "-1"] {
+            assert_eq!(error(&format!("bounds max_depth {}", depth)), "1:8: max_depth must be a whole number from 1 to 12");
+        }
+        assert_eq!(SceneDescription::parse("bounds max_depth 12").unwrap().bounds.max_depth, 12);
+    }
+
+    #[test]
+    fn subtract_keeps_the_first_shape() {
+        let scene = SceneDescription::parse("
+            subtract {
+                box center 0 0 0 size 1 1 1
+                sphere center 0.5 0 0 radius 0.3
+            }
+            subtract {
+                sphere center 0.5 3 0 radius 0.3
+                box center 0 3 0 size 1 1 1
+            }
+        ").unwrap();
+
+        assert!(scene.sample(&point(-0.45, 0.0, 0.0)).is_some());
+        assert!(scene.sample(&point(0.45, 0.0, 0.0)).is_none());
+        assert!(scene.sample(&point(0.7, 0.0, 0.0)).is_none());
+
+        // Reversed, only the part of the sphere outside the box is left
+        assert!(scene.sample(&point(0.7, 3.0, 0.0)).is_some());
+        assert!(scene.sample(&point(0.45, 3.0, 0.0)).is_none());
+
+        assert_eq!(error("subtract {\n}"), "1:1: subtract needs at least one shape");
+    }
+
+    #[test]
+    fn first_listed_shape_wins() {
+        let scene = SceneDescription::parse("
+            sphere center 0 0 0 radius 0.5 color 1 0 0
+            box center 0 0 0 size 2 2 2 color 0 0 1
+        ").unwrap();
+        assert_eq!(scene.sample(&point(0.0, 0.0, 0.0)).unwrap().color, [1.0, 0.0, 0.0]);
+        assert_eq!(scene.sample(&point(0.8, 0.0, 0.0)).unwrap().color, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn rotated_boxes() {
+        // A bar along X turned to run along Y
+        let scene = SceneDescription::parse("box center 0 0 0 size 2 0.2 0.2 rotate 0 0 90").unwrap();
+        assert!(scene.sample(&point(0.0, 0.9, 0.0)).is_some());
+        assert!(scene.sample(&point(0.0, -0.9, 0.05)).is_some());
+        assert!(scene.sample(&point(0.9, 0.0, 0.0)).is_none());
+
+        let (min, max) = scene.shapes[0].bounds().unwrap();
+        assert!((max - point(0.1, 1.0, 0.1)).norm() < 1e-5);
+        assert!((min + point(0.1, 1.0, 0.1)).norm() < 1e-5);
+
+        // Turned 45 degrees about Y, the corner region along X is empty
+        let scene = SceneDescription::parse("box center 0 0 0 size 1 1 1 rotate 0 45 0").unwrap();
+        assert!(scene.sample(&point(0.65, 0.0, 0.0)).is_some());
+        assert!(scene.sample(&point(0.45, 0.0, 0.45)).is_none());
+    }
+}
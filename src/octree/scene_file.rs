@@ -0,0 +1,379 @@
+use super::{MAX_DEPTH, Octree, OctreeNode, VoxelData};
+use super::material::{Material, MaterialTable};
+use nalgebra as na;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+/// Native binary octree format, little-endian throughout:
+///
+/// | field           | type       |
+/// |-----------------|------------|
+/// | magic `AVPO`    | [u8; 4]    |
+/// | version         | u32        |
+/// | center          | [f32; 3]   |
+/// | half_size       | f32        |
+/// | max_depth       | u32        |
+/// | base_voxel_size | f32        |
+/// | material_count  | u32        |
+/// | materials       | [Material] |
+/// | node_count      | u64        |
+/// | nodes           | pre-order  |
+/// | checksum        | u64        |
+///
+/// Materials are the octree's `MaterialTable` as 48-byte `Material`s, default
+/// first. Each node is a flag byte (bit 0: has children, bit 1: has data)
+/// followed by a 32-byte `VoxelData` for data-carrying leaves and then its eight
+/// children. Interior LoD data is not stored; it is rebuilt on load. The
+/// checksum is the 64-bit FNV-1a hash of every preceding byte.
+///
+/// Version 1 files have no material table; their voxels hold a material type
+/// (0 diffuse, 1 metallic, 2 glass, 3 emissive) that is converted on load.
+const MAGIC: &[u8; 4] = b"AVPO";
+const VERSION: u32 = 2;
+
+const FLAG_CHILDREN: u8 = 1;
+const FLAG_DATA: u8 = 2;
+
+impl Octree {
+    /// Write the octree to `path` in the native scene format
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let node_count = self.node_count();
+        let mut bytes = Vec::with_capacity(48 + node_count * 2);
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        for value in self.root.center.iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.root.half_size.to_le_bytes());
+        bytes.extend_from_slice(&(self.max_depth as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.base_voxel_size.to_le_bytes());
+        bytes.extend_from_slice(&(self.materials.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(bytemuck::cast_slice(self.materials.materials()));
+        bytes.extend_from_slice(&(node_count as u64).to_le_bytes());
+
+        write_node(&self.root, &mut bytes);
+
+        let checksum = fnv1a(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+
+        fs::write(path, bytes)
+    }
+
+    /// Read an octree previously written by `Octree::save`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Octree> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < 8 {
+            return Err(invalid("file too short"));
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 8);
+        if fnv1a(body) != u64::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        let mut reader = Reader::new(body);
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not an octree scene file"));
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+
+        let center = na::Vector3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+        let half_size = reader.f32()?;
+        let max_depth = u8::try_from(reader.u32()?).ok()
+            .filter(|&depth| depth <= MAX_DEPTH)
+            .ok_or_else(|| invalid("max_depth out of range"))?;
+        let base_voxel_size = reader.f32()?;
+
+        let mut octree = Octree::new(center, half_size, max_depth);
+        octree.base_voxel_size = base_voxel_size;
+
+        if version >= 2 {
+            let material_count = reader.u32()? as usize;
+            let bytes = reader.take(material_count * std::mem::size_of::<Material>())?;
+            octree.materials = MaterialTable::from_materials(
+                bytes.chunks_exact(std::mem::size_of::<Material>()).map(bytemuck::pod_read_unaligned).collect(),
+            );
+        }
+
+        let node_count = reader.u64()?;
+        let mut nodes_read = 0;
+        read_node(&mut octree.root, &mut reader, max_depth, &mut nodes_read)?;
+        if nodes_read != node_count || reader.offset != body.len() {
+            return Err(invalid("node stream does not match header"));
+        }
+
+        if version == 1 {
+            convert_material_types(&mut octree.root, &mut octree.materials);
+        }
+
+        octree.build_lod();
+        Ok(octree)
+    }
+}
+
+fn write_node(node: &OctreeNode, bytes: &mut Vec<u8>) {
+    let leaf_data = if node.is_leaf() { node.voxel_data } else { None };
+
+    let mut flags = 0;
+    if node.children.is_some() { flags |= FLAG_CHILDREN; }
+    if leaf_data.is_some() { flags |= FLAG_DATA; }
+    bytes.push(flags);
+
+    if let Some(data) = leaf_data {
+        bytes.extend_from_slice(bytemuck::bytes_of(&data));
+    }
+
+    if let Some(ref children) = node.children {
+        for child in children.iter() {
+            write_node(child, bytes);
+        }
+    }
+}
+
+fn read_node(node: &mut OctreeNode, reader: &mut Reader, max_depth: u8, nodes_read: &mut u64) -> io::Result<()> {
+    *nodes_read += 1;
+    let flags = reader.take(1)?[0];
+
+    if flags & FLAG_DATA != 0 {
+        let data: VoxelData = bytemuck::pod_read_unaligned(reader.take(std::mem::size_of::<VoxelData>())?);
+        node.voxel_data = Some(data);
+    }
+
+    if flags & FLAG_CHILDREN != 0 {
+        if node.level >= max_depth {
+            return Err(invalid("node subdivided below max_depth"));
+        }
+        node.voxel_data = None;
+        node.subdivide();
+        if let Some(ref mut children) = node.children {
+            for child in children.iter_mut() {
+                read_node(child, reader, max_depth, nodes_read)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the material types of version 1 leaves with table indices
+fn convert_material_types(node: &mut OctreeNode, materials: &mut MaterialTable) {
+    if let Some(ref mut data) = node.voxel_data {
+        data.material = materials.insert(Material::legacy(data.material, data.emission));
+    }
+    if let Some(ref mut children) = node.children {
+        for child in children.iter_mut() {
+            convert_material_types(child, materials);
+        }
+    }
+}
+
+/// Little-endian cursor over a byte slice, shared with the `.vox` importer
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    pub(super) offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    pub(super) fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    pub(super) fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let end = self.offset.saturating_add(count);
+        if end > self.bytes.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "file truncated"));
+        }
+        let slice = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(slice)
+    }
+
+    pub(super) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(super) fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(super) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(super) fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+pub(super) fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+pub(super) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Scratch file in the system temp directory, unique per test
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("avpo-{}-{}.avpo", std::process::id(), name))
+    }
+
+    /// Write `body` followed by its checksum and load it back
+    fn load_body(name: &str, mut body: Vec<u8>) -> io::Result<Octree> {
+        let checksum = fnv1a(&body);
+        body.extend_from_slice(&checksum.to_le_bytes());
+        let path = scratch(name);
+        fs::write(&path, body)?;
+        let result = Octree::load(&path);
+        fs::remove_file(path)?;
+        result
+    }
+
+    /// Header fields shared by every version, up to and including base_voxel_size
+    fn header(version: u32, max_depth: u32) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        for value in [0.0f32, 0.0, 0.0, 1.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&max_depth.to_le_bytes());
+        bytes.extend_from_slice(&0.25f32.to_le_bytes());
+        bytes
+    }
+
+    fn scene() -> Octree {
+        let mut octree = Octree::new(na::Vector3::new(0.0, 1.0, 0.0), 1.0, 3);
+        let metal = octree.materials.insert(Material::metal(0.3));
+        let light = octree.materials.insert(Material::emissive([1.0, 0.9, 0.8], 4.0));
+        for i in 0..4 {
+            for k in 0..4 {
+                // A uniform floor quarter that collapses, plus a few loose voxels
+                octree.insert(na::Vector3::new(i as f32 * 0.25 - 0.875, 0.125, k as f32 * 0.25 - 0.875), VoxelData::solid([0.73; 3]));
+            }
+        }
+        octree.insert(na::Vector3::new(0.375, 1.125, 0.125), VoxelData::with_material([0.9; 3], metal, &octree.materials));
+        octree.insert(na::Vector3::new(0.625, 1.875, 0.625), VoxelData::with_material([1.0; 3], light, &octree.materials));
+        octree
+    }
+
+    #[test]
+    fn round_trip() {
+        let octree = scene();
+        let path = scratch("round-trip");
+        octree.save(&path).unwrap();
+        let loaded = Octree::load(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.root.center, octree.root.center);
+        assert_eq!(loaded.root.half_size, octree.root.half_size);
+        assert_eq!(loaded.max_depth, octree.max_depth);
+        assert_eq!(loaded.base_voxel_size, octree.base_voxel_size);
+        assert_eq!(loaded.materials.materials(), octree.materials.materials());
+        assert_eq!(loaded.node_count(), octree.node_count());
+        assert_eq!(loaded.leaves().collect::<Vec<_>>(), octree.leaves().collect::<Vec<_>>());
+
+        // Interior LoD data is rebuilt rather than stored
+        assert_eq!(loaded.root.voxel_data, octree.root.voxel_data);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let path = scratch("checksum");
+        scene().save(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[40] ^= 1;
+        fs::write(&path, bytes).unwrap();
+        let error = Octree::load(&path).err().unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "checksum mismatch");
+    }
+
+    #[test]
+    fn truncated_input() {
+        let path = scratch("truncated");
+        scene().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        // Cut inside the node stream, with a checksum that matches what is left
+        let body = bytes[..bytes.len() - 8 - 20].to_vec();
+        assert_eq!(load_body("truncated", body).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(load_body("truncated", header(2, 3)).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+
+        let path = scratch("short");
+        fs::write(&path, b"AVPO").unwrap();
+        let error = Octree::load(&path).err().unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(error.to_string(), "file too short");
+    }
+
+    #[test]
+    fn unknown_version() {
+        let error = load_body("version", header(VERSION + 1, 3)).err().unwrap();
+        assert_eq!(error.to_string(), format!("unsupported version {}", VERSION + 1));
+        assert!(load_body("version", header(0, 3)).is_err());
+
+        let mut body = header(2, 3);
+        body[..4].copy_from_slice(b"AVPX");
+        assert_eq!(load_body("magic", body).err().unwrap().to_string(), "not an octree scene file");
+    }
+
+    #[test]
+    fn max_depth_out_of_range() {
+        for depth in [MAX_DEPTH as u32 + 1, 31, 255, u32::MAX] {
+            let mut body = header(2, depth);
+            body.extend_from_slice(&0u32.to_le_bytes());
+            body.extend_from_slice(&1u64.to_le_bytes());
+            body.push(0);
+            assert_eq!(load_body("depth", body).err().unwrap().to_string(), "max_depth out of range");
+        }
+    }
+
+    #[test]
+    fn version_1_material_types_become_table_entries() {
+        // Root split once; a metallic, an emissive and a plain diffuse leaf
+        let mut body = header(1, 1);
+        body.extend_from_slice(&9u64.to_le_bytes());
+        body.push(FLAG_CHILDREN);
+        let leaves = [
+            (0, VoxelData { material: 1, ..VoxelData::solid([0.9; 3]) }),
+            (3, VoxelData { emission: [5.0, 5.0, 4.0], material: 3, ..VoxelData::solid([1.0; 3]) }),
+            (6, VoxelData::solid([0.73; 3])),
+        ];
+        for index in 0..8 {
+            match leaves.iter().find(|(leaf, _)| *leaf == index) {
+                Some((_, data)) => {
+                    body.push(FLAG_DATA);
+                    body.extend_from_slice(bytemuck::bytes_of(data));
+                }
+                None => body.push(0),
+            }
+        }
+
+        let octree = load_body("version-1", body).unwrap();
+        let children = octree.root.children.as_ref().unwrap();
+        let material = |index: usize| octree.materials.material_of(&children[index].voxel_data.unwrap());
+        assert_eq!(material(0), &Material::metal(0.2));
+        assert_eq!(material(3).radiance(), [5.0, 5.0, 4.0]);
+        assert_eq!(material(6), &Material::default());
+        assert_eq!(children[6].voxel_data.unwrap().material, 0);
+        assert_eq!(octree.materials.len(), 3);
+    }
+}
@@ -1,244 +1,167 @@
-use super::{Octree, OctreeProvider, VoxelData};
+use super::{Octree, OctreeProvider, VoxelData, lod_for_distance};
+use super::scene_description::SceneDescription;
+use super::linear::LinearOctree;
+use super::distance_field::DistanceField;
+use super::material;
+use super::occupancy::OccupancyPyramid;
 use nalgebra as na;
+use rayon::prelude::*;
+use std::time::Instant;
 use wgpu::*;
+use wgpu::util::DeviceExt;
 use log::info;
 
+/// How the octree is handed to the GPU
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum GpuStorage {
+    /// Dense RGBA8 3D texture sampled at a fixed resolution, with a companion
+    /// material index texture and an occupancy pyramid for skipping empty space
+    DenseTexture,
+    /// Linearized sparse octree in a storage buffer, traversed in the shader
+    SparseBuffer,
+    /// The dense texture with a signed distance field in place of the
+    /// occupancy pyramid, so rays sphere-trace through empty space
+    DistanceField,
+}
+
+impl GpuStorage {
+    /// WGSL backend implementing `octree_lookup` for this storage mode
+    pub fn shader_source(self) -> &'static str {
+        match self {
+            GpuStorage::DenseTexture => include_str!("../shaders/octree_texture.wgsl"),
+            GpuStorage::SparseBuffer => include_str!("../shaders/octree_sparse.wgsl"),
+            GpuStorage::DistanceField => include_str!("../shaders/octree_distance_field.wgsl"),
+        }
+    }
+}
+
 /// Static octree provider for benchmark scenes (like Cornell Box)
-/// This implementation uses a 3D texture for GPU-accelerated sampling
+/// Uploads either a dense 3D texture or a linearized sparse node buffer for the GPU
 pub struct StaticOctreeProvider {
     octree: Octree,
     base_voxel_size: f32,
-    texture_3d: Option<Texture>,
-    texture_view: Option<TextureView>,
-    sampler: Option<Sampler>,
-    bind_group_layout: Option<BindGroupLayout>,
-    bind_group: Option<BindGroup>,
+    storage: GpuStorage,
+    volume: Option<VolumeTexture>,
+    node_buffer: Option<Buffer>,
+    header_buffer: Option<Buffer>,
+    material_buffer: Option<Buffer>,
     texture_size: u32,
 }
 
 impl StaticOctreeProvider {
     pub fn new_cornell_box() -> Self {
-        // Create octree centered at origin for simplicity
-        // Cornell Box: X: -1 to 1, Y: 0 to 2, Z: 0 to 2
-        let center = na::Vector3::new(0.0, 0.0, 0.0);
-        let half_size = 2.0;  // Covers -2 to 2 in all dimensions
-        let max_depth = 8;  // 256x256x256 resolution at max depth for better quality
+        Self::new_cornell_box_with_storage(GpuStorage::DenseTexture)
+    }
 
-        let mut octree = Octree::new(center, half_size, max_depth);
+    pub fn new_cornell_box_with_storage(storage: GpuStorage) -> Self {
+        let mut octree = SceneDescription::cornell_box().voxelize().expect("the built-in scene is valid");
 
-        // Build the Cornell Box scene in the octree
-        Self::build_cornell_box_scene(&mut octree);
+        let start = Instant::now();
+        let stats = octree.optimize();
+        info!("Collapsed uniform subtrees: {} -> {} nodes ({:.2} MB -> {:.2} MB) in {:.1?}",
+              stats.nodes_before, stats.nodes_after,
+              stats.bytes_before as f64 / (1024.0 * 1024.0),
+              stats.bytes_after as f64 / (1024.0 * 1024.0),
+              start.elapsed());
 
+        Self::from_octree(octree, storage)
+    }
+
+    pub fn from_octree(octree: Octree, storage: GpuStorage) -> Self {
         Self {
             octree,
             base_voxel_size: 0.02,
-            texture_3d: None,
-            texture_view: None,
-            sampler: None,
-            bind_group_layout: None,
-            bind_group: None,
+            storage,
+            volume: None,
+            node_buffer: None,
+            header_buffer: None,
+            material_buffer: None,
             texture_size: 256,  // 256x256x256 3D texture for better quality
         }
     }
 
-    fn build_cornell_box_scene(octree: &mut Octree) {
-        let resolution = 0.02;  // Finer resolution for better quality
-        let mut voxel_count = 0;
-        let mut ceiling_count = 0;
-        let mut light_count = 0;
-
-        // Sample the scene and insert into octree
-        for x in -60..=60 {
-            for y in -10..=110 {  // Extend Y range to ensure we capture ceiling at Y=2
-                for z in -10..=110 {
-                    let pos = na::Vector3::new(
-                        x as f32 * resolution,
-                        y as f32 * resolution,
-                        z as f32 * resolution,
-                    );
-
-                    if let Some(voxel) = Self::sample_cornell_box_at(pos) {
-                        octree.insert(pos, voxel);
-                        voxel_count += 1;
-
-                        // Count ceiling and light voxels for debugging
-                        if pos.y >= 1.95 && pos.y <= 2.05 {
-                            ceiling_count += 1;
-                            if pos.x >= -0.25 && pos.x <= 0.25 && pos.z >= 0.75 && pos.z <= 1.25 {
-                                light_count += 1;
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        info!("Built Cornell Box scene: {} total voxels, {} ceiling, {} light",
-              voxel_count, ceiling_count, light_count);
+    pub fn octree(&self) -> &Octree {
+        &self.octree
     }
 
-    fn sample_cornell_box_at(pos: na::Vector3<f32>) -> Option<VoxelData> {
-        let wall_thickness = 0.05;
-
-        // Floor (white)
-        if pos.y >= -wall_thickness && pos.y <= wall_thickness {
-            if pos.x >= -1.0 && pos.x <= 1.0 && pos.z >= 0.0 && pos.z <= 2.0 {
-                return Some(VoxelData::solid([0.73, 0.73, 0.73]));
-            }
-        }
-
-        // Ceiling (white)
-        if pos.y >= 2.0 - wall_thickness && pos.y <= 2.0 + wall_thickness {
-            if pos.x >= -1.0 && pos.x <= 1.0 && pos.z >= 0.0 && pos.z <= 2.0 {
-                // Light source in center of ceiling
-                if pos.x >= -0.25 && pos.x <= 0.25 && pos.z >= 0.75 && pos.z <= 1.25 {
-                    return Some(VoxelData::emissive([1.0, 1.0, 0.95], [5.0, 5.0, 4.75]));
-                }
-                return Some(VoxelData::solid([0.73, 0.73, 0.73]));
-            }
-        }
-
-        // Back wall (white)
-        if pos.z >= 2.0 - wall_thickness && pos.z <= 2.0 + wall_thickness {
-            if pos.x >= -1.0 - wall_thickness && pos.x <= 1.0 + wall_thickness &&
-               pos.y >= -wall_thickness && pos.y <= 2.0 + wall_thickness {
-                return Some(VoxelData::solid([0.73, 0.73, 0.73]));
-            }
-        }
-
-        // Left wall (red)
-        if pos.x >= -1.0 - wall_thickness && pos.x <= -1.0 + wall_thickness {
-            if pos.z >= 0.0 && pos.z <= 2.0 && pos.y >= 0.0 && pos.y <= 2.0 {
-                return Some(VoxelData::solid([0.65, 0.05, 0.05]));
-            }
-        }
-
-        // Right wall (green)
-        if pos.x >= 1.0 - wall_thickness && pos.x <= 1.0 + wall_thickness {
-            if pos.z >= 0.0 && pos.z <= 2.0 && pos.y >= 0.0 && pos.y <= 2.0 {
-                return Some(VoxelData::solid([0.12, 0.45, 0.15]));
-            }
-        }
-
-        // Tall box (white)
-        let tall_center = na::Vector3::new(-0.35, 0.3, 0.65);
-        let tall_half = na::Vector3::new(0.15, 0.3, 0.15);
-
-        // Simple rotation around Y
-        let cos_a = 0.956;
-        let sin_a = -0.292;
-        let offset = pos - tall_center;
-        let rotated_x = offset.x * cos_a - offset.z * sin_a;
-        let rotated_z = offset.x * sin_a + offset.z * cos_a;
-
-        if rotated_x.abs() <= tall_half.x &&
-           pos.y >= 0.0 && pos.y <= tall_half.y * 2.0 &&
-           rotated_z.abs() <= tall_half.z {
-            return Some(VoxelData::solid([0.73, 0.73, 0.73]));
-        }
-
-        // Short box (white)
-        let short_center = na::Vector3::new(0.35, 0.15, 1.35);
-        let short_half = na::Vector3::new(0.15, 0.15, 0.15);
+    /// Upload the octree and its material table using the configured storage mode
+    pub fn create_gpu_resources(&mut self, device: &Device, queue: &Queue) {
+        self.material_buffer = Some(self.octree.materials.create_buffer(device));
+        info!("Created material table: {} materials", self.octree.materials.len());
 
-        let offset2 = pos - short_center;
-        let rotated_x2 = offset2.x * cos_a + offset2.z * sin_a;
-        let rotated_z2 = -offset2.x * sin_a + offset2.z * cos_a;
-
-        if rotated_x2.abs() <= short_half.x &&
-           pos.y >= 0.0 && pos.y <= short_half.y * 2.0 &&
-           rotated_z2.abs() <= short_half.z {
-            return Some(VoxelData::solid([0.73, 0.73, 0.73]));
+        match self.storage {
+            GpuStorage::DenseTexture | GpuStorage::DistanceField => self.create_texture(device, queue),
+            GpuStorage::SparseBuffer => self.create_node_buffer(device),
         }
-
-        None
     }
 
     /// Create 3D texture from octree data
     pub fn create_texture(&mut self, device: &Device, queue: &Queue) {
         let size = self.texture_size;
-        let mut texture_data = vec![0u8; (size * size * size * 4) as usize];
+        let start = Instant::now();
+        let texels = bake_texels(&self.octree, size, [0; 3], [size; 3]);
+        let distance_field = (self.storage == GpuStorage::DistanceField).then(|| DistanceField::from_texels(size, &texels));
+        let occupancy = distance_field.is_none().then(|| OccupancyPyramid::from_texels(size, &texels));
+        let baked = start.elapsed();
+        let volume = VolumeTexture::new(device, size, self.storage);
 
-        // Sample octree into texture
-        for z in 0..size {
-            for y in 0..size {
-                for x in 0..size {
-                    let world_pos = na::Vector3::new(
-                        (x as f32 / size as f32 - 0.5) * 4.0,  // -2 to 2
-                        (y as f32 / size as f32 - 0.5) * 4.0,  // -2 to 2
-                        (z as f32 / size as f32 - 0.5) * 4.0,  // -2 to 2
-                    );
+        // Write all texture data at once for the 3D texture
+        volume.write(queue, [0; 3], [size; 3], &texels);
+        if let Some(ref field) = distance_field {
+            volume.write_distance_field(queue, field, [0; 3], [size; 3]);
+        }
+        if let Some(ref occupancy) = occupancy {
+            volume.write_pyramid(queue, occupancy);
+        }
+        self.volume = Some(volume);
 
-                    let voxel = self.octree.sample(world_pos, 0);
-                    let idx = ((z * size * size + y * size + x) * 4) as usize;
+        info!("Created {}x{}x{} 3D texture for octree: baked in {:.1?}, uploaded in {:.1?}",
+              size, size, size, baked, start.elapsed() - baked);
+    }
 
-                    // Pack as RGBA8
-                    texture_data[idx] = (voxel.color[0] * 255.0) as u8;
-                    texture_data[idx + 1] = (voxel.color[1] * 255.0) as u8;
-                    texture_data[idx + 2] = (voxel.color[2] * 255.0) as u8;
-                    texture_data[idx + 3] = (voxel.density * 255.0) as u8;
-                }
-            }
-        }
+    /// Create storage buffer holding the linearized sparse octree
+    pub fn create_node_buffer(&mut self, device: &Device) {
+        let linear = LinearOctree::from_octree(&self.octree);
 
-        // Create 3D texture
-        let texture = device.create_texture(&TextureDescriptor {
-            label: Some("Octree 3D Texture"),
-            size: Extent3d {
-                width: size,
-                height: size,
-                depth_or_array_layers: size,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D3,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            view_formats: &[],
+        let node_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Octree Node Buffer"),
+            contents: bytemuck::cast_slice(&linear.nodes),
+            usage: BufferUsages::STORAGE,
         });
 
-        // Write all texture data at once for the 3D texture
-        queue.write_texture(
-            texture.as_image_copy(),
-            &texture_data,
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * size),
-                rows_per_image: Some(size),
-            }.into(),
-            Extent3d {
-                width: size,
-                height: size,
-                depth_or_array_layers: size,
-            },
-        );
-
-        let texture_view = texture.create_view(&TextureViewDescriptor::default());
-
-        // Create sampler with linear filtering for smoother appearance
-        let sampler = device.create_sampler(&SamplerDescriptor {
-            label: Some("Octree Sampler"),
-            address_mode_u: AddressMode::ClampToEdge,
-            address_mode_v: AddressMode::ClampToEdge,
-            address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,  // Linear for smoother interpolation
-            min_filter: FilterMode::Linear,  // Linear for smoother interpolation
-            mipmap_filter: FilterMode::Nearest,
-            ..Default::default()
+        let header_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Octree Header Buffer"),
+            contents: bytemuck::cast_slice(&[linear.header]),
+            usage: BufferUsages::UNIFORM,
         });
 
-        self.texture_3d = Some(texture);
-        self.texture_view = Some(texture_view);
-        self.sampler = Some(sampler);
+        self.node_buffer = Some(node_buffer);
+        self.header_buffer = Some(header_buffer);
+
+        let dense_bytes = (self.texture_size as usize).pow(3) * 4;
+        info!("Created sparse octree buffer: {} nodes, {:.2} MB (dense texture: {:.2} MB)",
+              linear.nodes.len(),
+              linear.size_bytes() as f64 / (1024.0 * 1024.0),
+              dense_bytes as f64 / (1024.0 * 1024.0));
+    }
+
+    fn bind_texture_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+        self.volume.as_ref().unwrap().bind(device, self.material_buffer.as_ref().unwrap())
+    }
 
-        info!("Created {}x{}x{} 3D texture for octree", size, size, size);
+    fn bind_buffer_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+        bind_node_buffer(
+            device,
+            self.node_buffer.as_ref().unwrap(),
+            self.header_buffer.as_ref().unwrap(),
+            self.material_buffer.as_ref().unwrap(),
+        )
     }
 }
 
 impl OctreeProvider for StaticOctreeProvider {
     fn sample_voxel(&self, position: na::Vector3<f32>, distance_from_camera: f32) -> VoxelData {
-        // Calculate LoD level based on distance
-        let lod_level = (distance_from_camera / 5.0).floor() as u8;
+        let lod_level = lod_for_distance(distance_from_camera);
         self.octree.sample(position, lod_level.min(self.octree.max_depth))
     }
 
@@ -255,8 +178,152 @@ impl OctreeProvider for StaticOctreeProvider {
         )
     }
 
+    fn get_grid_dims(&self) -> na::Vector3<u32> {
+        match self.storage {
+            GpuStorage::DenseTexture | GpuStorage::DistanceField => na::Vector3::repeat(self.texture_size),
+            GpuStorage::SparseBuffer => na::Vector3::repeat(1 << self.octree.max_depth),
+        }
+    }
+
     fn bind_gpu_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
-        // Create bind group layout if not exists
+        match self.storage {
+            GpuStorage::DenseTexture | GpuStorage::DistanceField => self.bind_texture_resources(device),
+            GpuStorage::SparseBuffer => self.bind_buffer_resources(device),
+        }
+    }
+
+    fn shader_source(&self) -> &'static str {
+        self.storage.shader_source()
+    }
+}
+
+/// Texels baked from an octree: RGBA8 color and density, and a material index
+/// per texel, both in x-fastest order
+pub(super) struct VolumeTexels {
+    pub colors: Vec<u8>,
+    pub materials: Vec<u16>,
+}
+
+/// Sample the octree at full resolution into texels for the box of texels
+/// starting at `origin` with size `extent`. The texture spans the root cube, so
+/// texel `i` of a `size`-wide texture samples its center, `root_min + (i + 0.5) / size * root_extent`.
+/// Materials missing from `materials` fall back to index 0. Each z slice is
+/// baked on its own thread.
+pub(super) fn bake_texels(octree: &Octree, size: u32, origin: [u32; 3], extent: [u32; 3]) -> VolumeTexels {
+    let root_min = octree.root.center.add_scalar(-octree.root.half_size);
+    let texel_size = octree.root.half_size * 2.0 / size as f32;
+    let slice_texels = (extent[0] * extent[1]) as usize;
+    let mut texels = VolumeTexels {
+        colors: vec![0; slice_texels * 4 * extent[2] as usize],
+        materials: vec![0; slice_texels * extent[2] as usize],
+    };
+    if slice_texels == 0 {
+        return texels;
+    }
+
+    texels.colors.par_chunks_mut(slice_texels * 4)
+        .zip(texels.materials.par_chunks_mut(slice_texels))
+        .enumerate()
+        .for_each(|(slice, (colors, material_indices))| {
+            let z = origin[2] + slice as u32;
+            let mut texel = colors.chunks_exact_mut(4).zip(material_indices.iter_mut());
+            for y in origin[1]..origin[1] + extent[1] {
+                for x in origin[0]..origin[0] + extent[0] {
+                    let world_pos = root_min + na::Vector3::new(x, y, z).map(|i| (i as f32 + 0.5) * texel_size);
+
+                    // Pack as RGBA8
+                    let voxel = octree.sample(world_pos, 0);
+                    let (color, material) = texel.next().unwrap();
+                    color.copy_from_slice(&[
+                        (voxel.color[0] * 255.0) as u8,
+                        (voxel.color[1] * 255.0) as u8,
+                        (voxel.color[2] * 255.0) as u8,
+                        (voxel.density * 255.0) as u8,
+                    ]);
+                    *material = octree.materials.lookup(&voxel) as u16;
+                }
+            }
+        });
+
+    texels
+}
+
+/// Dense backend resources: a `size`³ RGBA8 volume and a same-sized R16Uint
+/// volume of material indices, both read texel by texel without filtering,
+/// plus a third volume for skipping empty space: the R8 occupancy pyramid as
+/// its mip chain, or the R8Sint distance field for `GpuStorage::DistanceField`
+pub(super) struct VolumeTexture {
+    color: Texture,
+    color_view: TextureView,
+    material: Texture,
+    material_view: TextureView,
+    empty_space: Texture,
+    empty_space_view: TextureView,
+    empty_space_type: TextureSampleType,
+}
+
+impl VolumeTexture {
+    pub(super) fn new(device: &Device, size: u32, storage: GpuStorage) -> Self {
+        let create = |label, format, mip_level_count| {
+            let texture = device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size: Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: size,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: TextureDimension::D3,
+                format,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&TextureViewDescriptor::default());
+            (texture, view)
+        };
+        let (color, color_view) = create("Octree 3D Texture", TextureFormat::Rgba8Unorm, 1);
+        let (material, material_view) = create("Octree Material Texture", TextureFormat::R16Uint, 1);
+        let ((empty_space, empty_space_view), empty_space_type) = match storage {
+            GpuStorage::DistanceField => (
+                create("Octree Distance Field Texture", TextureFormat::R8Sint, 1),
+                TextureSampleType::Sint,
+            ),
+            _ => (
+                create("Octree Occupancy Texture", TextureFormat::R8Unorm, OccupancyPyramid::level_count(size)),
+                TextureSampleType::Float { filterable: false },
+            ),
+        };
+
+        Self { color, color_view, material, material_view, empty_space, empty_space_view, empty_space_type }
+    }
+
+    /// Upload texels produced by `bake_texels` into a box of both volumes
+    pub(super) fn write(&self, queue: &Queue, origin: [u32; 3], extent: [u32; 3], texels: &VolumeTexels) {
+        write_box(queue, &self.color, 0, origin, extent, &texels.colors, 4);
+        write_box(queue, &self.material, 0, origin, extent, bytemuck::cast_slice(&texels.materials), 2);
+    }
+
+    /// Upload a box of one level of the occupancy pyramid
+    pub(super) fn write_occupancy(&self, queue: &Queue, occupancy: &OccupancyPyramid, level: u32, origin: [u32; 3], extent: [u32; 3]) {
+        write_box(queue, &self.empty_space, level, origin, extent, &occupancy.region(level, origin, extent), 1);
+    }
+
+    /// Upload every level of the occupancy pyramid
+    pub(super) fn write_pyramid(&self, queue: &Queue, occupancy: &OccupancyPyramid) {
+        for level in 0..self.empty_space.mip_level_count() {
+            let size = occupancy.level_size(level);
+            self.write_occupancy(queue, occupancy, level, [0; 3], [size; 3]);
+        }
+    }
+
+    /// Upload a box of the distance field
+    pub(super) fn write_distance_field(&self, queue: &Queue, field: &DistanceField, origin: [u32; 3], extent: [u32; 3]) {
+        write_box(queue, &self.empty_space, 0, origin, extent, &field.region(origin, extent), 1);
+    }
+
+    /// Bind group for the dense backend: the volumes, the empty space volume and the material table
+    pub(super) fn bind(&self, device: &Device, material_buffer: &Buffer) -> (BindGroupLayout, BindGroup) {
         let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("Octree Bind Group Layout"),
             entries: &[
@@ -264,7 +331,7 @@ impl OctreeProvider for StaticOctreeProvider {
                     binding: 0,
                     visibility: ShaderStages::COMPUTE,
                     ty: BindingType::Texture {
-                        sample_type: TextureSampleType::Float { filterable: true },
+                        sample_type: TextureSampleType::Float { filterable: false },
                         view_dimension: TextureViewDimension::D3,
                         multisampled: false,
                     },
@@ -273,28 +340,118 @@ impl OctreeProvider for StaticOctreeProvider {
                 BindGroupLayoutEntry {
                     binding: 1,
                     visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Uint,
+                        view_dimension: TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: self.empty_space_type,
+                        view_dimension: TextureViewDimension::D3,
+                        multisampled: false,
+                    },
                     count: None,
                 },
+                material::material_layout_entry(),
             ],
         });
 
-        // Create bind group
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("Octree Bind Group"),
             layout: &bind_group_layout,
             entries: &[
                 BindGroupEntry {
                     binding: 0,
-                    resource: BindingResource::TextureView(self.texture_view.as_ref().unwrap()),
+                    resource: BindingResource::TextureView(&self.color_view),
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: BindingResource::Sampler(self.sampler.as_ref().unwrap()),
+                    resource: BindingResource::TextureView(&self.material_view),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&self.empty_space_view),
+                },
+                material::material_bind_entry(material_buffer),
             ],
         });
 
         (bind_group_layout, bind_group)
     }
-}
\ No newline at end of file
+}
+
+/// Copy tightly packed texels into a box of one mip level of a 3D texture
+fn write_box(queue: &Queue, texture: &Texture, mip_level: u32, origin: [u32; 3], extent: [u32; 3], data: &[u8], bytes_per_texel: u32) {
+    queue.write_texture(
+        TexelCopyTextureInfo {
+            texture,
+            mip_level,
+            origin: Origin3d { x: origin[0], y: origin[1], z: origin[2] },
+            aspect: TextureAspect::All,
+        },
+        data,
+        TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(bytes_per_texel * extent[0]),
+            rows_per_image: Some(extent[1]),
+        },
+        Extent3d {
+            width: extent[0],
+            height: extent[1],
+            depth_or_array_layers: extent[2],
+        },
+    );
+}
+
+/// Bind group for the sparse backend: the node storage buffer, its header and the material table
+pub(super) fn bind_node_buffer(device: &Device, node_buffer: &Buffer, header_buffer: &Buffer, material_buffer: &Buffer) -> (BindGroupLayout, BindGroup) {
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Sparse Octree Bind Group Layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            material::material_layout_entry(),
+        ],
+    });
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Sparse Octree Bind Group"),
+        layout: &bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: node_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: header_buffer.as_entire_binding(),
+            },
+            material::material_bind_entry(material_buffer),
+        ],
+    });
+
+    (bind_group_layout, bind_group)
+}
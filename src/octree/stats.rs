@@ -0,0 +1,153 @@
+use super::{Octree, OctreeNode};
+use std::fmt;
+
+/// Size and content summary of an `Octree`, from `Octree::stats`
+#[derive(Clone, Debug, Default)]
+pub struct OctreeStats {
+    /// Nodes at each depth, root first
+    pub nodes_per_level: Vec<usize>,
+    pub node_count: usize,
+    pub leaf_count: usize,
+    /// Nodes holding data with non-zero density, interior LoD nodes included
+    pub occupied_nodes: usize,
+    pub empty_nodes: usize,
+    /// Heap held by child arrays; the root node itself is inline
+    pub heap_bytes: usize,
+    /// Finest-level voxels covered by occupied leaves
+    pub voxel_count: u64,
+    /// Kind and finest-level voxel count of each material table entry
+    pub material_voxels: Vec<(&'static str, u64)>,
+    /// Finest-level voxels that emit light
+    pub emissive_voxels: u64,
+}
+
+impl Octree {
+    /// Walk the whole tree and summarize its structure, memory and materials.
+    /// Voxel counts weigh each collapsed leaf by the finest-level cells it covers.
+    pub fn stats(&self) -> OctreeStats {
+        let mut stats = OctreeStats {
+            nodes_per_level: vec![0; self.max_depth as usize + 1],
+            material_voxels: self.materials.materials().iter().map(|material| (material.kind(), 0)).collect(),
+            ..Default::default()
+        };
+        self.stats_recursive(&self.root, 0, &mut stats);
+        stats.heap_bytes = (stats.node_count - 1) * std::mem::size_of::<OctreeNode>();
+        stats
+    }
+
+    fn stats_recursive(&self, node: &OctreeNode, depth: u8, stats: &mut OctreeStats) {
+        stats.nodes_per_level[depth as usize] += 1;
+        stats.node_count += 1;
+
+        let occupied = node.voxel_data.filter(|data| data.density > 0.0);
+        if occupied.is_some() {
+            stats.occupied_nodes += 1;
+        } else {
+            stats.empty_nodes += 1;
+        }
+
+        match node.children {
+            Some(ref children) => {
+                for child in children.iter() {
+                    self.stats_recursive(child, depth + 1, stats);
+                }
+            }
+            None => {
+                stats.leaf_count += 1;
+                if let Some(data) = occupied {
+                    let voxels = 1u64 << (3 * self.max_depth.saturating_sub(depth) as u32);
+                    stats.voxel_count += voxels;
+                    stats.material_voxels[self.materials.lookup(&data) as usize].1 += voxels;
+                    if self.materials.material_of(&data).is_emissive() {
+                        stats.emissive_voxels += voxels;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for OctreeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Nodes:            {} ({} leaves)", self.node_count, self.leaf_count)?;
+        writeln!(f, "Occupied / empty: {} / {}", self.occupied_nodes, self.empty_nodes)?;
+        writeln!(f, "Heap:             {:.2} MB", self.heap_bytes as f64 / (1024.0 * 1024.0))?;
+        writeln!(f, "Voxels:           {} ({} emissive)", self.voxel_count, self.emissive_voxels)?;
+
+        writeln!(f, "Nodes per level:")?;
+        for (level, count) in self.nodes_per_level.iter().enumerate() {
+            writeln!(f, "  {:>2}: {}", level, count)?;
+        }
+
+        writeln!(f, "Materials (voxels):")?;
+        for (index, &(kind, count)) in self.material_voxels.iter().enumerate() {
+            if count > 0 {
+                writeln!(f, "  {:>3} {:<9} {} ({:.1}%)", index, kind, count, count as f64 * 100.0 / self.voxel_count as f64)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::VoxelData;
+    use crate::octree::material::Material;
+    use nalgebra as na;
+
+    /// Depth 2 octree over -1..1: a collapsed diffuse octant, and a lamp
+    /// and a metal voxel in the opposite octant. Glass goes unused.
+    fn octree() -> Octree {
+        let mut octree = Octree::new(na::Vector3::zeros(), 1.0, 2);
+        let lamp = octree.materials.insert(Material::emissive([1.0, 0.9, 0.8], 5.0));
+        let metal = octree.materials.insert(Material::metal(0.2));
+        octree.materials.insert(Material::glass(1.5));
+
+        let wall = VoxelData::solid([0.73; 3]);
+        for i in 0..8 {
+            let offset = na::Vector3::new(i & 1, i >> 1 & 1, i >> 2).map(|bit| bit as f32 * 0.5);
+            octree.insert(na::Vector3::repeat(-0.75) + offset, wall);
+        }
+        octree.insert(na::Vector3::new(0.75, 0.75, 0.75), VoxelData { material: lamp, ..VoxelData::solid([1.0; 3]) });
+        octree.insert(na::Vector3::new(0.25, 0.75, 0.75), VoxelData { material: metal, ..VoxelData::solid([0.5; 3]) });
+        octree
+    }
+
+    #[test]
+    fn counts_nodes_voxels_and_materials() {
+        let stats = octree().stats();
+
+        assert_eq!(stats.nodes_per_level, vec![1, 8, 8]);
+        assert_eq!(stats.node_count, 17);
+        assert_eq!(stats.leaf_count, 15);
+        // Root, the two occupied octants and the two voxels in the split one
+        assert_eq!(stats.occupied_nodes, 5);
+        assert_eq!(stats.empty_nodes, 12);
+        assert_eq!(stats.heap_bytes, 16 * std::mem::size_of::<OctreeNode>());
+
+        // The collapsed octant covers 8 voxels
+        assert_eq!(stats.voxel_count, 10);
+        assert_eq!(stats.emissive_voxels, 1);
+        assert_eq!(stats.material_voxels, vec![("diffuse", 8), ("emissive", 1), ("metallic", 1), ("glass", 0)]);
+    }
+
+    #[test]
+    fn display_skips_unused_materials() {
+        let text = octree().stats().to_string();
+        assert!(text.contains("Nodes:            17 (15 leaves)\n"));
+        assert!(text.contains("Voxels:           10 (1 emissive)\n"));
+        assert!(text.contains("   2: 8\n"));
+        assert!(text.contains("    0 diffuse   8 (80.0%)\n"));
+        assert!(text.contains("    1 emissive  1 (10.0%)\n"));
+        assert!(!text.contains("glass"));
+    }
+
+    #[test]
+    fn empty_octree() {
+        let stats = Octree::new(na::Vector3::zeros(), 1.0, 3).stats();
+        assert_eq!(stats.nodes_per_level, vec![1, 0, 0, 0]);
+        assert_eq!((stats.node_count, stats.leaf_count, stats.empty_nodes), (1, 1, 1));
+        assert_eq!((stats.heap_bytes, stats.voxel_count), (0, 0));
+    }
+}
@@ -0,0 +1,857 @@
+use super::{GpuUpdate, Octree, OctreeProvider, VoxelData, lod_for_distance};
+use super::linear::{pack_rgba8, unpack_rgba8};
+use super::material::{self, Material, MaterialTable};
+use super::scene_file::{Reader, fnv1a, invalid};
+use bytemuck::{Pod, Zeroable};
+use nalgebra as na;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use wgpu::*;
+use wgpu::util::DeviceExt;
+use log::{info, warn};
+
+/// Voxels along each edge of a chunk
+pub const CHUNK_VOXELS: usize = 32;
+const CHUNK_VOXEL_COUNT: usize = CHUNK_VOXELS * CHUNK_VOXELS * CHUNK_VOXELS;
+const CHUNK_BYTES: u64 = (CHUNK_VOXEL_COUNT * 8) as u64;
+
+/// Fallback cells along each edge of a chunk, kept resident for every chunk
+pub const COARSE_VOXELS: usize = 4;
+const COARSE_COUNT: usize = COARSE_VOXELS * COARSE_VOXELS * COARSE_VOXELS;
+
+/// Page markers shared with `streaming.wgsl`
+const MISSING_CHUNK: u32 = u32::MAX;
+const NOT_RESIDENT: u32 = u32::MAX - 1;
+
+/// Chunks installed per frame, so a burst of finished loads cannot stall a frame
+const UPLOADS_PER_FRAME: usize = 8;
+
+/// Default load radius around the camera, in chunks
+const VIEW_DISTANCE_CHUNKS: f32 = 16.0;
+
+/// World manifest, little-endian, stored as `world.avpw` next to a `chunks/`
+/// directory holding one native octree file per chunk:
+///
+/// | field             | type            |
+/// |-------------------|-----------------|
+/// | magic `AVPW`      | [u8; 4]         |
+/// | version           | u32             |
+/// | origin            | [f32; 3]        |
+/// | voxel_size        | f32             |
+/// | material_count    | u32             |
+/// | materials         | [Material]      |
+/// | chunk_count       | u32             |
+/// | chunks            | see below       |
+/// | checksum          | u64             |
+///
+/// The material table is the octree's `MaterialTable` as 48-byte `Material`s;
+/// chunks loaded in any order have their voxels mapped onto it. Version 2
+/// stored an emission and material type per entry instead, and version 1
+/// manifests have no table and load with the default material only.
+/// Each chunk entry is its coordinate (`[i32; 3]`, in chunks from the origin)
+/// followed by 64 RGBA8 words: the 4x4x4 coarse fallback rendered while the
+/// chunk itself is not loaded. The checksum is FNV-1a as in `scene_file`.
+const MANIFEST: &str = "world.avpw";
+const MAGIC: &[u8; 4] = b"AVPW";
+const VERSION: u32 = 3;
+
+/// Page table entry for one chunk of the world grid
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuChunkPage {
+    pub atlas_slot: u32,
+    pub coarse_offset: u32,
+}
+
+/// Uniform header for `streaming.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct GpuStreamingHeader {
+    pub origin: [f32; 3],
+    pub voxel_size: f32,
+    pub grid_dims: [u32; 3],
+    pub _padding: u32,
+}
+
+impl Octree {
+    /// Split the octree into `CHUNK_VOXELS`³ chunks at its finest resolution and
+    /// write them with a world manifest into `directory`. Returns the chunk count.
+    pub fn save_world(&self, directory: impl AsRef<Path>) -> io::Result<usize> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory.join("chunks"))?;
+
+        let voxel_size = self.root.half_size * 2.0 / (1u32 << self.max_depth) as f32;
+        let origin = self.root.center.add_scalar(-self.root.half_size);
+        let chunk_extent = voxel_size * CHUNK_VOXELS as f32;
+        let chunk_depth = CHUNK_VOXELS.trailing_zeros() as u8;
+
+        let mut chunks: HashMap<na::Vector3<i32>, Octree> = HashMap::new();
+        for (center, half_size, data) in self.leaves() {
+            let to_voxel = |world: na::Vector3<f32>| ((world - origin) / voxel_size).map(|v| v.round() as i32);
+            let first = to_voxel(center.add_scalar(-half_size));
+            let last = to_voxel(center.add_scalar(half_size));
+
+            for z in first.z..last.z {
+                for y in first.y..last.y {
+                    for x in first.x..last.x {
+                        let voxel = na::Vector3::new(x, y, z);
+                        let coord = voxel.map(|v| v.div_euclid(CHUNK_VOXELS as i32));
+                        let chunk = chunks.entry(coord).or_insert_with(|| {
+                            let chunk_center = origin + coord.map(|c| (c as f32 + 0.5) * chunk_extent);
+                            let mut chunk = Octree::new(chunk_center, chunk_extent * 0.5, chunk_depth);
+                            chunk.materials = self.materials.clone();
+                            chunk
+                        });
+                        chunk.insert(origin + voxel.map(|v| (v as f32 + 0.5) * voxel_size), data);
+                    }
+                }
+            }
+        }
+
+        let mut manifest = Vec::new();
+        manifest.extend_from_slice(MAGIC);
+        manifest.extend_from_slice(&VERSION.to_le_bytes());
+        for value in origin.iter() {
+            manifest.extend_from_slice(&value.to_le_bytes());
+        }
+        manifest.extend_from_slice(&voxel_size.to_le_bytes());
+
+        manifest.extend_from_slice(&(self.materials.len() as u32).to_le_bytes());
+        manifest.extend_from_slice(bytemuck::cast_slice(self.materials.materials()));
+        manifest.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+
+        for (coord, chunk) in &chunks {
+            chunk.save(chunk_path(directory, coord))?;
+
+            for value in coord.iter() {
+                manifest.extend_from_slice(&value.to_le_bytes());
+            }
+            for cell in coarse_cells(chunk) {
+                manifest.extend_from_slice(&cell.to_le_bytes());
+            }
+        }
+
+        let checksum = fnv1a(&manifest);
+        manifest.extend_from_slice(&checksum.to_le_bytes());
+        fs::write(directory.join(MANIFEST), manifest)?;
+
+        info!("Saved world to {}: {} chunks of {}³ voxels", directory.display(), chunks.len(), CHUNK_VOXELS);
+        Ok(chunks.len())
+    }
+}
+
+fn chunk_path(directory: &Path, coord: &na::Vector3<i32>) -> PathBuf {
+    directory.join("chunks").join(format!("{}_{}_{}.avpo", coord.x, coord.y, coord.z))
+}
+
+/// Coarse fallback of a chunk from its LoD data. Any coverage makes a cell
+/// opaque so that thin walls do not vanish while the chunk is loading.
+fn coarse_cells(chunk: &Octree) -> Vec<u32> {
+    let cell_size = chunk.root.half_size * 2.0 / COARSE_VOXELS as f32;
+    let chunk_min = chunk.root.center.add_scalar(-chunk.root.half_size);
+    let lod_level = chunk.max_depth - COARSE_VOXELS.trailing_zeros() as u8;
+
+    let mut cells = Vec::with_capacity(COARSE_COUNT);
+    for z in 0..COARSE_VOXELS {
+        for y in 0..COARSE_VOXELS {
+            for x in 0..COARSE_VOXELS {
+                let center = chunk_min + na::Vector3::new(x, y, z).map(|i| (i as f32 + 0.5) * cell_size);
+                let mut data = chunk.sample(center, lod_level);
+                if data.density > 0.0 {
+                    data.density = 1.0;
+                }
+                cells.push(pack_rgba8(&data));
+            }
+        }
+    }
+    cells
+}
+
+/// Rasterize a chunk octree into `CHUNK_VOXELS`³ pairs of RGBA8 color and
+/// index into the world's `materials`, x fastest
+fn pack_chunk(chunk: &Octree, materials: &MaterialTable) -> Vec<[u32; 2]> {
+    let voxel_size = chunk.root.half_size * 2.0 / CHUNK_VOXELS as f32;
+    let chunk_min = chunk.root.center.add_scalar(-chunk.root.half_size);
+    let mut voxels = vec![[0; 2]; CHUNK_VOXEL_COUNT];
+
+    for (center, half_size, data) in chunk.leaves() {
+        let to_voxel = |world: na::Vector3<f32>| {
+            ((world - chunk_min) / voxel_size).map(|v| (v.round().max(0.0) as usize).min(CHUNK_VOXELS))
+        };
+        let first = to_voxel(center.add_scalar(-half_size));
+        let last = to_voxel(center.add_scalar(half_size));
+        let material = materials.find(chunk.materials.material_of(&data)).unwrap_or(0);
+        let packed = [pack_rgba8(&data), material];
+
+        for z in first.z..last.z {
+            for y in first.y..last.y {
+                for x in first.x..last.x {
+                    voxels[(z * CHUNK_VOXELS + y) * CHUNK_VOXELS + x] = packed;
+                }
+            }
+        }
+    }
+    voxels
+}
+
+/// Result of a background chunk load
+struct LoadedChunk {
+    coord: na::Vector3<i32>,
+    result: io::Result<(Octree, Vec<[u32; 2]>)>,
+}
+
+struct ResidentChunk {
+    octree: Octree,
+    slot: u32,
+    last_used: u64,
+}
+
+/// Out-of-core provider for worlds written by `Octree::save_world`.
+/// Only the manifest and the coarse fallback stay in memory; chunks around the
+/// camera are loaded by a background thread, kept within a resident budget with
+/// LRU eviction, and copied into a GPU chunk atlas addressed by a page table.
+/// Chunks that are still loading render from their coarse 4x4x4 fallback.
+pub struct StreamingOctreeProvider {
+    origin: na::Vector3<f32>,
+    voxel_size: f32,
+    grid_min: na::Vector3<i32>,
+    grid_dims: na::Vector3<u32>,
+    pages: Vec<GpuChunkPage>,
+    coarse: Vec<u32>,
+    materials: Arc<MaterialTable>,
+    resident: HashMap<na::Vector3<i32>, ResidentChunk>,
+    pending: HashSet<na::Vector3<i32>>,
+    failed: HashSet<na::Vector3<i32>>,
+    resident_budget: usize,
+    free_slots: Vec<u32>,
+    view_distance: f32,
+    frame: u64,
+    requests: Sender<na::Vector3<i32>>,
+    results: Mutex<Receiver<LoadedChunk>>,
+    page_buffer: Option<Buffer>,
+    atlas_buffer: Option<Buffer>,
+    coarse_buffer: Option<Buffer>,
+    header_buffer: Option<Buffer>,
+    material_buffer: Option<Buffer>,
+    base_voxel_size: f32,
+}
+
+impl StreamingOctreeProvider {
+    /// Open a world directory, keeping at most `resident_budget` chunks loaded
+    pub fn open(directory: impl AsRef<Path>, resident_budget: usize) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let bytes = fs::read(directory.join(MANIFEST))?;
+        if bytes.len() < 8 {
+            return Err(invalid("manifest too short"));
+        }
+
+        let (body, checksum) = bytes.split_at(bytes.len() - 8);
+        if fnv1a(body) != u64::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(invalid("manifest checksum mismatch"));
+        }
+
+        let mut reader = Reader::new(body);
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a world manifest"));
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(invalid(&format!("unsupported world version {}", version)));
+        }
+
+        let origin = na::Vector3::new(reader.f32()?, reader.f32()?, reader.f32()?);
+        let voxel_size = reader.f32()?;
+
+        let mut materials = Vec::new();
+        if version >= 2 {
+            let material_count = reader.u32()?;
+            for _ in 0..material_count {
+                materials.push(if version >= 3 {
+                    bytemuck::pod_read_unaligned(reader.take(std::mem::size_of::<Material>())?)
+                } else {
+                    let emission = [reader.f32()?, reader.f32()?, reader.f32()?];
+                    Material::legacy(reader.u32()?, emission)
+                });
+            }
+        }
+        let materials = MaterialTable::from_materials(materials);
+
+        let chunk_count = reader.u32()? as usize;
+        if reader.remaining() != chunk_count * (12 + COARSE_COUNT * 4) {
+            return Err(invalid("chunk table does not match header"));
+        }
+
+        let mut entries = Vec::with_capacity(chunk_count);
+        for _ in 0..chunk_count {
+            let coord = na::Vector3::new(reader.i32()?, reader.i32()?, reader.i32()?);
+            let cells = (0..COARSE_COUNT).map(|_| reader.u32()).collect::<io::Result<Vec<u32>>>()?;
+            entries.push((coord, cells));
+        }
+
+        // Page table over the bounding box of the stored chunks
+        let grid_min = entries.iter().fold(na::Vector3::repeat(i32::MAX), |m, (c, _)| m.inf(c));
+        let grid_max = entries.iter().fold(na::Vector3::repeat(i32::MIN), |m, (c, _)| m.sup(c));
+        let (grid_min, grid_dims) = if entries.is_empty() {
+            (na::Vector3::zeros(), na::Vector3::repeat(1))
+        } else {
+            (grid_min, (grid_max - grid_min).map(|v| v as u32 + 1))
+        };
+
+        let mut provider = Self {
+            origin,
+            voxel_size,
+            grid_min,
+            grid_dims,
+            pages: vec![GpuChunkPage { atlas_slot: MISSING_CHUNK, coarse_offset: 0 }; grid_dims.iter().product::<u32>() as usize],
+            coarse: Vec::with_capacity(chunk_count * COARSE_COUNT),
+            materials: Arc::new(materials),
+            resident: HashMap::new(),
+            pending: HashSet::new(),
+            failed: HashSet::new(),
+            resident_budget: resident_budget.max(1),
+            free_slots: Vec::new(),
+            view_distance: 0.0,
+            frame: 0,
+            requests: mpsc::channel().0,
+            results: Mutex::new(mpsc::channel().1),
+            page_buffer: None,
+            atlas_buffer: None,
+            coarse_buffer: None,
+            header_buffer: None,
+            material_buffer: None,
+            base_voxel_size: 0.02,
+        };
+
+        for (coord, cells) in entries {
+            let index = provider.page_index(&coord).unwrap();
+            provider.pages[index] = GpuChunkPage {
+                atlas_slot: NOT_RESIDENT,
+                coarse_offset: provider.coarse.len() as u32,
+            };
+            provider.coarse.extend_from_slice(&cells);
+        }
+
+        provider.view_distance = voxel_size * CHUNK_VOXELS as f32 * VIEW_DISTANCE_CHUNKS;
+
+        let (requests, results) = spawn_loader(directory.clone(), provider.materials.clone());
+        provider.requests = requests;
+        provider.results = Mutex::new(results);
+
+        info!("Opened world {}: {} chunks in a {}x{}x{} grid, {} materials, budget {} resident",
+              directory.display(), chunk_count, grid_dims.x, grid_dims.y, grid_dims.z,
+              provider.materials.materials().len(), provider.resident_budget);
+        Ok(provider)
+    }
+
+    /// Radius around the camera within which chunks are loaded
+    pub fn set_view_distance(&mut self, distance: f32) {
+        self.view_distance = distance;
+    }
+
+    pub fn resident_count(&self) -> usize {
+        self.resident.len()
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn chunk_extent(&self) -> f32 {
+        self.voxel_size * CHUNK_VOXELS as f32
+    }
+
+    fn page_index(&self, coord: &na::Vector3<i32>) -> Option<usize> {
+        let local = coord - self.grid_min;
+        if (0..3).any(|axis| local[axis] < 0 || local[axis] as u32 >= self.grid_dims[axis]) {
+            return None;
+        }
+        let local = local.map(|v| v as usize);
+        let dims = self.grid_dims.map(|v| v as usize);
+        Some((local.z * dims.y + local.y) * dims.x + local.x)
+    }
+
+    fn chunk_coord(&self, position: &na::Vector3<f32>) -> na::Vector3<i32> {
+        ((position - self.origin) / self.chunk_extent()).map(|v| v.floor() as i32)
+    }
+
+    /// Create the page table, coarse fallback, material table and chunk atlas. The atlas holds
+    /// the resident budget, capped by the device's storage binding limit.
+    pub fn create_gpu_resources(&mut self, device: &Device, _queue: &Queue) {
+        let max_slots = (device.limits().max_storage_buffer_binding_size as u64 / CHUNK_BYTES) as usize;
+        if self.resident_budget > max_slots {
+            warn!("Resident budget {} exceeds the device limit, using {}", self.resident_budget, max_slots);
+            self.resident_budget = max_slots;
+        }
+        self.free_slots = (0..self.resident_budget as u32).rev().collect();
+
+        self.page_buffer = Some(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Chunk Page Table Buffer"),
+            contents: bytemuck::cast_slice(&self.pages),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        }));
+
+        // Storage bindings cannot be empty
+        let coarse: &[u32] = if self.coarse.is_empty() { &[0] } else { &self.coarse };
+        self.coarse_buffer = Some(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Chunk Coarse Buffer"),
+            contents: bytemuck::cast_slice(coarse),
+            usage: BufferUsages::STORAGE,
+        }));
+
+        self.material_buffer = Some(self.materials.create_buffer(device));
+
+        self.atlas_buffer = Some(device.create_buffer(&BufferDescriptor {
+            label: Some("Chunk Atlas Buffer"),
+            size: self.resident_budget as u64 * CHUNK_BYTES,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+
+        let header = GpuStreamingHeader {
+            origin: (self.origin + self.grid_min.map(|v| v as f32 * self.chunk_extent())).into(),
+            voxel_size: self.voxel_size,
+            grid_dims: self.grid_dims.into(),
+            _padding: 0,
+        };
+        self.header_buffer = Some(device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Streaming Header Buffer"),
+            contents: bytemuck::cast_slice(&[header]),
+            usage: BufferUsages::UNIFORM,
+        }));
+
+        info!("Created chunk atlas: {} slots, {:.2} MB",
+              self.resident_budget, (self.resident_budget as u64 * CHUNK_BYTES) as f64 / (1024.0 * 1024.0));
+    }
+
+    /// Block until every requested chunk has arrived and been uploaded, for
+    /// one-off renders that must not show the coarse fallback
+    pub fn wait_for_pending(&mut self, queue: &Queue) {
+        while !self.pending.is_empty() {
+            let loaded = self.results.lock().unwrap().recv();
+            match loaded {
+                Ok(loaded) => {
+                    self.install(loaded, queue);
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Place a loaded chunk in a free atlas slot, evicting the least recently
+    /// used chunk the camera no longer needs if the atlas is full. Returns
+    /// false when the chunk could not be placed.
+    fn install(&mut self, loaded: LoadedChunk, queue: &Queue) -> bool {
+        let LoadedChunk { coord, result } = loaded;
+        self.pending.remove(&coord);
+
+        let (octree, voxels) = match result {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("Failed to load chunk ({}, {}, {}): {}", coord.x, coord.y, coord.z, e);
+                self.failed.insert(coord);
+                return false;
+            }
+        };
+
+        let Some(index) = self.page_index(&coord) else {
+            return false;
+        };
+        if self.atlas_buffer.is_none() {
+            return false;
+        }
+        let Some(slot) = self.free_slots.pop().or_else(|| self.evict_lru(queue)) else {
+            // Every slot holds a chunk in view; this one is requested again later
+            return false;
+        };
+
+        if let Some(ref atlas_buffer) = self.atlas_buffer {
+            queue.write_buffer(atlas_buffer, slot as u64 * CHUNK_BYTES, bytemuck::cast_slice(&voxels));
+        }
+        self.set_page_slot(index, slot, queue);
+        self.resident.insert(coord, ResidentChunk { octree, slot, last_used: self.frame });
+        true
+    }
+
+    fn evict_lru(&mut self, queue: &Queue) -> Option<u32> {
+        let (&coord, _) = self.resident.iter()
+            .filter(|(_, chunk)| chunk.last_used < self.frame)
+            .min_by_key(|(_, chunk)| chunk.last_used)?;
+
+        let chunk = self.resident.remove(&coord)?;
+        if let Some(index) = self.page_index(&coord) {
+            self.set_page_slot(index, NOT_RESIDENT, queue);
+        }
+        Some(chunk.slot)
+    }
+
+    fn set_page_slot(&mut self, index: usize, slot: u32, queue: &Queue) {
+        self.pages[index].atlas_slot = slot;
+        if let Some(ref page_buffer) = self.page_buffer {
+            let offset = (index * std::mem::size_of::<GpuChunkPage>()) as u64;
+            queue.write_buffer(page_buffer, offset, bytemuck::bytes_of(&self.pages[index]));
+        }
+    }
+}
+
+/// Background thread reading chunk files; it exits when the provider is dropped
+fn spawn_loader(directory: PathBuf, materials: Arc<MaterialTable>) -> (Sender<na::Vector3<i32>>, Receiver<LoadedChunk>) {
+    let (request_sender, request_receiver) = mpsc::channel::<na::Vector3<i32>>();
+    let (result_sender, result_receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name("chunk-loader".to_string())
+        .spawn(move || {
+            for coord in request_receiver {
+                let result = Octree::load(chunk_path(&directory, &coord)).map(|octree| {
+                    let voxels = pack_chunk(&octree, &materials);
+                    (octree, voxels)
+                });
+                if result_sender.send(LoadedChunk { coord, result }).is_err() {
+                    break;
+                }
+            }
+        })
+        .expect("failed to spawn chunk loader thread");
+
+    (request_sender, result_receiver)
+}
+
+impl OctreeProvider for StreamingOctreeProvider {
+    fn sample_voxel(&self, position: na::Vector3<f32>, distance_from_camera: f32) -> VoxelData {
+        let coord = self.chunk_coord(&position);
+        if let Some(chunk) = self.resident.get(&coord) {
+            let lod_level = lod_for_distance(distance_from_camera);
+            return chunk.octree.sample(position, lod_level.min(chunk.octree.max_depth));
+        }
+
+        let Some(index) = self.page_index(&coord) else {
+            return VoxelData::empty();
+        };
+        let page = self.pages[index];
+        if page.atlas_slot == MISSING_CHUNK {
+            return VoxelData::empty();
+        }
+
+        let cell_size = self.chunk_extent() / COARSE_VOXELS as f32;
+        let chunk_min = self.origin + coord.map(|v| v as f32 * self.chunk_extent());
+        let cell = ((position - chunk_min) / cell_size).map(|v| (v.max(0.0) as usize).min(COARSE_VOXELS - 1));
+        let offset = (cell.z * COARSE_VOXELS + cell.y) * COARSE_VOXELS + cell.x;
+        unpack_rgba8(self.coarse[page.coarse_offset as usize + offset])
+    }
+
+    fn set_performance_target(&mut self, target_voxel_size: f32) {
+        self.base_voxel_size = target_voxel_size;
+    }
+
+    fn get_bounds(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let min = self.origin + self.grid_min.map(|v| v as f32 * self.chunk_extent());
+        (min, min + self.grid_dims.map(|v| v as f32 * self.chunk_extent()))
+    }
+
+    fn get_grid_dims(&self) -> na::Vector3<u32> {
+        self.grid_dims * CHUNK_VOXELS as u32
+    }
+
+    /// Request the nearest chunks within the view distance, up to the budget,
+    /// and mark the resident ones as recently used
+    fn update_view(&mut self, eye: na::Point3<f32>) {
+        let chunk_extent = self.chunk_extent();
+        let center = self.chunk_coord(&eye.coords);
+        let radius = (self.view_distance / chunk_extent).ceil() as i32;
+
+        // Only the part of the view cube that overlaps stored chunks is scanned
+        let first = (center.add_scalar(-radius) - self.grid_min).map(|v| v.max(0));
+        let last = (center.add_scalar(radius) - self.grid_min)
+            .zip_map(&self.grid_dims, |v, dim| v.min(dim as i32 - 1));
+
+        let mut wanted = Vec::new();
+        for z in first.z..=last.z {
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    let coord = self.grid_min + na::Vector3::new(x, y, z);
+                    let Some(index) = self.page_index(&coord) else {
+                        continue;
+                    };
+                    if self.pages[index].atlas_slot == MISSING_CHUNK || self.failed.contains(&coord) {
+                        continue;
+                    }
+                    let chunk_center = self.origin + coord.map(|v| (v as f32 + 0.5) * chunk_extent);
+                    let distance = (chunk_center - eye.coords).norm();
+                    if distance <= self.view_distance + chunk_extent {
+                        wanted.push((distance, coord));
+                    }
+                }
+            }
+        }
+        wanted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        wanted.truncate(self.resident_budget);
+
+        for (_, coord) in wanted {
+            if let Some(chunk) = self.resident.get_mut(&coord) {
+                chunk.last_used = self.frame;
+            } else if self.pending.len() < self.resident_budget
+                && !self.pending.contains(&coord)
+                && self.requests.send(coord).is_ok()
+            {
+                // Nearest first, and never more in flight than can be resident
+                self.pending.insert(coord);
+            }
+        }
+    }
+
+    fn bind_gpu_resources(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Streaming Bind Group Layout"),
+            entries: &[
+                storage_entry(0),
+                storage_entry(1),
+                storage_entry(2),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                material::material_layout_entry(),
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Streaming Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: self.page_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.atlas_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: self.coarse_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: self.header_buffer.as_ref().unwrap().as_entire_binding(),
+                },
+                material::material_bind_entry(self.material_buffer.as_ref().unwrap()),
+            ],
+        });
+
+        (bind_group_layout, bind_group)
+    }
+
+    fn shader_source(&self) -> &'static str {
+        include_str!("../shaders/streaming.wgsl")
+    }
+
+    /// Install up to `UPLOADS_PER_FRAME` chunks finished by the loader thread
+    fn update_gpu_resources(&mut self, _device: &Device, queue: &Queue) -> GpuUpdate {
+        let loaded: Vec<LoadedChunk> = {
+            let results = self.results.lock().unwrap();
+            results.try_iter().take(UPLOADS_PER_FRAME).collect()
+        };
+        let mut installed = false;
+        for chunk in loaded {
+            installed |= self.install(chunk, queue);
+        }
+
+        self.frame += 1;
+        if installed { GpuUpdate::ContentChanged } else { GpuUpdate::Unchanged }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::test_scenes::{self, COLORS, Rng};
+    use std::io::ErrorKind;
+
+    /// Scratch world directory in the system temp directory, unique per test
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("avpw-{}-{}", std::process::id(), name))
+    }
+
+    /// 128³ voxels, so 4³ chunks, scattered over every chunk and several materials
+    fn world() -> Octree {
+        let mut octree = Octree::new(na::Vector3::new(0.0, 1.0, 0.0), 4.0, 7);
+        octree.materials.insert(Material::metal(0.3));
+        let lamp = octree.materials.insert(Material::emissive([1.0, 0.9, 0.8], 4.0));
+        test_scenes::insert_cells(&mut octree, &test_scenes::random_voxels(&mut Rng::new(0x68e31da4), 2000, 128, lamp + 1));
+        octree
+    }
+
+    #[test]
+    fn chunks_keep_every_voxel() {
+        // One collapsed 64³ leaf spanning eight chunks
+        let mut octree = world();
+        let octant = &mut octree.root.children.as_mut().unwrap()[0];
+        octant.children = None;
+        octant.voxel_data = Some(VoxelData { material: 2, ..VoxelData::solid(COLORS[2]) });
+        octree.build_lod();
+
+        let directory = scratch("voxels");
+        let chunk_count = octree.save_world(&directory).unwrap();
+        let provider = StreamingOctreeProvider::open(&directory, 4).unwrap();
+        assert_eq!(provider.grid_min, na::Vector3::zeros());
+        assert_eq!(provider.grid_dims, na::Vector3::repeat(4));
+
+        let mut chunks = HashMap::new();
+        for z in 0..4 {
+            for y in 0..4 {
+                for x in 0..4 {
+                    let coord = na::Vector3::new(x, y, z);
+                    if let Ok(chunk) = Octree::load(chunk_path(&directory, &coord)) {
+                        assert_eq!(chunk.materials.materials(), octree.materials.materials());
+                        chunks.insert(coord, chunk);
+                    }
+                }
+            }
+        }
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(chunks.len(), chunk_count);
+
+        let root_min = octree.root.center.add_scalar(-octree.root.half_size);
+        let mut expected = vec![None; 128 * 128 * 128];
+        rasterize(&octree, root_min, &mut expected);
+        let mut chunked = vec![None; 128 * 128 * 128];
+        for chunk in chunks.values() {
+            rasterize(chunk, root_min, &mut chunked);
+        }
+        for (index, (chunked, expected)) in chunked.iter().zip(&expected).enumerate() {
+            assert_eq!(chunked, expected, "voxel {}", index);
+        }
+    }
+
+    /// Write the data of every leaf into the 128³ voxels of `world` it covers
+    fn rasterize(octree: &Octree, world_min: na::Vector3<f32>, voxels: &mut [Option<VoxelData>]) {
+        for (center, half_size, data) in octree.leaves() {
+            let to_voxel = |world: na::Vector3<f32>| ((world - world_min) * 16.0).map(|v| v.round() as usize);
+            let (first, last) = (to_voxel(center.add_scalar(-half_size)), to_voxel(center.add_scalar(half_size)));
+            for z in first.z..last.z {
+                for y in first.y..last.y {
+                    for x in first.x..last.x {
+                        voxels[(z * 128 + y) * 128 + x] = Some(data);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn coarse_cells_mark_any_coverage_opaque() {
+        let mut chunk = Octree::new(na::Vector3::zeros(), 1.0, CHUNK_VOXELS.trailing_zeros() as u8);
+        let cells = test_scenes::random_voxels(&mut Rng::new(0x7feb352d), 20, CHUNK_VOXELS as u32, 1);
+        test_scenes::insert_cells(&mut chunk, &cells);
+
+        // A single voxel is 1/512 of its coarse cell, far below one RGBA8 step
+        let cell_voxels = (CHUNK_VOXELS / COARSE_VOXELS) as u32;
+        for (index, packed) in coarse_cells(&chunk).into_iter().enumerate() {
+            let cell = [index % COARSE_VOXELS, index / COARSE_VOXELS % COARSE_VOXELS, index / (COARSE_VOXELS * COARSE_VOXELS)];
+            let covered = cells.iter().any(|(voxel, _)| (0..3).all(|axis| voxel[axis] / cell_voxels == cell[axis] as u32));
+            let density = unpack_rgba8(packed).density;
+            assert_eq!(density, if covered { 1.0 } else { 0.0 }, "cell {:?}", cell);
+        }
+    }
+
+    #[test]
+    fn pack_chunk_remaps_materials() {
+        let metal = Material::metal(0.3);
+        let lamp = Material::emissive([1.0, 0.9, 0.8], 4.0);
+        let rough = Material::metal(0.9);
+
+        let mut chunk = Octree::new(na::Vector3::zeros(), 1.0, CHUNK_VOXELS.trailing_zeros() as u8);
+        let voxels: Vec<_> = [metal, lamp, rough].into_iter().enumerate().map(|(i, material)| {
+            let data = VoxelData { material: chunk.materials.insert(material), ..VoxelData::solid(COLORS[i]) };
+            ([i as u32 * 5, 3, 7], data)
+        }).collect();
+        test_scenes::insert_cells(&mut chunk, &voxels);
+
+        // The world table holds the same materials in another order, without the rough metal
+        let world = MaterialTable::from_materials(vec![Material::default(), lamp, metal]);
+        let packed = pack_chunk(&chunk, &world);
+        let index = |[x, y, z]: [u32; 3]| (z as usize * CHUNK_VOXELS + y as usize) * CHUNK_VOXELS + x as usize;
+        for ((cell, data), expected) in voxels.iter().zip([2, 1, 0]) {
+            assert_eq!(packed[index(*cell)], [pack_rgba8(data), expected]);
+        }
+        assert_eq!(packed.iter().filter(|voxel| voxel[0] != 0).count(), voxels.len());
+    }
+
+    #[test]
+    fn open_rejects_damaged_manifests() {
+        let octree = world();
+        let directory = scratch("manifest");
+        octree.save_world(&directory).unwrap();
+        let manifest = directory.join(MANIFEST);
+        let bytes = fs::read(&manifest).unwrap();
+
+        let mut corrupted = bytes.clone();
+        corrupted[30] ^= 1;
+        fs::write(&manifest, corrupted).unwrap();
+        let error = StreamingOctreeProvider::open(&directory, 4).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "manifest checksum mismatch");
+
+        // One chunk more than the table holds, with a checksum that matches
+        let count_offset = 28 + octree.materials.len() * std::mem::size_of::<Material>();
+        let mut body = bytes[..bytes.len() - 8].to_vec();
+        let chunk_count = u32::from_le_bytes(body[count_offset..count_offset + 4].try_into().unwrap());
+        body[count_offset..count_offset + 4].copy_from_slice(&(chunk_count + 1).to_le_bytes());
+        let checksum = fnv1a(&body);
+        body.extend_from_slice(&checksum.to_le_bytes());
+        fs::write(&manifest, body).unwrap();
+        let error = StreamingOctreeProvider::open(&directory, 4).err().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "chunk table does not match header");
+    }
+
+    #[test]
+    fn update_view_requests_nearest_chunks_within_budget() {
+        let directory = scratch("view");
+        world().save_world(&directory).unwrap();
+        let mut provider = StreamingOctreeProvider::open(&directory, 3).unwrap();
+
+        // Inside chunk (0, 0, 0), nearer its +z face than its +y and +x faces
+        let chunk_extent = provider.chunk_extent();
+        let eye = provider.origin + na::Vector3::new(0.6, 0.7, 0.8) * chunk_extent;
+        provider.update_view(na::Point3::from(eye));
+        provider.update_view(na::Point3::from(eye));
+        assert_eq!(provider.pending_count(), 3);
+
+        // The loader thread answers in request order
+        let results = provider.results.lock().unwrap();
+        let order: Vec<_> = (0..3).map(|_| {
+            let loaded = results.recv().unwrap();
+            assert!(loaded.result.is_ok());
+            loaded.coord
+        }).collect();
+        assert!(results.recv_timeout(std::time::Duration::from_millis(100)).is_err());
+        drop(results);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(order, [na::Vector3::new(0, 0, 0), na::Vector3::new(0, 0, 1), na::Vector3::new(0, 1, 0)]);
+    }
+}
@@ -0,0 +1,339 @@
+use super::material::{Material, MaterialTable};
+use super::scene_file::{invalid, Reader};
+use super::{Octree, VoxelData};
+use nalgebra as na;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Voxels of a single MagicaVoxel model in its local grid
+#[derive(Clone, Debug)]
+pub struct VoxModel {
+    pub size: [u32; 3],
+    pub voxels: Vec<[u8; 4]>,  // x, y, z, palette index
+}
+
+/// Placement of a model produced by walking the scene graph
+#[derive(Clone, Debug)]
+pub struct VoxInstance {
+    pub model: usize,
+    pub rotation: na::Matrix3<i32>,
+    pub translation: na::Vector3<i32>,
+}
+
+/// Contents of a MagicaVoxel `.vox` file
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    pub instances: Vec<VoxInstance>,
+    /// Voxel data for each palette index, with MATL chunks already applied
+    pub palette: Vec<VoxelData>,
+    /// Materials the palette voxels refer to
+    pub materials: MaterialTable,
+}
+
+/// Scene graph node (nTRN, nGRP or nSHP chunk)
+enum SceneNode {
+    Transform { child: i32, rotation: na::Matrix3<i32>, translation: na::Vector3<i32> },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+type Dict = HashMap<String, String>;
+
+/// Deepest scene graph nesting we follow, guarding against cyclic files
+const MAX_GRAPH_DEPTH: u32 = 64;
+
+impl VoxScene {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<VoxScene> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<VoxScene> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(4)? != b"VOX " {
+            return Err(invalid("not a MagicaVoxel file"));
+        }
+        let _version = reader.u32()?;
+
+        // MAIN holds every other chunk as its children
+        let (id, _, children) = read_chunk(&mut reader)?;
+        if id != b"MAIN" {
+            return Err(invalid("missing MAIN chunk"));
+        }
+
+        let mut models = Vec::new();
+        let mut pending_size = None;
+        let mut colors = default_palette();
+        let mut materials: HashMap<u8, Dict> = HashMap::new();
+        let mut nodes: HashMap<i32, SceneNode> = HashMap::new();
+
+        let mut reader = Reader::new(children);
+        while reader.remaining() > 0 {
+            let (id, content, _) = read_chunk(&mut reader)?;
+            let mut chunk = Reader::new(content);
+
+            match id {
+                b"SIZE" => {
+                    pending_size = Some([chunk.u32()?, chunk.u32()?, chunk.u32()?]);
+                }
+                b"XYZI" => {
+                    let size = pending_size.take().ok_or_else(|| invalid("XYZI chunk without SIZE"))?;
+                    let count = chunk.u32()? as usize;
+                    let voxels = chunk.take(count.saturating_mul(4))?
+                        .chunks_exact(4)
+                        .map(|v| [v[0], v[1], v[2], v[3]])
+                        .collect();
+                    models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // Entry i of the chunk describes palette index i + 1
+                    for color in colors.iter_mut().skip(1) {
+                        let rgba = chunk.take(4)?;
+                        *color = [rgba[0], rgba[1], rgba[2], rgba[3]];
+                    }
+                }
+                b"MATL" => {
+                    let id = chunk.i32()?;
+                    let properties = read_dict(&mut chunk)?;
+                    if (1..256).contains(&id) {
+                        materials.insert(id as u8, properties);
+                    }
+                }
+                b"nTRN" => {
+                    let node_id = chunk.i32()?;
+                    let _attributes = read_dict(&mut chunk)?;
+                    let child = chunk.i32()?;
+                    let _reserved = chunk.i32()?;
+                    let _layer = chunk.i32()?;
+                    let frame_count = chunk.i32()?;
+
+                    let mut rotation = na::Matrix3::identity();
+                    let mut translation = na::Vector3::zeros();
+                    // Only the first animation frame is used
+                    if frame_count > 0 {
+                        let frame = read_dict(&mut chunk)?;
+                        if let Some(r) = frame.get("_r") {
+                            rotation = decode_rotation(r.parse().map_err(|_| invalid("bad _r value"))?);
+                        }
+                        if let Some(t) = frame.get("_t") {
+                            translation = parse_translation(t)?;
+                        }
+                    }
+                    nodes.insert(node_id, SceneNode::Transform { child, rotation, translation });
+                }
+                b"nGRP" => {
+                    let node_id = chunk.i32()?;
+                    let _attributes = read_dict(&mut chunk)?;
+                    let count = chunk.i32()?.max(0);
+                    let children = (0..count).map(|_| chunk.i32()).collect::<io::Result<_>>()?;
+                    nodes.insert(node_id, SceneNode::Group { children });
+                }
+                b"nSHP" => {
+                    let node_id = chunk.i32()?;
+                    let _attributes = read_dict(&mut chunk)?;
+                    let count = chunk.i32()?.max(0);
+                    let mut shape_models = Vec::new();
+                    for _ in 0..count {
+                        shape_models.push(chunk.i32()?);
+                        let _model_attributes = read_dict(&mut chunk)?;
+                    }
+                    nodes.insert(node_id, SceneNode::Shape { models: shape_models });
+                }
+                // PACK, LAYR, rOBJ, rCAM, NOTE, IMAP and future chunks are not needed
+                _ => {}
+            }
+        }
+
+        let mut instances = Vec::new();
+        if nodes.contains_key(&0) {
+            walk_graph(&nodes, 0, &na::Matrix3::identity(), &na::Vector3::zeros(), 0, &mut instances)?;
+        } else {
+            // Files without a scene graph place every model at the origin
+            instances = (0..models.len())
+                .map(|model| VoxInstance { model, rotation: na::Matrix3::identity(), translation: na::Vector3::zeros() })
+                .collect();
+        }
+
+        if let Some(instance) = instances.iter().find(|instance| instance.model >= models.len()) {
+            return Err(invalid(&format!("scene graph references missing model {}", instance.model)));
+        }
+
+        let mut table = MaterialTable::default();
+        let palette = colors.iter().enumerate()
+            .map(|(index, rgba)| palette_voxel(rgba, materials.get(&(index as u8)), &mut table))
+            .collect();
+
+        Ok(VoxScene { models, instances, palette, materials: table })
+    }
+
+    /// Every placed voxel as an integer cell in Y-up world space (MagicaVoxel is Z-up)
+    pub fn voxels(&self) -> Vec<(na::Vector3<i32>, VoxelData)> {
+        let mut voxels = Vec::new();
+        for instance in &self.instances {
+            let model = &self.models[instance.model];
+            let rotation = instance.rotation.map(|v| v as f32);
+            let translation = instance.translation.map(|v| v as f32);
+            let half_size = na::Vector3::new(model.size[0] as f32, model.size[1] as f32, model.size[2] as f32) * 0.5;
+
+            for &[x, y, z, index] in &model.voxels {
+                // Rotate about the model's center, matching MagicaVoxel's pivot
+                let local = na::Vector3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) - half_size;
+                let world = (rotation * local + translation).map(|v| v.floor() as i32);
+                let cell = na::Vector3::new(world.x, world.z, -world.y - 1);
+                voxels.push((cell, self.palette[index as usize]));
+            }
+        }
+        voxels
+    }
+
+    /// Build an octree with one leaf per voxel of `voxel_size` world units.
+    /// Fails when the placed models span more cells than an octree can hold.
+    pub fn to_octree(&self, voxel_size: f32) -> io::Result<Octree> {
+        let mut octree = Octree::from_cells(&self.voxels(), voxel_size)?;
+        octree.materials = self.materials.clone();
+        Ok(octree)
+    }
+}
+
+fn read_chunk<'a>(reader: &mut Reader<'a>) -> io::Result<(&'a [u8], &'a [u8], &'a [u8])> {
+    let id = reader.take(4)?;
+    let content_size = reader.u32()? as usize;
+    let children_size = reader.u32()? as usize;
+    let content = reader.take(content_size)?;
+    let children = reader.take(children_size)?;
+    Ok((id, content, children))
+}
+
+fn read_string(reader: &mut Reader) -> io::Result<String> {
+    let length = reader.u32()? as usize;
+    Ok(String::from_utf8_lossy(reader.take(length)?).into_owned())
+}
+
+fn read_dict(reader: &mut Reader) -> io::Result<Dict> {
+    let count = reader.u32()?;
+    let mut dict = Dict::new();
+    for _ in 0..count {
+        let key = read_string(reader)?;
+        let value = read_string(reader)?;
+        dict.insert(key, value);
+    }
+    Ok(dict)
+}
+
+fn walk_graph(
+    nodes: &HashMap<i32, SceneNode>,
+    node_id: i32,
+    rotation: &na::Matrix3<i32>,
+    translation: &na::Vector3<i32>,
+    depth: u32,
+    instances: &mut Vec<VoxInstance>,
+) -> io::Result<()> {
+    if depth > MAX_GRAPH_DEPTH {
+        return Err(invalid("scene graph too deep or cyclic"));
+    }
+
+    match nodes.get(&node_id) {
+        Some(SceneNode::Transform { child, rotation: local_rotation, translation: local_translation }) => {
+            let world_rotation = rotation * local_rotation;
+            let world_translation = rotation * local_translation + translation;
+            walk_graph(nodes, *child, &world_rotation, &world_translation, depth + 1, instances)
+        }
+        Some(SceneNode::Group { children }) => {
+            for child in children {
+                walk_graph(nodes, *child, rotation, translation, depth + 1, instances)?;
+            }
+            Ok(())
+        }
+        Some(SceneNode::Shape { models }) => {
+            for &model in models {
+                let model = usize::try_from(model).map_err(|_| invalid("negative model id"))?;
+                instances.push(VoxInstance { model, rotation: *rotation, translation: *translation });
+            }
+            Ok(())
+        }
+        None => Err(invalid(&format!("scene graph references missing node {}", node_id))),
+    }
+}
+
+/// Decode the packed `_r` byte: bits 0-1 and 2-3 give the column of the non-zero
+/// entry in rows one and two, bits 4-6 the sign of each row
+fn decode_rotation(packed: u8) -> na::Matrix3<i32> {
+    let first = (packed & 3) as usize;
+    let second = ((packed >> 2) & 3) as usize;
+    if first > 2 || second > 2 || first == second {
+        return na::Matrix3::identity();
+    }
+    let third = 3 - first - second;
+
+    let mut rotation = na::Matrix3::zeros();
+    for (row, column) in [first, second, third].into_iter().enumerate() {
+        let negative = packed & (1 << (4 + row)) != 0;
+        rotation[(row, column)] = if negative { -1 } else { 1 };
+    }
+    rotation
+}
+
+fn parse_translation(value: &str) -> io::Result<na::Vector3<i32>> {
+    let parts: Vec<i32> = value.split_whitespace()
+        .map(|part| part.parse().map_err(|_| invalid("bad _t value")))
+        .collect::<io::Result<_>>()?;
+    match parts[..] {
+        [x, y, z] => Ok(na::Vector3::new(x, y, z)),
+        _ => Err(invalid("bad _t value")),
+    }
+}
+
+/// Map a palette color and its optional MATL properties onto `VoxelData`,
+/// adding the material to `table`
+fn palette_voxel(rgba: &[u8; 4], material: Option<&Dict>, table: &mut MaterialTable) -> VoxelData {
+    let color = [rgba[0] as f32 / 255.0, rgba[1] as f32 / 255.0, rgba[2] as f32 / 255.0];
+
+    let Some(material) = material else {
+        return VoxelData::solid(color);
+    };
+    let optional = |key: &str| material.get(key).and_then(|v| v.parse::<f32>().ok());
+    let property = |key: &str| optional(key).unwrap_or(0.0);
+
+    let material = match material.get("_type").map(String::as_str) {
+        Some("_metal") => Material {
+            metalness: optional("_metal").unwrap_or(1.0),
+            roughness: property("_rough"),
+            ..Default::default()
+        },
+        Some("_glass") | Some("_media") => Material {
+            transmission: optional("_trans").unwrap_or(1.0),
+            roughness: property("_rough"),
+            // MagicaVoxel stores the index of refraction minus one
+            ior: 1.0 + optional("_ior").unwrap_or(0.5),
+            ..Default::default()
+        },
+        // `_emit` scales the color and each step of `_flux` doubles it
+        Some("_emit") => Material::emissive(color, property("_emit") * 2f32.powf(property("_flux"))),
+        _ => return VoxelData::solid(color),
+    };
+    VoxelData::with_material(color, table.insert(material), table)
+}
+
+/// MagicaVoxel's built-in palette, used when a file has no RGBA chunk: a 6x6x6
+/// color cube without black followed by red, green, blue and gray ramps
+fn default_palette() -> Vec<[u8; 4]> {
+    let cube = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+    let mut palette = vec![[0, 0, 0, 0]];
+    for r in cube {
+        for g in cube {
+            for b in cube {
+                if r != 0 || g != 0 || b != 0 {
+                    palette.push([r, g, b, 0xff]);
+                }
+            }
+        }
+    }
+    palette.extend(ramp.iter().map(|&v| [v, 0, 0, 0xff]));
+    palette.extend(ramp.iter().map(|&v| [0, v, 0, 0xff]));
+    palette.extend(ramp.iter().map(|&v| [0, 0, v, 0xff]));
+    palette.extend(ramp.iter().map(|&v| [v, v, v, 0xff]));
+    palette
+}
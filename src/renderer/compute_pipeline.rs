@@ -12,12 +12,14 @@ impl ComputePipeline {
         camera_bind_group_layout: &BindGroupLayout,
         performance_bind_group_layout: &BindGroupLayout,
         octree_bind_group_layout: &BindGroupLayout,
+        octree_shader: &str,
     ) -> Self {
         Self::new_with_format(
             device,
             camera_bind_group_layout,
             performance_bind_group_layout,
             octree_bind_group_layout,
+            octree_shader,
             TextureFormat::Rgba8Unorm,
         )
     }
@@ -27,11 +29,13 @@ impl ComputePipeline {
         camera_bind_group_layout: &BindGroupLayout,
         performance_bind_group_layout: &BindGroupLayout,
         octree_bind_group_layout: &BindGroupLayout,
+        octree_shader: &str,
         output_format: TextureFormat,
     ) -> Self {
         info!("Creating compute pipeline with format {:?}", output_format);
 
-        let shader_code = include_str!("../shaders/ray_march.wgsl");
+        // The octree backend supplies group 3 and `octree_lookup`
+        let shader_code = format!("{}\n{}", octree_shader, include_str!("../shaders/ray_march.wgsl"));
         let shader = device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Ray March Shader"),
             source: ShaderSource::Wgsl(shader_code.into()),
@@ -50,6 +54,16 @@ impl ComputePipeline {
                     },
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -79,11 +93,13 @@ impl ComputePipeline {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn dispatch(
         &self,
         device: &Device,
         encoder: &mut CommandEncoder,
         output_texture: &TextureView,
+        accumulation_buffer: &Buffer,
         camera_bind_group: &BindGroup,
         performance_bind_group: &BindGroup,
         octree_bind_group: &BindGroup,
@@ -98,6 +114,10 @@ impl ComputePipeline {
                     binding: 0,
                     resource: BindingResource::TextureView(output_texture),
                 },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: accumulation_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -113,8 +133,8 @@ impl ComputePipeline {
         compute_pass.set_bind_group(3, octree_bind_group, &[]);
 
         let workgroup_size = 8;
-        let num_workgroups_x = (width + workgroup_size - 1) / workgroup_size;
-        let num_workgroups_y = (height + workgroup_size - 1) / workgroup_size;
+        let num_workgroups_x = width.div_ceil(workgroup_size);
+        let num_workgroups_y = height.div_ceil(workgroup_size);
 
         compute_pass.dispatch_workgroups(num_workgroups_x, num_workgroups_y, 1);
     }
@@ -11,9 +11,9 @@ pub mod blit_pipeline;
 pub mod performance_monitor;
 
 use compute_pipeline::ComputePipeline;
-use performance::PerformanceController;
+use performance::{PerformanceController, TraversalMode};
 use blit_pipeline::BlitPipeline;
-use crate::octree::{OctreeProvider, static_provider::StaticOctreeProvider};
+use crate::octree::{GpuUpdate, OctreeProvider};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -27,12 +27,199 @@ pub struct CameraData {
     pub _padding3: [f32; 2],
 }
 
+/// Extent of the provider's data, bound next to the camera. The ray marcher
+/// clips rays to the bounds and sizes its step budget from the grid dims.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct SceneData {
+    pub bounds_min: [f32; 3],
+    pub _padding1: f32,
+    pub bounds_max: [f32; 3],
+    pub _padding2: f32,
+    pub grid_dims: [u32; 3],
+    pub _padding3: u32,
+}
+
+impl SceneData {
+    pub fn from_provider(provider: &dyn OctreeProvider) -> Self {
+        let (bounds_min, bounds_max) = provider.get_bounds();
+        Self {
+            bounds_min: bounds_min.into(),
+            _padding1: 0.0,
+            bounds_max: bounds_max.into(),
+            _padding2: 0.0,
+            grid_dims: provider.get_grid_dims().into(),
+            _padding3: 0,
+        }
+    }
+}
+
+/// Layout of group 1: the camera at binding 0 and the scene at binding 1
+pub fn camera_bind_group_layout(device: &Device) -> BindGroupLayout {
+    let uniform_entry = |binding| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Camera Bind Group Layout"),
+        entries: &[uniform_entry(0), uniform_entry(1)],
+    })
+}
+
+pub fn camera_bind_group(device: &Device, layout: &BindGroupLayout, camera_buffer: &Buffer, scene_buffer: &Buffer) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Camera Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: scene_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// `PerformanceData::debug_flags`: shade pixels by lookups per ray instead of color
+pub const DEBUG_ITERATION_VIEW: u32 = 1;
+/// `PerformanceData::debug_flags`: march through the empty space backends report
+pub const DEBUG_NO_SKIPPING: u32 = 2;
+/// `PerformanceData::debug_flags`: add every ray's lookups to the ray stats buffer
+pub const DEBUG_COUNT_ITERATIONS: u32 = 4;
+
+/// Bounces per path when path tracing is switched on without a count
+pub const DEFAULT_MAX_BOUNCES: u32 = 4;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct PerformanceData {
     pub base_voxel_size: f32,
     pub frame_time: f32,
-    pub _padding: [f32; 2],
+    /// `TraversalMode::shader_value` of the traversal to use
+    pub traversal_mode: u32,
+    /// `DEBUG_*` bits
+    pub debug_flags: u32,
+    /// Bounces per path traced from each pixel; 0 shades the first hit without lighting
+    pub max_bounces: u32,
+    /// Paths traced per pixel and frame when `max_bounces` is not 0
+    pub samples_per_pixel: u32,
+    /// Frames summed into the accumulation buffer before this one, 0 to start
+    /// over. Also seeds the random paths, so every frame adds new samples.
+    pub frame_index: u32,
+    pub _padding: u32,
+}
+
+/// Totals the ray marcher accumulates while `DEBUG_COUNT_ITERATIONS` is set
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct RayStats {
+    /// Backend lookups made by all rays
+    pub iterations: u32,
+    pub rays: u32,
+}
+
+impl RayStats {
+    pub fn iterations_per_ray(&self) -> f32 {
+        self.iterations as f32 / self.rays.max(1) as f32
+    }
+}
+
+pub fn create_ray_stats_buffer(device: &Device) -> Buffer {
+    device.create_buffer_init(&util::BufferInitDescriptor {
+        label: Some("Ray Stats Buffer"),
+        contents: bytemuck::cast_slice(&[RayStats::default()]),
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+    })
+}
+
+/// Read the totals of the frames submitted so far and reset them
+pub fn read_ray_stats(device: &Device, queue: &Queue, stats_buffer: &Buffer) -> RayStats {
+    let size = std::mem::size_of::<RayStats>() as BufferAddress;
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("Ray Stats Staging Buffer"),
+        size,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Ray Stats Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(stats_buffer, 0, &staging, 0, size);
+    encoder.clear_buffer(stats_buffer, 0, None);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging.slice(..);
+    slice.map_async(MapMode::Read, |_| {});
+    device.poll(PollType::Wait).unwrap();
+    *bytemuck::from_bytes::<RayStats>(&slice.get_mapped_range())
+}
+
+/// Running HDR sum of path traced radiance per pixel, with the number of
+/// samples in the fourth component, at group 0 binding 1
+pub fn create_accumulation_buffer(device: &Device, width: u32, height: u32) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Accumulation Buffer"),
+        size: (width.max(1) * height.max(1)) as BufferAddress * 16,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+/// Layout of group 2: the performance uniform at binding 0 and the ray stats at binding 1
+pub fn performance_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Performance Bind Group Layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+pub fn performance_bind_group(device: &Device, layout: &BindGroupLayout, performance_buffer: &Buffer, stats_buffer: &Buffer) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Performance Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: performance_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: stats_buffer.as_entire_binding(),
+            },
+        ],
+    })
 }
 
 pub struct VoxelRenderer {
@@ -41,12 +228,24 @@ pub struct VoxelRenderer {
     compute_pipeline: ComputePipeline,
     blit_pipeline: BlitPipeline,
     camera_buffer: Buffer,
+    scene_buffer: Buffer,
     camera_bind_group: BindGroup,
     performance_buffer: Buffer,
     performance_bind_group: BindGroup,
     performance_controller: PerformanceController,
+    stats_buffer: Buffer,
+    /// `DEBUG_*` bits toggled from the keyboard
+    debug_flags: u32,
+    /// Path tracing settings, kept while it is toggled off
+    path_tracing: bool,
+    max_bounces: u32,
+    samples_per_pixel: u32,
+    accumulation_buffer: Buffer,
+    /// Frames in the accumulation buffer; reset whenever the image would change
+    accumulated_frames: u32,
+    /// Eye and target of the last `update_camera`
+    camera: Option<(na::Point3<f32>, na::Point3<f32>)>,
     octree_provider: Box<dyn OctreeProvider>,
-    octree_bind_group_layout: BindGroupLayout,
     octree_bind_group: BindGroup,
     output_texture: Texture,
     output_texture_view: TextureView,
@@ -55,14 +254,16 @@ pub struct VoxelRenderer {
 }
 
 impl VoxelRenderer {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        queue: &Queue,
         adapter: &Adapter,
         surface: Surface<'static>,
         width: u32,
         height: u32,
         target_fps: f32,
+        traversal: Option<TraversalMode>,
+        octree_provider: Box<dyn OctreeProvider>,
     ) -> Self {
         info!("Creating VoxelRenderer with resolution {}x{}, target FPS: {}", width, height, target_fps);
 
@@ -93,38 +294,29 @@ impl VoxelRenderer {
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let camera_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Camera Bind Group Layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
+        // Create scene uniform buffer from the provider's extent
+        let scene_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Scene Buffer"),
+            contents: bytemuck::cast_slice(&[SceneData::from_provider(octree_provider.as_ref())]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Camera Bind Group"),
-            layout: &camera_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        let camera_bind_group_layout = camera_bind_group_layout(device);
+        let camera_bind_group = camera_bind_group(device, &camera_bind_group_layout, &camera_buffer, &scene_buffer);
+
+        // Create performance controller with user-specified target FPS
+        let performance_controller = PerformanceController::new(target_fps).with_traversal(traversal);
 
         // Create performance uniform buffer
         let performance_data = PerformanceData {
             base_voxel_size: 0.02,  // Start with high performance for 60 FPS
             frame_time: 0.016,
-            _padding: [0.0; 2],
+            traversal_mode: performance_controller.traversal().shader_value(),
+            debug_flags: 0,
+            max_bounces: 0,
+            samples_per_pixel: 1,
+            frame_index: 0,
+            _padding: 0,
         };
 
         let performance_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
@@ -133,38 +325,11 @@ impl VoxelRenderer {
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let performance_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Performance Bind Group Layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
+        let stats_buffer = create_ray_stats_buffer(device);
+        let performance_bind_group_layout = performance_bind_group_layout(device);
+        let performance_bind_group = performance_bind_group(device, &performance_bind_group_layout, &performance_buffer, &stats_buffer);
 
-        let performance_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Performance Bind Group"),
-            layout: &performance_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: performance_buffer.as_entire_binding(),
-                },
-            ],
-        });
-
-        // Create octree provider and initialize 3D texture
-        let mut octree_provider = Box::new(StaticOctreeProvider::new_cornell_box());
-        octree_provider.create_texture(device, queue);
-
-        // Get octree bind group resources
+        // Get octree bind group resources (the provider has already uploaded its data)
         let (octree_bind_group_layout, octree_bind_group) = octree_provider.bind_gpu_resources(device);
 
         // Create compute pipeline with octree support
@@ -173,6 +338,7 @@ impl VoxelRenderer {
             &camera_bind_group_layout,
             &performance_bind_group_layout,
             &octree_bind_group_layout,
+            octree_provider.shader_source(),
         );
 
         // Create blit pipeline for format conversion
@@ -194,9 +360,7 @@ impl VoxelRenderer {
             view_formats: &[],
         });
         let output_texture_view = output_texture.create_view(&TextureViewDescriptor::default());
-
-        // Create performance controller with user-specified target FPS
-        let performance_controller = PerformanceController::new(target_fps);
+        let accumulation_buffer = create_accumulation_buffer(device, width, height);
 
         Self {
             surface,
@@ -204,12 +368,20 @@ impl VoxelRenderer {
             compute_pipeline,
             blit_pipeline,
             camera_buffer,
+            scene_buffer,
             camera_bind_group,
             performance_buffer,
             performance_bind_group,
             performance_controller,
+            stats_buffer,
+            debug_flags: 0,
+            path_tracing: false,
+            max_bounces: DEFAULT_MAX_BOUNCES,
+            samples_per_pixel: 1,
+            accumulation_buffer,
+            accumulated_frames: 0,
+            camera: None,
             octree_provider,
-            octree_bind_group_layout,
             octree_bind_group,
             output_texture,
             output_texture_view,
@@ -241,6 +413,8 @@ impl VoxelRenderer {
                 view_formats: &[],
             });
             self.output_texture_view = self.output_texture.create_view(&TextureViewDescriptor::default());
+            self.accumulation_buffer = create_accumulation_buffer(device, width, height);
+            self.reset_accumulation();
 
             // Update camera data
             let camera_data = Self::create_camera_data(width as f32, height as f32);
@@ -252,7 +426,32 @@ impl VoxelRenderer {
         }
     }
 
+    pub fn octree_provider(&self) -> &dyn OctreeProvider {
+        self.octree_provider.as_ref()
+    }
+
+    /// Mutable access for editing dynamic scenes; edits are uploaded at the next
+    /// `render`, which starts accumulating afresh
+    pub fn octree_provider_mut(&mut self) -> &mut dyn OctreeProvider {
+        self.reset_accumulation();
+        self.octree_provider.as_mut()
+    }
+
+    /// Drop the accumulated frames; the next `render` starts a new image
+    pub fn reset_accumulation(&mut self) {
+        self.accumulated_frames = 0;
+    }
+
+    /// Frames averaged into the current image
+    pub fn accumulated_frames(&self) -> u32 {
+        self.accumulated_frames
+    }
+
     pub fn update_camera(&mut self, queue: &Queue, eye: na::Point3<f32>, target: na::Point3<f32>) {
+        if self.camera.replace((eye, target)) != Some((eye, target)) {
+            self.reset_accumulation();
+        }
+
         let width = self.surface_config.width as f32;
         let height = self.surface_config.height as f32;
 
@@ -283,6 +482,8 @@ impl VoxelRenderer {
             0,
             bytemuck::cast_slice(&[camera_data]),
         );
+
+        self.octree_provider.update_view(eye);
     }
 
     pub fn render(&mut self, device: &Device, queue: &Queue) {
@@ -290,19 +491,37 @@ impl VoxelRenderer {
         let delta_time = now.duration_since(self.last_frame_time).as_secs_f32();
         self.last_frame_time = now;
 
-        // Update performance controller
-        if let Some(new_voxel_size) = self.performance_controller.update(delta_time) {
+        // Update performance controller. The step size decides which surfaces
+        // adaptive rays hit, so it is frozen while a path traced image converges
+        // rather than blending frames of different geometry; moving the camera
+        // restarts accumulation and lets it adapt again
+        let traversal = self.performance_controller.traversal();
+        let converging = self.path_tracing && self.accumulated_frames > 0;
+        if !converging && let Some(new_voxel_size) = self.performance_controller.update(delta_time) {
             let fps = 1.0 / delta_time;
-            info!("📊 Adjusting step size: {:.4} (FPS: {:.1})", new_voxel_size, fps);
-            let performance_data = PerformanceData {
-                base_voxel_size: new_voxel_size,
-                frame_time: delta_time,
-                _padding: [0.0; 2],
-            };
+            info!("📊 Adjusting step size: {:.4}, {:?} traversal (FPS: {:.1})",
+                  new_voxel_size, self.performance_controller.traversal(), fps);
+            if self.performance_controller.traversal() != traversal {
+                self.reset_accumulation();
+            }
+        }
+
+        // In the iteration view, count lookups on every 60th frame and log them
+        let count_iterations = self.debug_flags & DEBUG_ITERATION_VIEW != 0 && self.frame_count.is_multiple_of(60);
+        let debug_flags = self.debug_flags | if count_iterations { DEBUG_COUNT_ITERATIONS } else { 0 };
+
+        // Upload scene edits made since the last frame
+        let update = self.octree_provider.update_gpu_resources(device, queue);
+        if update != GpuUpdate::Unchanged {
+            self.reset_accumulation();
+        }
+        if update == GpuUpdate::Recreated {
+            let (_, octree_bind_group) = self.octree_provider.bind_gpu_resources(device);
+            self.octree_bind_group = octree_bind_group;
             queue.write_buffer(
-                &self.performance_buffer,
+                &self.scene_buffer,
                 0,
-                bytemuck::cast_slice(&[performance_data]),
+                bytemuck::cast_slice(&[SceneData::from_provider(self.octree_provider.as_ref())]),
             );
         }
 
@@ -320,11 +539,14 @@ impl VoxelRenderer {
             label: Some("Render Encoder"),
         });
 
-        // Run compute shader on our storage texture with octree data
+        // Run compute shader on our storage texture with octree data, adding
+        // this frame to the accumulated ones
+        self.write_performance_data(queue, delta_time, debug_flags);
         self.compute_pipeline.dispatch(
             device,
             &mut encoder,
             &self.output_texture_view,
+            &self.accumulation_buffer,
             &self.camera_bind_group,
             &self.performance_bind_group,
             &self.octree_bind_group,
@@ -342,13 +564,66 @@ impl VoxelRenderer {
 
         queue.submit(std::iter::once(encoder.finish()));
         output.present();
+        self.accumulated_frames = self.accumulated_frames.saturating_add(1);
+
+        if count_iterations {
+            let stats = read_ray_stats(device, queue, &self.stats_buffer);
+            info!("🔍 {:.1} lookups per ray (empty space skipping {})",
+                  stats.iterations_per_ray(),
+                  if self.debug_flags & DEBUG_NO_SKIPPING != 0 { "off" } else { "on" });
+        }
 
         self.frame_count += 1;
-        if self.frame_count % 60 == 0 {
+        if self.frame_count.is_multiple_of(60) {
             info!("Frame time: {:.2}ms, FPS: {:.1}", delta_time * 1000.0, 1.0 / delta_time);
         }
     }
 
+    /// Flip `DEBUG_*` bits, e.g. to switch the iteration view on or off
+    pub fn toggle_debug_flags(&mut self, queue: &Queue, flags: u32) {
+        self.debug_flags ^= flags;
+        self.reset_accumulation();
+        self.write_performance_data(queue, 0.016, self.debug_flags);
+    }
+
+    /// Path trace with up to `max_bounces` bounces and `samples_per_pixel`
+    /// paths per pixel, or shade the first hit only when `max_bounces` is 0
+    pub fn set_path_tracing(&mut self, queue: &Queue, max_bounces: u32, samples_per_pixel: u32) {
+        self.path_tracing = max_bounces > 0;
+        if self.path_tracing {
+            self.max_bounces = max_bounces;
+        }
+        self.samples_per_pixel = samples_per_pixel.max(1);
+        self.reset_accumulation();
+        self.write_performance_data(queue, 0.016, self.debug_flags);
+    }
+
+    /// Switch between path tracing with the last settings and first-hit shading
+    pub fn toggle_path_tracing(&mut self, queue: &Queue) {
+        self.path_tracing = !self.path_tracing;
+        info!("Path tracing {}", if self.path_tracing { "on" } else { "off" });
+        self.reset_accumulation();
+        self.write_performance_data(queue, 0.016, self.debug_flags);
+    }
+
+    fn write_performance_data(&self, queue: &Queue, frame_time: f32, debug_flags: u32) {
+        let performance_data = PerformanceData {
+            base_voxel_size: self.performance_controller.get_current_voxel_size(),
+            frame_time,
+            traversal_mode: self.performance_controller.traversal().shader_value(),
+            debug_flags,
+            max_bounces: if self.path_tracing { self.max_bounces } else { 0 },
+            samples_per_pixel: self.samples_per_pixel,
+            frame_index: self.accumulated_frames,
+            _padding: 0,
+        };
+        queue.write_buffer(
+            &self.performance_buffer,
+            0,
+            bytemuck::cast_slice(&[performance_data]),
+        );
+    }
+
     fn create_camera_data(width: f32, height: f32) -> CameraData {
         let aspect_ratio = width / height;
         let fov_y = 60.0_f32.to_radians();  // Wider FOV to see more of the room
@@ -1,13 +1,46 @@
 use std::collections::VecDeque;
 
+/// Finest step size the adaptive marcher is allowed
+const MIN_VOXEL_SIZE: f32 = 0.005;
+
+/// Fraction of the target frame rate below which a frame counts as too slow,
+/// leaving room for frame times jittering around a vsync-capped target
+const SHORTFALL_TOLERANCE: f32 = 0.95;
+
+/// Stable frames before DDA is tried again after it could not hold the target
+const DDA_RETRY_FRAMES: u32 = 300;
+
+/// How `ray_march.wgsl` walks rays through the scene
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraversalMode {
+    /// Fixed steps sized by the controller; cheap, but large steps can skip thin walls
+    Adaptive,
+    /// Amanatides–Woo grid traversal visiting every voxel the ray crosses; never leaks
+    Dda,
+}
+
+impl TraversalMode {
+    /// Value of `PerformanceData::traversal_mode`
+    pub fn shader_value(self) -> u32 {
+        match self {
+            TraversalMode::Adaptive => 0,
+            TraversalMode::Dda => 1,
+        }
+    }
+}
+
 pub struct PerformanceController {
     target_framerate: f32,
     current_voxel_size: f32,
     frame_time_history: VecDeque<f32>,
-    adjustment_rate: f32,
     history_size: usize,
     last_adjustment_direction: i8,  // -1 for decrease, 0 for none, 1 for increase
     stable_frames: u32,  // Count frames at stable performance
+    traversal: TraversalMode,
+    /// Trade between the traversal modes instead of keeping the configured one
+    switch_traversal: bool,
+    /// DDA fell short of the target the last time it was tried
+    dda_failed: bool,
 }
 
 impl PerformanceController {
@@ -16,13 +49,28 @@ impl PerformanceController {
             target_framerate,
             current_voxel_size: 0.02,  // Start with high performance for 60 FPS target
             frame_time_history: VecDeque::with_capacity(10),  // Smaller window for faster response
-            adjustment_rate: 0.1,
             history_size: 10,  // Smaller history for quicker reaction
             last_adjustment_direction: 0,
             stable_frames: 0,
+            traversal: TraversalMode::Adaptive,
+            switch_traversal: false,
+            dda_failed: false,
         }
     }
 
+    /// Keep `traversal` fixed, or with `None` start exact and fall back to the
+    /// adaptive marcher whenever DDA cannot hold the target frame rate
+    pub fn with_traversal(mut self, traversal: Option<TraversalMode>) -> Self {
+        self.traversal = traversal.unwrap_or(TraversalMode::Dda);
+        self.switch_traversal = traversal.is_none();
+        self
+    }
+
+    pub fn traversal(&self) -> TraversalMode {
+        self.traversal
+    }
+
+    /// Returns the new step size when the step size or the traversal mode changed
     pub fn update(&mut self, frame_time: f32) -> Option<f32> {
         self.frame_time_history.push_back(frame_time);
 
@@ -39,8 +87,25 @@ impl PerformanceController {
         let avg_frame_time = self.average_frame_time();
         let avg_fps = 1.0 / avg_frame_time;
 
+        // DDA has no step size to tune: keep it unless every frame of a full
+        // window fell clearly short of the target
+        if self.traversal == TraversalMode::Dda {
+            let fastest_fps = 1.0 / self.frame_time_history.iter().copied().fold(f32::INFINITY, f32::min);
+            let sustained_shortfall = self.frame_time_history.len() == self.history_size
+                && fastest_fps < self.target_framerate * SHORTFALL_TOLERANCE;
+            if !self.switch_traversal || !sustained_shortfall {
+                return None;
+            }
+            log::info!("⚠️ DDA at {:.1} FPS < {:.1}, switching to adaptive marching", avg_fps, self.target_framerate);
+            self.traversal = TraversalMode::Adaptive;
+            self.dda_failed = true;
+            self.stable_frames = 0;
+            self.frame_time_history.clear();
+            return Some(self.current_voxel_size);
+        }
+
         // CRITICAL: If current FPS drops below target, react IMMEDIATELY
-        let emergency_threshold = self.target_framerate * 0.95;  // 5% below target
+        let emergency_threshold = self.target_framerate * SHORTFALL_TOLERANCE;
         if current_fps < emergency_threshold {
             // Emergency increase - big jump to get back above target FPS
             let panic_multiplier = self.target_framerate / current_fps.max(10.0);  // How much we need to improve
@@ -77,9 +142,23 @@ impl PerformanceController {
             Some(self.current_voxel_size)
 
         } else if avg_fps > self.target_framerate * 1.2 && self.stable_frames > 15 {
+            // Already at the finest step with time to spare: go exact
+            let retry_frames = if self.dda_failed { DDA_RETRY_FRAMES } else { 15 };
+            if self.switch_traversal && self.current_voxel_size <= MIN_VOXEL_SIZE && self.stable_frames > retry_frames {
+                log::info!("✨ FPS {:.1} at the finest step size, switching to DDA traversal", avg_fps);
+                self.traversal = TraversalMode::Dda;
+                self.stable_frames = 0;
+                self.frame_time_history.clear();
+                return Some(self.current_voxel_size);
+            }
+            if self.current_voxel_size <= MIN_VOXEL_SIZE {
+                self.stable_frames += 1;
+                return None;
+            }
+
             // Only improve quality if we've been stable and well above target
             let scale = 1.0 - (0.1 * adjustment_factor);
-            self.current_voxel_size = (self.current_voxel_size * scale).max(0.005);
+            self.current_voxel_size = (self.current_voxel_size * scale).max(MIN_VOXEL_SIZE);
 
             log::debug!("Performance good: FPS {:.1} -> step size {:.4}", avg_fps, self.current_voxel_size);
             self.last_adjustment_direction = -1;
@@ -105,4 +184,53 @@ impl PerformanceController {
     pub fn get_current_voxel_size(&self) -> f32 {
         self.current_voxel_size
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn automatic(target_framerate: f32) -> PerformanceController {
+        PerformanceController::new(target_framerate).with_traversal(None)
+    }
+
+    #[test]
+    fn vsync_jitter_keeps_dda() {
+        let mut controller = automatic(60.0);
+        for frame in 0..2000 {
+            let frame_time = [0.0166, 0.0168, 0.0167, 0.01665][frame % 4];
+            assert_eq!(controller.update(frame_time), None);
+            assert_eq!(controller.traversal(), TraversalMode::Dda, "left DDA at frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn single_hitches_keep_dda() {
+        let mut controller = automatic(60.0);
+        for frame in 0..200 {
+            let frame_time = if frame % 7 == 0 { 0.05 } else { 0.0167 };
+            controller.update(frame_time);
+        }
+        assert_eq!(controller.traversal(), TraversalMode::Dda);
+    }
+
+    #[test]
+    fn sustained_shortfall_leaves_dda() {
+        let mut controller = automatic(60.0);
+        for _ in 0..9 {
+            assert_eq!(controller.update(1.0 / 40.0), None);
+        }
+        assert_eq!(controller.traversal(), TraversalMode::Dda);
+        assert!(controller.update(1.0 / 40.0).is_some());
+        assert_eq!(controller.traversal(), TraversalMode::Adaptive);
+    }
+
+    #[test]
+    fn fixed_traversal_never_switches() {
+        let mut controller = PerformanceController::new(60.0).with_traversal(Some(TraversalMode::Dda));
+        for _ in 0..100 {
+            controller.update(0.1);
+        }
+        assert_eq!(controller.traversal(), TraversalMode::Dda);
+    }
+}
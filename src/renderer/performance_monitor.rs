@@ -13,6 +13,12 @@ pub struct PerformanceMonitor {
     camera_positions: Vec<(f32, [f32; 3])>, // (time, position)
 }
 
+impl Default for PerformanceMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PerformanceMonitor {
     pub fn new() -> Self {
         Self {
@@ -0,0 +1,52 @@
+// Two-level brick map backend for ray_march.wgsl
+struct BrickMapHeader {
+    origin: vec3<f32>,
+    voxel_size: f32,
+    grid_dims: vec3<u32>,  // in bricks
+    brick_count: u32,
+}
+
+const BRICK_SIZE: u32 = 8u;
+const EMPTY_BRICK: u32 = 0xffffffffu;
+
+@group(3) @binding(0) var<storage, read> brick_grid: array<u32>;   // brick index per grid cell
+@group(3) @binding(1) var<storage, read> brick_atlas: array<u32>;  // 8x8x8 palette references per brick, 0 is empty
+@group(3) @binding(2) var<uniform> brick_header: BrickMapHeader;
+@group(3) @binding(3) var<storage, read> brick_palette: array<vec2<u32>>;  // RGBA8 color + density, material index
+
+// Distance along the ray to where it leaves the cube [cell_min, cell_min + size]
+fn brick_cell_exit(position: vec3<f32>, ray_dir: vec3<f32>, cell_min: vec3<f32>, size: f32) -> f32 {
+    let exit_planes = cell_min + select(vec3<f32>(0.0), vec3<f32>(size), ray_dir > vec3<f32>(0.0));
+    let t_exit = select(vec3<f32>(1e30), (exit_planes - position) / ray_dir, ray_dir != vec3<f32>(0.0));
+    return max(min(min(t_exit.x, t_exit.y), t_exit.z), 0.0) + 1e-4;
+}
+
+fn octree_lookup(position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    let dims = brick_header.grid_dims;
+    let local = (position - brick_header.origin) / brick_header.voxel_size;
+    if any(local < vec3<f32>(0.0)) || any(local >= vec3<f32>(dims * BRICK_SIZE)) {
+        return OctreeLookup(vec4<f32>(0.0), 0.0, 0u);
+    }
+
+    // Coarse step: an empty grid cell is skipped as a whole brick
+    let voxel = vec3<u32>(local);
+    let brick = voxel / BRICK_SIZE;
+    let brick_index = brick_grid[(brick.z * dims.y + brick.y) * dims.x + brick.x];
+    if brick_index == EMPTY_BRICK {
+        let brick_extent = f32(BRICK_SIZE) * brick_header.voxel_size;
+        let brick_min = brick_header.origin + vec3<f32>(brick) * brick_extent;
+        return OctreeLookup(vec4<f32>(0.0), brick_cell_exit(position, ray_dir, brick_min, brick_extent), 0u);
+    }
+
+    // Fine step: inside an occupied brick, move one voxel at a time
+    let in_brick = voxel % BRICK_SIZE;
+    let offset = (in_brick.z * BRICK_SIZE + in_brick.y) * BRICK_SIZE + in_brick.x;
+    let entry = brick_palette[brick_atlas[brick_index * BRICK_SIZE * BRICK_SIZE * BRICK_SIZE + offset]];
+    let color = unpack4x8unorm(entry.x);
+    if color.a > 0.0 {
+        return OctreeLookup(color, 0.0, entry.y);
+    }
+
+    let voxel_min = brick_header.origin + vec3<f32>(voxel) * brick_header.voxel_size;
+    return OctreeLookup(color, brick_cell_exit(position, ray_dir, voxel_min, brick_header.voxel_size), 0u);
+}
@@ -0,0 +1,62 @@
+// Sparse voxel DAG backend for ray_march.wgsl
+struct DagHeader {
+    center: vec3<f32>,
+    half_size: f32,
+    max_depth: u32,
+    root: u32,  // reference to the root, encoded like a child reference
+}
+
+const LEAF_BIT: u32 = 0x80000000u;
+
+// Each node is a child mask word followed by one reference per occupied child.
+// A reference is a node index, or LEAF_BIT plus an index into dag_attributes.
+@group(3) @binding(0) var<storage, read> dag_nodes: array<u32>;
+@group(3) @binding(1) var<storage, read> dag_attributes: array<vec2<u32>>;  // RGBA8 color + density, material index
+@group(3) @binding(2) var<uniform> dag_header: DagHeader;
+
+fn octree_lookup(position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    var node_center = dag_header.center;
+    var half_size = dag_header.half_size;
+
+    if any(abs(position - node_center) > vec3<f32>(half_size)) {
+        return OctreeLookup(vec4<f32>(0.0), 0.0, 0u);
+    }
+
+    // Descend until we reach a leaf reference or an empty child
+    var reference = dag_header.root;
+    var empty = false;
+    for (var level = 0u; level < dag_header.max_depth; level++) {
+        if (reference & LEAF_BIT) != 0u {
+            break;
+        }
+
+        let mask = dag_nodes[reference] & 0xffu;
+        half_size = half_size * 0.5;
+        var octant = 0u;
+        if position.x > node_center.x { octant |= 1u; }
+        if position.y > node_center.y { octant |= 2u; }
+        if position.z > node_center.z { octant |= 4u; }
+        node_center = node_center + select(vec3<f32>(-half_size), vec3<f32>(half_size), position > node_center);
+
+        if (mask & (1u << octant)) == 0u {
+            empty = true;
+            break;
+        }
+        let slot = countOneBits(mask & ((1u << octant) - 1u));
+        reference = dag_nodes[reference + 1u + slot];
+    }
+
+    if !empty && (reference & LEAF_BIT) != 0u {
+        let leaf = dag_attributes[reference & ~LEAF_BIT];
+        let voxel = unpack4x8unorm(leaf.x);
+        if voxel.a > 0.0 {
+            return OctreeLookup(voxel, 0.0, leaf.y);
+        }
+    }
+
+    // Empty cell: the ray can jump straight to where it leaves it
+    let exit_planes = node_center + sign(ray_dir) * half_size;
+    let t_exit = select(vec3<f32>(1e30), (exit_planes - position) / ray_dir, ray_dir != vec3<f32>(0.0));
+    let skip = min(min(t_exit.x, t_exit.y), t_exit.z);
+    return OctreeLookup(vec4<f32>(0.0), max(skip, 0.0) + 1e-4, 0u);
+}
@@ -0,0 +1,132 @@
+// Instanced voxel models backend for ray_march.wgsl
+struct OctreeNode {
+    first_child: u32,  // relative to the asset's first_node, 0 means leaf
+    payload: u32,      // RGBA8 color + density
+    material: u32,     // index into materials
+}
+
+struct AssetHeader {
+    center: vec3<f32>,
+    half_size: f32,
+    max_depth: u32,
+    first_node: u32,   // slot of the asset's root in asset_nodes
+}
+
+struct Instance {
+    rotation: mat3x3<f32>,  // object to world
+    translation: vec3<f32>,
+    scale: f32,
+    bounds_min: vec3<f32>,  // world-space box around the placed asset
+    asset: u32,
+    bounds_max: vec3<f32>,
+}
+
+struct BvhNode {
+    bounds_min: vec3<f32>,
+    first: u32,             // first child (the second follows it), or first instance of a leaf
+    bounds_max: vec3<f32>,
+    count: u32,             // instances in a leaf, 0 for interior nodes
+}
+
+const BVH_STACK_SIZE: u32 = 32u;
+const NOTHING_AHEAD: f32 = 1e30;
+
+@group(3) @binding(0) var<storage, read> asset_nodes: array<OctreeNode>;
+@group(3) @binding(1) var<storage, read> assets: array<AssetHeader>;
+@group(3) @binding(2) var<storage, read> instances: array<Instance>;  // in BVH leaf order
+@group(3) @binding(3) var<storage, read> bvh_nodes: array<BvhNode>;
+
+// Sparse octree lookup in the asset's own space; skip is in object units
+fn asset_lookup(asset: AssetHeader, position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    var node_center = asset.center;
+    var half_size = asset.half_size;
+
+    // The instance box is looser than a rotated root cube: skip to where the ray enters the cube
+    if any(abs(position - node_center) > vec3<f32>(half_size)) {
+        let hit = ray_box_intersection(position, ray_dir, node_center - vec3<f32>(half_size), node_center + vec3<f32>(half_size));
+        return OctreeLookup(vec4<f32>(0.0), select(hit.x + 1e-4, NOTHING_AHEAD, hit.y < 0.0), 0u);
+    }
+
+    // Descend until we reach the leaf containing the position
+    var index = asset.first_node;
+    for (var level = 0u; level < asset.max_depth; level++) {
+        let node = asset_nodes[index];
+        if node.first_child == 0u {
+            break;
+        }
+
+        half_size = half_size * 0.5;
+        var octant = 0u;
+        if position.x > node_center.x { octant |= 1u; }
+        if position.y > node_center.y { octant |= 2u; }
+        if position.z > node_center.z { octant |= 4u; }
+
+        node_center = node_center + select(vec3<f32>(-half_size), vec3<f32>(half_size), position > node_center);
+        index = asset.first_node + node.first_child + octant;
+    }
+
+    let leaf = asset_nodes[index];
+    let voxel = unpack4x8unorm(leaf.payload);
+    if voxel.a > 0.0 {
+        return OctreeLookup(voxel, 0.0, leaf.material);
+    }
+
+    // Empty leaf: the ray can jump straight to where it leaves this cell
+    let exit_planes = node_center + sign(ray_dir) * half_size;
+    let t_exit = select(vec3<f32>(1e30), (exit_planes - position) / ray_dir, ray_dir != vec3<f32>(0.0));
+    let skip = min(min(t_exit.x, t_exit.y), t_exit.z);
+    return OctreeLookup(voxel, max(skip, 0.0) + 1e-4, 0u);
+}
+
+fn octree_lookup(position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    // Distance to the nearest thing the ray could hit: an instance box ahead or
+    // the end of an empty cell in an instance the position is inside
+    var skip = NOTHING_AHEAD;
+
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size = 1u;
+    stack[0] = 0u;
+
+    while stack_size > 0u {
+        stack_size -= 1u;
+        let node = bvh_nodes[stack[stack_size]];
+
+        // Boxes the ray misses, or enters beyond the current skip, cannot change the result
+        let node_hit = ray_box_intersection(position, ray_dir, node.bounds_min, node.bounds_max);
+        if node_hit.y < 0.0 || node_hit.x >= skip {
+            continue;
+        }
+
+        if node.count == 0u {
+            if stack_size + 2u <= BVH_STACK_SIZE {
+                stack[stack_size] = node.first;
+                stack[stack_size + 1u] = node.first + 1u;
+                stack_size += 2u;
+            }
+            continue;
+        }
+
+        for (var i = node.first; i < node.first + node.count; i++) {
+            let instance = instances[i];
+            let hit = ray_box_intersection(position, ray_dir, instance.bounds_min, instance.bounds_max);
+            if hit.y < 0.0 || hit.x >= skip {
+                continue;
+            }
+            if hit.x > 0.0 {
+                skip = min(skip, hit.x + 1e-4);
+                continue;
+            }
+
+            // Inside the instance box: transform the ray into object space
+            let to_object = transpose(instance.rotation);
+            let local_position = to_object * (position - instance.translation) / instance.scale;
+            let lookup = asset_lookup(assets[instance.asset], local_position, to_object * ray_dir);
+            if lookup.voxel.a > 0.5 {
+                return OctreeLookup(lookup.voxel, 0.0, lookup.material);
+            }
+            skip = min(skip, lookup.skip * instance.scale);
+        }
+    }
+
+    return OctreeLookup(vec4<f32>(0.0), skip, 0u);
+}
@@ -0,0 +1,36 @@
+// Dense 3D texture backend with a signed distance field, for ray_march.wgsl
+@group(3) @binding(0) var octree_texture: texture_3d<f32>;
+@group(3) @binding(1) var octree_materials: texture_3d<u32>;  // material index per texel
+@group(3) @binding(2) var octree_distance: texture_3d<i32>;   // see DistanceField, negative inside
+
+const DISTANCE_UNITS_PER_TEXEL: f32 = 4.0;
+
+// Texel containing a world position, as in octree_texture.wgsl
+fn octree_texel(position: vec3<f32>) -> vec3<u32> {
+    let size = textureDimensions(octree_texture);
+    let texture_coords = (position - scene_data.bounds_min) / (scene_data.bounds_max - scene_data.bounds_min);
+
+    // Clamp to valid texture range to avoid edge artifacts
+    return min(vec3<u32>(clamp(texture_coords, vec3<f32>(0.0), vec3<f32>(1.0)) * vec3<f32>(size)), size - 1u);
+}
+
+// Radius of the empty ball around a position in an empty texel. The field
+// holds center-to-center distances; taking off the offset from this texel's
+// center and half the diagonal of the occupied texel leaves a distance to the
+// nearest point of any occupied texel, so stepping by it cannot cross one
+fn sphere_trace_skip(position: vec3<f32>, texel: vec3<u32>) -> f32 {
+    let texel_size = (scene_data.bounds_max - scene_data.bounds_min) / vec3<f32>(textureDimensions(octree_texture));
+    let texel_center = scene_data.bounds_min + (vec3<f32>(texel) + 0.5) * texel_size;
+    let distance = f32(textureLoad(octree_distance, texel, 0).r) / DISTANCE_UNITS_PER_TEXEL;
+    let clearance = distance - length((position - texel_center) / texel_size) - sqrt(3.0) * 0.5;
+    return max(clearance, 0.0) * min(min(texel_size.x, texel_size.y), texel_size.z);
+}
+
+fn octree_lookup(position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    let texel = octree_texel(position);
+    let voxel = textureLoad(octree_texture, texel, 0);
+    if voxel.a > 0.0 {
+        return OctreeLookup(voxel, 0.0, textureLoad(octree_materials, texel, 0).r);
+    }
+    return OctreeLookup(voxel, sphere_trace_skip(position, texel), 0u);
+}
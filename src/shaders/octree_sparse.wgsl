@@ -0,0 +1,55 @@
+// Linearized sparse voxel octree backend for ray_march.wgsl
+struct OctreeHeader {
+    center: vec3<f32>,
+    half_size: f32,
+    max_depth: u32,
+    node_count: u32,
+}
+
+struct OctreeNode {
+    first_child: u32,  // 0 means leaf, children occupy 8 consecutive slots otherwise
+    payload: u32,      // RGBA8 color + density
+    material: u32,     // index into materials
+}
+
+@group(3) @binding(0) var<storage, read> octree_nodes: array<OctreeNode>;
+@group(3) @binding(1) var<uniform> octree_header: OctreeHeader;
+
+fn octree_lookup(position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    var node_center = octree_header.center;
+    var half_size = octree_header.half_size;
+
+    if any(abs(position - node_center) > vec3<f32>(half_size)) {
+        return OctreeLookup(vec4<f32>(0.0), 0.0, 0u);
+    }
+
+    // Descend until we reach the leaf containing the position
+    var index = 0u;
+    for (var level = 0u; level < octree_header.max_depth; level++) {
+        let node = octree_nodes[index];
+        if node.first_child == 0u {
+            break;
+        }
+
+        half_size = half_size * 0.5;
+        var octant = 0u;
+        if position.x > node_center.x { octant |= 1u; }
+        if position.y > node_center.y { octant |= 2u; }
+        if position.z > node_center.z { octant |= 4u; }
+
+        node_center = node_center + select(vec3<f32>(-half_size), vec3<f32>(half_size), position > node_center);
+        index = node.first_child + octant;
+    }
+
+    let leaf = octree_nodes[index];
+    let voxel = unpack4x8unorm(leaf.payload);
+    if voxel.a > 0.0 {
+        return OctreeLookup(voxel, 0.0, leaf.material);
+    }
+
+    // Empty leaf: the ray can jump straight to where it leaves this cell
+    let exit_planes = node_center + sign(ray_dir) * half_size;
+    let t_exit = select(vec3<f32>(1e30), (exit_planes - position) / ray_dir, ray_dir != vec3<f32>(0.0));
+    let skip = min(min(t_exit.x, t_exit.y), t_exit.z);
+    return OctreeLookup(voxel, max(skip, 0.0) + 1e-4, 0u);
+}
@@ -0,0 +1,52 @@
+// Dense 3D texture backend for ray_march.wgsl
+@group(3) @binding(0) var octree_texture: texture_3d<f32>;
+@group(3) @binding(1) var octree_materials: texture_3d<u32>;  // material index per texel
+@group(3) @binding(2) var octree_occupancy: texture_3d<f32>;  // max density, mip level k covers 2^k texels
+
+// Enough for a 2^15 texel wide volume
+const MAX_OCCUPANCY_LEVELS: u32 = 16u;
+
+// Texel containing a world position. The texture spans the scene bounds and
+// each texel holds the voxel sampled at its center, so no filtering is applied:
+// interpolating across a wall would let rays see through it
+fn octree_texel(position: vec3<f32>) -> vec3<u32> {
+    let size = textureDimensions(octree_texture);
+    let texture_coords = (position - scene_data.bounds_min) / (scene_data.bounds_max - scene_data.bounds_min);
+
+    // Clamp to valid texture range to avoid edge artifacts
+    return min(vec3<u32>(clamp(texture_coords, vec3<f32>(0.0), vec3<f32>(1.0)) * vec3<f32>(size)), size - 1u);
+}
+
+// Distance along the ray to where it leaves the largest empty box of the
+// occupancy pyramid around an empty texel
+fn empty_space_skip(position: vec3<f32>, ray_dir: vec3<f32>, texel: vec3<u32>) -> f32 {
+    // Climb while the parent box is still empty. The pyramid runs down to a
+    // single texel. The loop bound is a constant and the level count comes from
+    // the size, as some GL drivers fetch the wrong texels when the mip level
+    // varies in a loop bounded at run time
+    var level = 0u;
+    let levels = firstLeadingBit(textureDimensions(octree_texture).x) + 1u;
+    for (var parent = 1u; parent < MAX_OCCUPANCY_LEVELS; parent++) {
+        if parent >= levels || textureLoad(octree_occupancy, texel >> vec3<u32>(parent), i32(parent)).r > 0.0 {
+            break;
+        }
+        level = parent;
+    }
+
+    let texel_size = (scene_data.bounds_max - scene_data.bounds_min) / vec3<f32>(textureDimensions(octree_texture));
+    let box_min = scene_data.bounds_min + vec3<f32>((texel >> vec3<u32>(level)) << vec3<u32>(level)) * texel_size;
+    let box_size = texel_size * f32(1u << level);
+    let exit_planes = box_min + select(vec3<f32>(0.0), box_size, ray_dir > vec3<f32>(0.0));
+    let t_exit = select((exit_planes - position) / ray_dir, vec3<f32>(1e30), ray_dir == vec3<f32>(0.0));
+    let skip = min(min(t_exit.x, t_exit.y), t_exit.z);
+    return max(skip, 0.0) + 1e-4;
+}
+
+fn octree_lookup(position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    let texel = octree_texel(position);
+    let voxel = textureLoad(octree_texture, texel, 0);
+    if voxel.a > 0.0 {
+        return OctreeLookup(voxel, 0.0, textureLoad(octree_materials, texel, 0).r);
+    }
+    return OctreeLookup(voxel, empty_space_skip(position, ray_dir, texel), 0u);
+}
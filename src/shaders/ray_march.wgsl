@@ -5,17 +5,142 @@ struct CameraData {
     screen_size: vec2<f32>,
 }
 
+// Extent of the provider's data (renderer::SceneData)
+struct SceneData {
+    bounds_min: vec3<f32>,
+    bounds_max: vec3<f32>,
+    grid_dims: vec3<u32>,  // voxels along each axis of the bounds
+}
+
 struct PerformanceData {
     base_voxel_size: f32,
     frame_time: f32,
+    traversal_mode: u32,  // TRAVERSAL_ADAPTIVE or TRAVERSAL_DDA
+    debug_flags: u32,     // DEBUG_* bits
+    max_bounces: u32,     // 0 shades the first hit without lighting
+    samples_per_pixel: u32,
+    frame_index: u32,     // frames already in accumulation, 0 starts over
+}
+
+// renderer::performance::TraversalMode
+const TRAVERSAL_ADAPTIVE: u32 = 0u;
+const TRAVERSAL_DDA: u32 = 1u;
+
+// renderer::DEBUG_*
+const DEBUG_ITERATION_VIEW: u32 = 1u;
+const DEBUG_NO_SKIPPING: u32 = 2u;
+const DEBUG_COUNT_ITERATIONS: u32 = 4u;
+
+// Paths surviving this many bounces continue with a probability tied to their throughput
+const RUSSIAN_ROULETTE_START: u32 = 3u;
+
+// Reflections inside a transmissive medium followed before the path is dropped
+const MAX_INTERNAL_REFLECTIONS: u32 = 8u;
+
+// renderer::RayStats
+struct RayStats {
+    iterations: atomic<u32>,
+    rays: atomic<u32>,
+}
+
+// Result of querying the octree backend (octree_texture.wgsl / octree_sparse.wgsl)
+struct OctreeLookup {
+    voxel: vec4<f32>,  // color in rgb, density in alpha
+    skip: f32,         // distance along the ray known to be empty, 0 if unknown
+    material: u32,     // index into materials, 0 for empty space
+}
+
+// Entry of the provider's material table (octree::material::Material)
+struct Material {
+    albedo: vec3<f32>,        // tint applied to the voxel color
+    roughness: f32,
+    emission: vec3<f32>,      // emitted color, scaled by emission_strength
+    emission_strength: f32,
+    metalness: f32,
+    ior: f32,
+    transmission: f32,
+    _padding: f32,
+}
+
+// Outcome of tracing one ray through the scene bounds
+const RAY_MISSED: u32 = 0u;     // left the bounds without hitting anything
+const RAY_HIT: u32 = 1u;
+const RAY_EXHAUSTED: u32 = 2u;  // ran out of steps inside the bounds
+
+struct RayHit {
+    outcome: u32,
+    position: vec3<f32>,  // where the ray entered the hit voxel
+    normal: vec3<f32>,    // of the face it entered through
+    voxel: vec4<f32>,
+    material: u32,
+    iterations: u32,      // backend lookups made
+}
+
+struct Ray {
+    origin: vec3<f32>,
+    direction: vec3<f32>,
 }
 
+// Ray that hit nothing
+fn ray_ended(outcome: u32, iterations: u32) -> RayHit {
+    return RayHit(outcome, vec3<f32>(0.0), vec3<f32>(0.0), vec4<f32>(0.0), 0u, iterations);
+}
+
+// Linear HDR radiance leaving an emissive surface
+fn material_radiance(material: Material) -> vec3<f32> {
+    return material.emission * material.emission_strength;
+}
+
+// Paths carry their own random sequence, seeded per pixel
+var<private> rng_state: u32;
+
+fn pcg_hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Uniform in [0, 1)
+fn random_float() -> f32 {
+    rng_state = pcg_hash(rng_state);
+    return f32(rng_state >> 8u) / 16777216.0;
+}
+
+// Schlick's approximation of the Fresnel reflectance at a boundary between
+// media whose refractive indices have the ratio `ior`, either way round
+fn fresnel_schlick(cos_theta: f32, ior: f32) -> f32 {
+    let r = (1.0 - ior) / (1.0 + ior);
+    let r0 = r * r;
+    return r0 + (1.0 - r0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Direction about the normal with density proportional to the cosine, so a
+// Lambertian bounce weighs the sample by the albedo alone
+fn cosine_sample_hemisphere(normal: vec3<f32>) -> vec3<f32> {
+    let phi = 6.2831853 * random_float();
+    let r2 = random_float();
+    let r = sqrt(r2);
+
+    // Orthonormal basis around the normal (Duff et al. 2017)
+    let s = select(-1.0, 1.0, normal.z >= 0.0);
+    let a = -1.0 / (s + normal.z);
+    let b = normal.x * normal.y * a;
+    let tangent = vec3<f32>(1.0 + s * normal.x * normal.x * a, s * b, -s * normal.x);
+    let bitangent = vec3<f32>(b, s + normal.y * normal.y * a, -normal.y);
+    return normalize(tangent * (r * cos(phi)) + bitangent * (r * sin(phi)) + normal * sqrt(1.0 - r2));
+}
+
+// Every provider binds its material table here, next to its own resources
+@group(3) @binding(8) var<storage, read> materials: array<Material>;
+
 // Use rgba8unorm for compatibility - runtime will use appropriate format
 @group(0) @binding(0) var output_texture: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(1) var<storage, read_write> accumulation: array<vec4<f32>>;  // radiance sum, sample count in w
 @group(1) @binding(0) var<uniform> camera_data: CameraData;
+@group(1) @binding(1) var<uniform> scene_data: SceneData;
 @group(2) @binding(0) var<uniform> performance_data: PerformanceData;
-@group(3) @binding(0) var octree_texture: texture_3d<f32>;
-@group(3) @binding(1) var octree_sampler: sampler;
+@group(2) @binding(1) var<storage, read_write> ray_stats: RayStats;
+// Group 3 is declared by the octree backend prepended to this file
 
 fn get_ray_direction(screen_uv: vec2<f32>, camera: CameraData) -> vec3<f32> {
     // Convert to NDC, but flip Y to correct for inverted image
@@ -68,18 +193,6 @@ fn ray_box_intersection(ray_origin: vec3<f32>, ray_dir: vec3<f32>, box_min: vec3
     return vec2<f32>(max(t_near, 0.0), t_far);
 }
 
-fn sample_voxel_from_octree(position: vec3<f32>) -> vec4<f32> {
-    // Convert world position to texture coordinates
-    // Octree covers -2 to 2 in all dimensions, texture is 0 to 1
-    let texture_coords = (position + vec3<f32>(2.0, 2.0, 2.0)) / 4.0;
-
-    // Clamp to valid texture range to avoid edge artifacts
-    let clamped_coords = clamp(texture_coords, vec3<f32>(0.0), vec3<f32>(1.0));
-
-    // Sample the 3D texture
-    return textureSampleLevel(octree_texture, octree_sampler, clamped_coords, 0.0);
-}
-
 fn volume_scatter(accumulated_color: vec4<f32>, voxel_data: vec4<f32>, step_size: f32) -> vec4<f32> {
     if voxel_data.a < 0.01 {
         return accumulated_color;
@@ -95,65 +208,341 @@ fn volume_scatter(accumulated_color: vec4<f32>, voxel_data: vec4<f32>, step_size
     return vec4<f32>(new_color, new_alpha);
 }
 
-@compute @workgroup_size(8, 8, 1)
-fn ray_march_compute(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let pixel_coord = vec2<i32>(global_id.xy);
-    let screen_size = vec2<f32>(camera_data.screen_size);
+// Empty space the backend reported, unless skipping is switched off for comparison
+fn lookup_skip(lookup: OctreeLookup) -> f32 {
+    return select(lookup.skip, 0.0, (performance_data.debug_flags & DEBUG_NO_SKIPPING) != 0u);
+}
 
-    if f32(pixel_coord.x) >= screen_size.x || f32(pixel_coord.y) >= screen_size.y {
-        return;
+// Heat map from blue (one lookup) through green and yellow to red (the whole
+// budget), on a log scale so cheap rays stay distinguishable
+fn iteration_heat(iterations: u32, max_steps: u32) -> vec3<f32> {
+    let heat = clamp(log2(f32(iterations) + 1.0) / log2(f32(max_steps) + 1.0), 0.0, 1.0);
+    return clamp(vec3<f32>(
+        heat * 3.0 - 1.5,
+        1.5 - abs(heat * 3.0 - 1.5),
+        1.5 - heat * 3.0
+    ), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Voxel size of the scene grid along each axis
+fn grid_cell_size() -> vec3<f32> {
+    return (scene_data.bounds_max - scene_data.bounds_min) / vec3<f32>(scene_data.grid_dims);
+}
+
+// Normal of the face a ray travelling along ray_dir entered a cell through:
+// the entry plane closest behind the position
+fn entered_face_normal(position: vec3<f32>, ray_dir: vec3<f32>, cell_min: vec3<f32>, cell_size: vec3<f32>) -> vec3<f32> {
+    let entry_planes = select(cell_min + cell_size, cell_min, ray_dir > vec3<f32>(0.0));
+    let t_back = select((position - entry_planes) / ray_dir, vec3<f32>(1e30), ray_dir == vec3<f32>(0.0));
+    if t_back.x <= t_back.y && t_back.x <= t_back.z {
+        return vec3<f32>(-sign(ray_dir.x), 0.0, 0.0);
+    }
+    if t_back.y <= t_back.z {
+        return vec3<f32>(0.0, -sign(ray_dir.y), 0.0);
     }
+    return vec3<f32>(0.0, 0.0, -sign(ray_dir.z));
+}
 
-    let screen_uv = (vec2<f32>(global_id.xy) + 0.5) / screen_size;
+// Fixed-step marching whose step size the performance controller scales.
+// Cheap, but a step longer than a voxel can pass through thin walls
+fn trace_adaptive(ray_origin: vec3<f32>, ray_direction: vec3<f32>, t_range: vec2<f32>, max_steps: u32) -> RayHit {
+    var t = t_range.x;
+    for (var i = 0u; i < max_steps; i++) {
+        if t >= t_range.y {
+            return ray_ended(RAY_MISSED, i);
+        }
 
-    let ray_origin = camera_data.position;
-    let ray_direction = get_ray_direction(screen_uv, camera_data);
+        let current_pos = ray_origin + ray_direction * t;
+        let lookup = octree_lookup(current_pos, ray_direction);
+        let skip = lookup_skip(lookup);
+        if lookup.voxel.a > 0.5 {
+            // The step may have landed anywhere inside the voxel; take the
+            // normal of the grid cell it is in
+            let cell_size = grid_cell_size();
+            let cell_min = scene_data.bounds_min + floor((current_pos - scene_data.bounds_min) / cell_size) * cell_size;
+            let normal = entered_face_normal(current_pos, ray_direction, cell_min, cell_size);
+
+            // Back up to where the ray entered that cell, as trace_dda reports
+            let face_point = cell_min + select(vec3<f32>(0.0), cell_size, normal > vec3<f32>(0.0));
+            let facing = dot(ray_direction, normal);
+            let t_back = select(dot(current_pos - face_point, normal) / facing, 0.0, facing == 0.0);
+            let position = current_pos - ray_direction * max(t_back, 0.0);
+            return RayHit(RAY_HIT, position, normal, lookup.voxel, lookup.material, i + 1u);
+        }
 
-    // Test ray-box intersection with scene bounds (Cornell Box)
-    // Cornell Box actual bounds: X: -1 to 1, Y: 0 to 2, Z: 0 to 2
-    // Extend slightly to ensure we capture walls
-    let scene_min = vec3<f32>(-1.1, -0.1, -0.1);
-    let scene_max = vec3<f32>(1.1, 2.1, 2.1);
-    let intersection = ray_box_intersection(ray_origin, ray_direction, scene_min, scene_max);
+        // Backends report empty space around the position, letting us leap over it
+        let step_size = get_adaptive_step_size(t, performance_data.base_voxel_size);
+        t += max(step_size, skip);
+    }
 
-    var accumulated_color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    if t >= t_range.y {
+        return ray_ended(RAY_MISSED, max_steps);
+    }
+    return ray_ended(RAY_EXHAUSTED, max_steps);
+}
 
-    if intersection.x >= 0.0 {
-        var current_pos = ray_origin + ray_direction * intersection.x;
-        let max_distance = intersection.y - intersection.x;
-        var distance_traveled = 0.0;
-        var hit_something = false;
+// Amanatides–Woo traversal of the scene grid: visits every voxel the ray
+// crosses, in order, sampling each once in the middle of the ray's span inside
+// it, so no wall is ever skipped. Empty space reported by the backend is
+// jumped over and the walk resumes from the voxel the ray lands in. With
+// `inside` the ray starts in solid voxels and stops at the first empty one
+fn trace_dda(ray_origin: vec3<f32>, ray_direction: vec3<f32>, t_range: vec2<f32>, max_steps: u32, inside: bool) -> RayHit {
+    let dims = vec3<i32>(scene_data.grid_dims);
+    let cell_size = grid_cell_size();
+    let step = vec3<i32>(sign(ray_direction));
+    let t_delta = select(abs(cell_size / ray_direction), vec3<f32>(1e30), ray_direction == vec3<f32>(0.0));
+
+    var t = t_range.x;
+    var cell = vec3<i32>(0);
+    var t_next = vec3<f32>(0.0);  // where the ray crosses into the next voxel along each axis
+    var normal = vec3<f32>(0.0);
+    var locate = true;
+
+    for (var i = 0u; i < max_steps; i++) {
+        if locate {
+            let position = ray_origin + ray_direction * t;
+            cell = clamp(vec3<i32>(floor((position - scene_data.bounds_min) / cell_size)), vec3<i32>(0), dims - 1);
+            let cell_min = scene_data.bounds_min + vec3<f32>(cell) * cell_size;
+            let exit_planes = select(cell_min, cell_min + cell_size, ray_direction > vec3<f32>(0.0));
+            t_next = select((exit_planes - ray_origin) / ray_direction, vec3<f32>(1e30), ray_direction == vec3<f32>(0.0));
+            normal = entered_face_normal(position, ray_direction, cell_min, cell_size);
+            locate = false;
+        }
 
-        for (var i = 0; i < 500 && distance_traveled < max_distance; i++) {
-            let distance_from_camera = length(current_pos - ray_origin);
-            let step_size = get_adaptive_step_size(distance_from_camera, performance_data.base_voxel_size);
+        let t_exit = max(min(min(t_next.x, t_next.y), t_next.z), t);
+        let t_sample = (t + min(t_exit, t_range.y)) * 0.5;
+        let lookup = octree_lookup(ray_origin + ray_direction * t_sample, ray_direction);
+        let skip = select(lookup_skip(lookup), 0.0, inside);
+        if (lookup.voxel.a > 0.5) != inside {
+            return RayHit(RAY_HIT, ray_origin + ray_direction * t, normal, lookup.voxel, lookup.material, i + 1u);
+        }
+        if t_exit >= t_range.y {
+            return ray_ended(RAY_MISSED, i + 1u);
+        }
 
-            let voxel_data = sample_voxel_from_octree(current_pos);
+        // The backend vouches for space past this voxel: land where it ends
+        if t_sample + skip > t_exit {
+            t = t_sample + skip;
+            if t >= t_range.y {
+                return ray_ended(RAY_MISSED, i + 1u);
+            }
+            locate = true;
+            continue;
+        }
 
-            // If we hit a solid voxel, use its color directly
-            if voxel_data.a > 0.5 {
-                accumulated_color = voxel_data;
-                hit_something = true;
+        // Step into the neighbour across the nearest boundary
+        var axis = 2;
+        if t_next.x <= t_next.y && t_next.x <= t_next.z {
+            axis = 0;
+        } else if t_next.y <= t_next.z {
+            axis = 1;
+        }
+        t = t_next[axis];
+        t_next[axis] += t_delta[axis];
+        cell[axis] += step[axis];
+        normal = vec3<f32>(0.0);
+        normal[axis] = -f32(step[axis]);
+        if cell[axis] < 0 || cell[axis] >= dims[axis] {
+            return ray_ended(RAY_MISSED, i + 1u);
+        }
+    }
+
+    return ray_ended(RAY_EXHAUSTED, max_steps);
+}
+
+// Trace a ray through the provider's bounds with the selected traversal. Rays
+// missing the bounds never reach the backend
+fn trace_ray(ray_origin: vec3<f32>, ray_direction: vec3<f32>, max_steps: u32) -> RayHit {
+    // Only march the part of the ray inside the provider's bounds
+    let intersection = ray_box_intersection(ray_origin, ray_direction, scene_data.bounds_min, scene_data.bounds_max);
+    if intersection.x < 0.0 {
+        return ray_ended(RAY_MISSED, 0u);
+    }
+    if performance_data.traversal_mode == TRAVERSAL_DDA {
+        return trace_dda(ray_origin, ray_direction, intersection, max_steps, false);
+    }
+    return trace_adaptive(ray_origin, ray_direction, intersection, max_steps);
+}
+
+// Trace a ray starting inside solid voxels to the first empty voxel along it.
+// Always walks the grid, so a thin gap is never stepped over. The hit normal
+// faces back into the solid
+fn trace_exit(ray_origin: vec3<f32>, ray_direction: vec3<f32>, max_steps: u32) -> RayHit {
+    let intersection = ray_box_intersection(ray_origin, ray_direction, scene_data.bounds_min, scene_data.bounds_max);
+    if intersection.x < 0.0 {
+        return ray_ended(RAY_MISSED, 0u);
+    }
+    return trace_dda(ray_origin, ray_direction, intersection, max_steps, true);
+}
+
+// Carry a ray refracted into transmissive voxels through them: walk to where
+// it reaches empty space, then refract out or reflect back inside by the
+// Fresnel term. Returns the ray leaving the medium, with a zero direction if
+// it never gets out
+fn cross_medium(ray_origin: vec3<f32>, ray_direction: vec3<f32>, ior: f32, offset: f32, max_steps: u32) -> Ray {
+    var origin = ray_origin;
+    var direction = ray_direction;
+    for (var i = 0u; i <= MAX_INTERNAL_REFLECTIONS; i++) {
+        let exit = trace_exit(origin, direction, max_steps);
+        if exit.outcome != RAY_HIT {
+            break;
+        }
+
+        // Total internal reflection leaves refract() with a zero vector
+        let refracted = refract(direction, exit.normal, ior);
+        if any(refracted != vec3<f32>(0.0)) && random_float() >= fresnel_schlick(-dot(refracted, exit.normal), ior) {
+            return Ray(exit.position - exit.normal * offset, refracted);
+        }
+        direction = reflect(direction, exit.normal);
+        origin = exit.position + exit.normal * offset;
+    }
+    return Ray(origin, vec3<f32>(0.0));
+}
+
+struct PathSample {
+    radiance: vec3<f32>,
+    iterations: u32,  // lookups of the camera ray
+}
+
+// Monte Carlo estimate of the radiance arriving along a camera ray. Paths
+// pass through a smooth dielectric in proportion to the transmission, and
+// otherwise bounce diffusely off surfaces or off a roughened mirror in
+// proportion to the metalness. They end when they reach an emissive voxel,
+// leave the bounds or lose the Russian roulette. Only emissive voxels light
+// the scene
+fn trace_path(ray_origin: vec3<f32>, ray_direction: vec3<f32>, max_steps: u32) -> PathSample {
+    let cell_size = grid_cell_size();
+    let offset = min(min(cell_size.x, cell_size.y), cell_size.z) * 0.01;
+
+    var origin = ray_origin;
+    var direction = ray_direction;
+    var throughput = vec3<f32>(1.0);
+    var radiance = vec3<f32>(0.0);
+    var iterations = 0u;
+
+    for (var bounce = 0u; bounce <= performance_data.max_bounces; bounce++) {
+        let hit = trace_ray(origin, direction, max_steps);
+        if bounce == 0u {
+            iterations = hit.iterations;
+        }
+        if hit.outcome != RAY_HIT {
+            break;
+        }
+
+        let material = materials[hit.material];
+        let emitted = material_radiance(material);
+        if any(emitted > vec3<f32>(0.0)) {
+            radiance += throughput * emitted;
+            break;
+        }
+
+        // Every lobe is sampled in proportion to what it scatters, leaving the base color as the weight
+        throughput *= hit.voxel.rgb * material.albedo;
+        if bounce >= RUSSIAN_ROULETTE_START {
+            let survival = clamp(max(max(throughput.x, throughput.y), throughput.z), 0.05, 0.95);
+            if random_float() >= survival {
                 break;
             }
-
-            current_pos = current_pos + ray_direction * step_size;
-            distance_traveled = distance_traveled + step_size;
+            throughput /= survival;
         }
 
-        // If ray didn't hit anything inside the box, show black (empty space)
-        if !hit_something {
-            accumulated_color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        if random_float() < material.transmission {
+            // Refract in unless the Fresnel term mirrors the ray off the
+            // surface, and carry on from where it comes out of the voxels
+            if random_float() >= fresnel_schlick(-dot(direction, hit.normal), material.ior) {
+                let refracted = refract(direction, hit.normal, 1.0 / material.ior);
+                let exit = cross_medium(hit.position - hit.normal * offset, refracted, material.ior, offset, max_steps);
+                if all(exit.direction == vec3<f32>(0.0)) {
+                    break;
+                }
+                origin = exit.origin;
+                direction = exit.direction;
+                continue;
+            }
+            direction = reflect(direction, hit.normal);
+        } else if random_float() < material.metalness {
+            let jitter = cosine_sample_hemisphere(hit.normal) * material.roughness;
+            direction = normalize(reflect(direction, hit.normal) + jitter);
+            if dot(direction, hit.normal) <= 0.0 {
+                break;
+            }
+        } else {
+            direction = cosine_sample_hemisphere(hit.normal);
         }
-    } else {
-        // Ray missed the scene bounds entirely - show background
-        let background = mix(
+        origin = hit.position + hit.normal * offset;
+    }
+
+    return PathSample(radiance, iterations);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn ray_march_compute(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let pixel_coord = vec2<i32>(global_id.xy);
+    let screen_size = vec2<f32>(camera_data.screen_size);
+
+    if f32(pixel_coord.x) >= screen_size.x || f32(pixel_coord.y) >= screen_size.y {
+        return;
+    }
+
+    let screen_uv = (vec2<f32>(global_id.xy) + 0.5) / screen_size;
+
+    let ray_origin = camera_data.position;
+    let ray_direction = get_ray_direction(screen_uv, camera_data);
+
+    // A ray visits at most x + y + z voxels of the grid; allow two lookups per voxel
+    let dims = scene_data.grid_dims;
+    let max_steps = max(500u, 2u * (dims.x + dims.y + dims.z));
+
+    var accumulated_color: vec4<f32>;
+    var iterations = 0u;
+    if performance_data.max_bounces == 0u {
+        // Shown where the ray leaves the scene bounds without hitting anything
+        accumulated_color = vec4<f32>(mix(
             vec3<f32>(0.5, 0.7, 0.9),
             vec3<f32>(0.1, 0.2, 0.4),
             screen_uv.y
-        );
-        accumulated_color = vec4<f32>(background, 1.0);
+        ), 1.0);
+
+        let hit = trace_ray(ray_origin, ray_direction, max_steps);
+        if hit.outcome == RAY_HIT {
+            // Use the voxel's tinted color plus whatever it emits
+            let material = materials[hit.material];
+            accumulated_color = vec4<f32>(hit.voxel.rgb * material.albedo + material_radiance(material), 1.0);
+        } else if hit.outcome == RAY_EXHAUSTED {
+            // Out of steps before leaving the bounds: show black (unresolved)
+            accumulated_color = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        }
+        iterations = hit.iterations;
+    } else {
+        // Jittered paths through the pixel, with a fresh sequence every frame
+        let pixel_index = global_id.y * u32(screen_size.x) + global_id.x;
+        rng_state = pcg_hash(pixel_index ^ pcg_hash(performance_data.frame_index));
+        let samples = max(performance_data.samples_per_pixel, 1u);
+        var radiance = vec3<f32>(0.0);
+        for (var i = 0u; i < samples; i++) {
+            let sample_uv = (vec2<f32>(global_id.xy) + vec2<f32>(random_float(), random_float())) / screen_size;
+            let path = trace_path(ray_origin, get_ray_direction(sample_uv, camera_data), max_steps);
+            radiance += path.radiance;
+            iterations += path.iterations;
+        }
+        iterations /= samples;
+
+        // Show the mean of every sample since accumulation last started
+        var sum = vec4<f32>(radiance, f32(samples));
+        if performance_data.frame_index > 0u {
+            sum += accumulation[pixel_index];
+        }
+        accumulation[pixel_index] = sum;
+        accumulated_color = vec4<f32>(sum.rgb / sum.w, 1.0);
+    }
+
+    if (performance_data.debug_flags & DEBUG_ITERATION_VIEW) != 0u {
+        accumulated_color = vec4<f32>(iteration_heat(iterations, max_steps), 1.0);
+    }
+    if (performance_data.debug_flags & DEBUG_COUNT_ITERATIONS) != 0u {
+        atomicAdd(&ray_stats.iterations, iterations);
+        atomicAdd(&ray_stats.rays, 1u);
     }
 
     textureStore(output_texture, pixel_coord, accumulated_color);
-}
\ No newline at end of file
+}
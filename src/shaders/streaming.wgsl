@@ -0,0 +1,73 @@
+// Out-of-core chunk streaming backend for ray_march.wgsl
+struct StreamingHeader {
+    origin: vec3<f32>,
+    voxel_size: f32,
+    grid_dims: vec3<u32>,  // in chunks
+    _padding: u32,
+}
+
+struct ChunkPage {
+    atlas_slot: u32,     // slot in chunk_atlas, or one of the markers below
+    coarse_offset: u32,  // first of the chunk's 4x4x4 fallback cells in chunk_coarse
+}
+
+const CHUNK_VOXELS: u32 = 32u;
+const COARSE_VOXELS: u32 = 4u;
+const MISSING_CHUNK: u32 = 0xffffffffu;  // nothing stored on disk
+const NOT_RESIDENT: u32 = 0xfffffffeu;   // on disk but not loaded yet
+
+@group(3) @binding(0) var<storage, read> chunk_pages: array<ChunkPage>;
+@group(3) @binding(1) var<storage, read> chunk_atlas: array<vec2<u32>>;  // 32x32x32 RGBA8 colors + material indices per slot
+@group(3) @binding(2) var<storage, read> chunk_coarse: array<u32>;  // 4x4x4 RGBA8 cells per chunk
+@group(3) @binding(3) var<uniform> streaming_header: StreamingHeader;
+
+// Distance along the ray to where it leaves the cube [cell_min, cell_min + size]
+fn streaming_cell_exit(position: vec3<f32>, ray_dir: vec3<f32>, cell_min: vec3<f32>, size: f32) -> f32 {
+    let exit_planes = cell_min + select(vec3<f32>(0.0), vec3<f32>(size), ray_dir > vec3<f32>(0.0));
+    let t_exit = select(vec3<f32>(1e30), (exit_planes - position) / ray_dir, ray_dir != vec3<f32>(0.0));
+    return max(min(min(t_exit.x, t_exit.y), t_exit.z), 0.0) + 1e-4;
+}
+
+fn octree_lookup(position: vec3<f32>, ray_dir: vec3<f32>) -> OctreeLookup {
+    let dims = streaming_header.grid_dims;
+    let voxel_size = streaming_header.voxel_size;
+    let local = (position - streaming_header.origin) / voxel_size;
+    if any(local < vec3<f32>(0.0)) || any(local >= vec3<f32>(dims * CHUNK_VOXELS)) {
+        return OctreeLookup(vec4<f32>(0.0), 0.0, 0u);
+    }
+
+    let voxel = vec3<u32>(local);
+    let chunk = voxel / CHUNK_VOXELS;
+    let page = chunk_pages[(chunk.z * dims.y + chunk.y) * dims.x + chunk.x];
+
+    if page.atlas_slot == MISSING_CHUNK {
+        let chunk_extent = f32(CHUNK_VOXELS) * voxel_size;
+        let chunk_min = streaming_header.origin + vec3<f32>(chunk) * chunk_extent;
+        return OctreeLookup(vec4<f32>(0.0), streaming_cell_exit(position, ray_dir, chunk_min, chunk_extent), 0u);
+    }
+
+    // Coarse cells are kept for every chunk: an empty one is skipped whole, and
+    // while the chunk is still loading an occupied one is drawn in its place
+    let in_chunk = voxel % CHUNK_VOXELS;
+    let cell_voxels = CHUNK_VOXELS / COARSE_VOXELS;
+    let cell = in_chunk / cell_voxels;
+    let coarse = unpack4x8unorm(chunk_coarse[page.coarse_offset + (cell.z * COARSE_VOXELS + cell.y) * COARSE_VOXELS + cell.x]);
+    if coarse.a == 0.0 {
+        let cell_extent = f32(cell_voxels) * voxel_size;
+        let cell_min = streaming_header.origin + vec3<f32>(chunk * CHUNK_VOXELS + cell * cell_voxels) * voxel_size;
+        return OctreeLookup(coarse, streaming_cell_exit(position, ray_dir, cell_min, cell_extent), 0u);
+    }
+    if page.atlas_slot == NOT_RESIDENT {
+        return OctreeLookup(coarse, 0.0, 0u);
+    }
+
+    let offset = (in_chunk.z * CHUNK_VOXELS + in_chunk.y) * CHUNK_VOXELS + in_chunk.x;
+    let entry = chunk_atlas[page.atlas_slot * CHUNK_VOXELS * CHUNK_VOXELS * CHUNK_VOXELS + offset];
+    let color = unpack4x8unorm(entry.x);
+    if color.a > 0.0 {
+        return OctreeLookup(color, 0.0, entry.y);
+    }
+
+    let voxel_min = streaming_header.origin + vec3<f32>(voxel) * voxel_size;
+    return OctreeLookup(color, streaming_cell_exit(position, ray_dir, voxel_min, voxel_size), 0u);
+}
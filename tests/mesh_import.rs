@@ -0,0 +1,107 @@
+use adaptive_voxel_pathtracer::octree::VoxelData;
+use adaptive_voxel_pathtracer::octree::material::MaterialTable;
+use adaptive_voxel_pathtracer::octree::mesh::{Mesh, Triangle};
+use nalgebra as na;
+use std::collections::HashSet;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// The fixtures are the cube 0.1..0.9, so at this size it spans cells 0..=4 on
+/// every axis with its faces inside the outer cells
+const VOXEL_SIZE: f32 = 0.2;
+
+fn cells(mesh: &Mesh, solid: bool) -> HashSet<na::Vector3<i32>> {
+    mesh.voxelize_cells(VOXEL_SIZE, solid).unwrap().into_iter().map(|(cell, _)| cell).collect()
+}
+
+fn mesh(triangles: Vec<[[f32; 3]; 3]>) -> Mesh {
+    Mesh {
+        triangles: triangles.into_iter()
+            .map(|vertices| Triangle { vertices: vertices.map(na::Vector3::from), material: 0 })
+            .collect(),
+        materials: vec![VoxelData::solid([0.73; 3])],
+        material_table: MaterialTable::default(),
+    }
+}
+
+#[test]
+fn ascii_and_binary_stl() {
+    let ascii = Mesh::load(format!("{}/cube.stl", FIXTURES)).unwrap();
+    let binary = Mesh::load(format!("{}/cube_binary.stl", FIXTURES)).unwrap();
+    assert_eq!(ascii.triangles.len(), 12);
+    assert_eq!(binary.triangles.len(), 12);
+    for (a, b) in ascii.triangles.iter().zip(&binary.triangles) {
+        assert_eq!(a.vertices, b.vertices);
+    }
+    assert_eq!(cells(&ascii, false), cells(&binary, false));
+}
+
+#[test]
+fn surface_and_solid_voxel_counts() {
+    let mesh = Mesh::load(format!("{}/cube.stl", FIXTURES)).unwrap();
+
+    // A 5³ block of cells with its 3³ core hollow, then filled
+    let surface = cells(&mesh, false);
+    assert_eq!(surface.len(), 5 * 5 * 5 - 3 * 3 * 3);
+    assert!(!surface.contains(&na::Vector3::new(2, 2, 2)));
+    let solid = cells(&mesh, true);
+    assert_eq!(solid.len(), 5 * 5 * 5);
+    assert!(solid.is_superset(&surface));
+
+    let octree = mesh.voxelize(VOXEL_SIZE, true).unwrap();
+    let volume: f32 = octree.leaves().map(|(_, half_size, _)| (half_size * 2.0 / VOXEL_SIZE).powi(3)).sum();
+    assert_eq!(volume.round(), 125.0);
+}
+
+#[test]
+fn obj_materials() {
+    let mesh = Mesh::load(format!("{}/cube.obj", FIXTURES)).unwrap();
+    assert_eq!(mesh.triangles.len(), 12);
+    assert_eq!(mesh.materials.len(), 3);
+
+    let lamp = mesh.materials[2];
+    assert_eq!(lamp.color, [1.0, 1.0, 1.0]);
+    assert_eq!(lamp.emission, [4.0, 4.0, 3.0]);
+    assert!(mesh.material_table.material_of(&lamp).is_emissive());
+    assert_eq!(mesh.materials[1].color, [0.8, 0.8, 0.8]);
+    assert!(!mesh.material_table.material_of(&mesh.materials[1]).is_emissive());
+    assert_eq!(mesh.triangles.iter().filter(|triangle| triangle.material == 2).count(), 2);
+
+    // Cells only the top face touches take its material; the fill takes the
+    // material of the wall bounding it along -X
+    let voxels = mesh.voxelize_cells(VOXEL_SIZE, true).unwrap();
+    for (cell, data) in &voxels {
+        if cell.y == 4 && (1..=3).contains(&cell.x) && (1..=3).contains(&cell.z) {
+            assert_eq!(*data, lamp);
+        } else if (1..=3).contains(&cell.y) && (1..=3).contains(&cell.x) && (1..=3).contains(&cell.z) {
+            assert_eq!(*data, mesh.materials[1]);
+        }
+    }
+
+    let octree = mesh.voxelize(VOXEL_SIZE, false).unwrap();
+    assert_eq!(octree.materials.materials(), mesh.material_table.materials());
+}
+
+#[test]
+fn degenerate_triangles() {
+    // Collinear corners along X, and all three at one point
+    let line = mesh(vec![[[0.1, 0.1, 0.1], [0.5, 0.1, 0.1], [0.9, 0.1, 0.1]]]);
+    let expected: HashSet<_> = (0..5).map(|x| na::Vector3::new(x, 0, 0)).collect();
+    assert_eq!(cells(&line, false), expected);
+    assert_eq!(cells(&line, true), expected);
+
+    let point = mesh(vec![[[0.3, 0.5, 0.7]; 3]]);
+    assert_eq!(cells(&point, false), HashSet::from([na::Vector3::new(1, 2, 3)]));
+}
+
+#[test]
+fn oversized_grids_are_rejected() {
+    let cube = Mesh::load(format!("{}/cube.stl", FIXTURES)).unwrap();
+    assert!(cube.voxelize_cells(1e-4, true).is_err());
+    assert!(cube.voxelize(0.0, false).is_err());
+
+    let huge = mesh(vec![[[0.0, 0.0, 0.0], [1e9, 0.0, 0.0], [0.0, 0.0, 1e9]]]);
+    assert!(huge.voxelize_cells(1.0, false).is_err());
+    let broken = mesh(vec![[[0.0, 0.0, 0.0], [f32::NAN, 0.0, 0.0], [0.0, 1.0, 0.0]]]);
+    assert!(broken.voxelize_cells(1.0, false).is_err());
+}
@@ -0,0 +1,56 @@
+use adaptive_voxel_pathtracer::octree::vox::VoxScene;
+use nalgebra as na;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+#[test]
+fn scene_graph_instances_and_materials() {
+    let scene = VoxScene::load(format!("{}/scene_graph.vox", FIXTURES)).unwrap();
+    assert_eq!(scene.models.len(), 2);
+    assert_eq!(scene.instances.len(), 3);
+
+    // Two copies of the red 2x2x2 cube plus the rotated three-voxel bar
+    let voxels = scene.voxels();
+    assert_eq!(voxels.len(), 19);
+    assert_eq!(voxels.iter().filter(|(_, data)| data.color == [1.0, 0.0, 0.0]).count(), 16);
+    assert_eq!(voxels.iter().filter(|(_, data)| data.color == [0.0, 1.0, 0.0]).count(), 2);
+
+    let emissive: Vec<_> = voxels.iter().filter(|(_, data)| scene.materials.material_of(data).is_emissive()).collect();
+    assert_eq!(emissive.len(), 1);
+    assert_eq!(scene.materials.material_of(&emissive[0].1).emission, emissive[0].1.color);
+    assert_eq!(emissive[0].1.emission, scene.materials.material_of(&emissive[0].1).radiance());
+
+    // The bar is rotated 90 degrees about MagicaVoxel's Z (our Y) axis, so it runs along Z
+    let bar: Vec<_> = voxels.iter().filter(|(_, data)| data.color != [1.0, 0.0, 0.0]).map(|(cell, _)| *cell).collect();
+    assert!(bar.iter().all(|cell| cell.x == bar[0].x && cell.y == bar[0].y));
+}
+
+#[test]
+fn octree_round_trip_preserves_voxels() {
+    let scene = VoxScene::load(format!("{}/scene_graph.vox", FIXTURES)).unwrap();
+    let voxel_size = 0.1;
+    let octree = scene.to_octree(voxel_size).unwrap();
+
+    for (cell, data) in scene.voxels() {
+        let center = cell.map(|v| (v as f32 + 0.5) * voxel_size);
+        assert_eq!(octree.sample(center, 0), data);
+    }
+
+    // Collapsed leaves stand for several voxels, so count by volume
+    let voxel_count: f32 = octree.leaves()
+        .map(|(_, half_size, _)| (half_size * 2.0 / voxel_size).powi(3))
+        .sum();
+    assert_eq!(voxel_count.round() as usize, 19);
+}
+
+#[test]
+fn default_palette_without_rgba_chunk() {
+    let scene = VoxScene::load(format!("{}/default_palette.vox", FIXTURES)).unwrap();
+    assert_eq!(scene.instances.len(), 1);
+
+    let voxels = scene.voxels();
+    assert_eq!(voxels.len(), 2);
+    assert_eq!(voxels[0].1.color, [1.0, 1.0, 1.0]);
+    assert_eq!(voxels[1].1.color, [0xee as f32 / 255.0, 0.0, 0.0]);
+    assert_eq!(voxels[1].0 - voxels[0].0, na::Vector3::new(1, 0, 0));
+}